@@ -0,0 +1,59 @@
+//! Scripted SIP2 client for trying the listener by hand.
+//!
+//! ```bash
+//! cargo run --example sip2_client -- localhost:6001 \
+//!     '9300CNkiosk|COsecret|CPMain|' \
+//!     '9900302.00'
+//! ```
+//!
+//! Each argument after the address is sent as one message and the response
+//! is printed before the next one goes out. Pass `--checksum` before the
+//! address to append `AY<sequence>AZ<checksum>` to every message.
+
+use std::env;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+fn checksum(message: &str) -> String {
+    let sum = message
+        .bytes()
+        .fold(0u16, |acc, byte| acc.wrapping_add(byte as u16));
+    format!("{:04X}", (!sum).wrapping_add(1))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1).peekable();
+    let error_detection = args.next_if(|arg| arg == "--checksum").is_some();
+    let Some(address) = args.next() else {
+        anyhow::bail!("usage: sip2_client [--checksum] HOST:PORT MESSAGE...");
+    };
+
+    let (reader, mut writer) = TcpStream::connect(&address).await?.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut buffer = Vec::new();
+
+    for (index, message) in args.enumerate() {
+        let mut message = message;
+        if error_detection {
+            message.push_str(&format!("AY{}AZ", index % 10));
+            let sum = checksum(&message);
+            message.push_str(&sum);
+        }
+        println!("> {}", message);
+        writer.write_all(message.as_bytes()).await?;
+        writer.write_all(b"\r").await?;
+
+        buffer.clear();
+        if reader.read_until(b'\r', &mut buffer).await? == 0 {
+            println!("connection closed");
+            return Ok(());
+        }
+        println!("< {}", String::from_utf8_lossy(&buffer).trim_end());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,22 @@
+-- 
+-- Creates the `api_key` table behind `/api-keys` and API key
+-- authentication on a SLiMS database that was not created from this
+-- project's `slims.sql`. Safe to run more than once.
+-- 
+
+CREATE TABLE IF NOT EXISTS `api_key` (
+  `api_key_id` int NOT NULL AUTO_INCREMENT,
+  `key_name` varchar(100) NOT NULL,
+  `key_prefix` varchar(16) NOT NULL,
+  `hashed_key` varchar(64) NOT NULL,
+  `permissions` text NOT NULL,
+  `allowed_ips` text DEFAULT NULL,
+  `created_by` int NOT NULL,
+  `created_at` datetime NOT NULL,
+  `expires_at` datetime DEFAULT NULL,
+  `last_used_at` datetime DEFAULT NULL,
+  `last_used_ip` varchar(45) DEFAULT NULL,
+  `revoked_at` datetime DEFAULT NULL,
+  PRIMARY KEY (`api_key_id`),
+  UNIQUE KEY `key_prefix` (`key_prefix`)
+) ENGINE=MyISAM DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
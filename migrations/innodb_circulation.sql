@@ -0,0 +1,20 @@
+-- 
+-- Converts the tables that circulation writes inside one transaction from
+-- MyISAM, which SLiMS creates them with, to InnoDB, together with the item
+-- and member rows that checkouts lock. MyISAM ignores ROLLBACK and row
+-- locks, so the API answers circulation writes with 503 until this has
+-- been applied and the API restarted.
+-- `biblio` and `biblio_log` are converted too, so a catalog change and
+-- its audit rows are also written together or not at all.
+-- Safe to run more than once.
+-- 
+
+ALTER TABLE `loan` ENGINE=InnoDB;
+ALTER TABLE `loan_history` ENGINE=InnoDB;
+ALTER TABLE `fines` ENGINE=InnoDB;
+ALTER TABLE `reserve` ENGINE=InnoDB;
+ALTER TABLE `system_log` ENGINE=InnoDB;
+ALTER TABLE `item` ENGINE=InnoDB;
+ALTER TABLE `member` ENGINE=InnoDB;
+ALTER TABLE `biblio` ENGINE=InnoDB;
+ALTER TABLE `biblio_log` ENGINE=InnoDB;
@@ -0,0 +1,18 @@
+-- 
+-- Creates the `password_reset` table behind `/auth/password/forgot` and
+-- `/auth/password/reset` on a SLiMS database that was not created from
+-- this project's `slims.sql`. Safe to run more than once.
+-- 
+
+CREATE TABLE IF NOT EXISTS `password_reset` (
+  `id` int NOT NULL AUTO_INCREMENT,
+  `account_type` enum('staff','member') NOT NULL,
+  `account_id` varchar(20) NOT NULL,
+  `selector` varchar(32) NOT NULL,
+  `hashed_validator` varchar(64) NOT NULL,
+  `expires_at` datetime NOT NULL,
+  `created_at` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `selector` (`selector`),
+  KEY `account` (`account_type`,`account_id`)
+) ENGINE=MyISAM DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
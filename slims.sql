@@ -1539,4 +1539,42 @@ CREATE TABLE `user_tokens` (
   `expires_at` datetime NOT NULL,
   `created_at` datetime NOT NULL,
   PRIMARY KEY (`id`)
-) ENGINE=MyISAM DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
\ No newline at end of file
+) ENGINE=MyISAM DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+-- 
+-- Table for API keys used by service integrations
+-- 
+
+CREATE TABLE IF NOT EXISTS `api_key` (
+  `api_key_id` int NOT NULL AUTO_INCREMENT,
+  `key_name` varchar(100) NOT NULL,
+  `key_prefix` varchar(16) NOT NULL,
+  `hashed_key` varchar(64) NOT NULL,
+  `permissions` text NOT NULL,
+  `allowed_ips` text DEFAULT NULL,
+  `created_by` int NOT NULL,
+  `created_at` datetime NOT NULL,
+  `expires_at` datetime DEFAULT NULL,
+  `last_used_at` datetime DEFAULT NULL,
+  `last_used_ip` varchar(45) DEFAULT NULL,
+  `revoked_at` datetime DEFAULT NULL,
+  PRIMARY KEY (`api_key_id`),
+  UNIQUE KEY `key_prefix` (`key_prefix`)
+) ENGINE=MyISAM DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+-- 
+-- Table for one-time password reset tokens of staff users and members
+-- 
+
+CREATE TABLE IF NOT EXISTS `password_reset` (
+  `id` int NOT NULL AUTO_INCREMENT,
+  `account_type` enum('staff','member') NOT NULL,
+  `account_id` varchar(20) NOT NULL,
+  `selector` varchar(32) NOT NULL,
+  `hashed_validator` varchar(64) NOT NULL,
+  `expires_at` datetime NOT NULL,
+  `created_at` datetime NOT NULL,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `selector` (`selector`),
+  KEY `account` (`account_type`,`account_id`)
+) ENGINE=MyISAM DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,130 @@
+use std::net::IpAddr;
+
+use serde_json::{Value as JsonValue, json};
+use sqlx::MySqlConnection;
+
+use crate::{auth::AuthUser, error::AppError};
+
+/// Mirrors the `log_type` enum of the `system_log` table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogType {
+    Staff,
+    Member,
+    System,
+}
+
+impl LogType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogType::Staff => "staff",
+            LogType::Member => "member",
+            LogType::System => "system",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SystemLogEntry<'a> {
+    pub log_type: LogType,
+    /// User ID for staff entries, member ID for member entries.
+    pub id: Option<String>,
+    pub location: &'a str,
+    pub sub_module: &'a str,
+    pub action: &'a str,
+    pub message: String,
+}
+
+impl<'a> SystemLogEntry<'a> {
+    /// An entry for a change made by the caller. Staff entries carry the
+    /// user ID and member entries the member ID; API keys have no account,
+    /// so they are logged as `system` entries with `api-key:{id}`.
+    pub fn by(
+        auth: &AuthUser,
+        location: &'a str,
+        sub_module: &'a str,
+        action: &'a str,
+        message: String,
+    ) -> Self {
+        let (log_type, id) = match (&auth.claims.member_id, auth.api_key_id) {
+            (Some(member_id), _) => (LogType::Member, member_id.clone()),
+            (None, Some(api_key_id)) => (LogType::System, format!("api-key:{}", api_key_id)),
+            (None, None) => (LogType::Staff, auth.claims.sub.to_string()),
+        };
+
+        SystemLogEntry {
+            log_type,
+            id: Some(id),
+            location,
+            sub_module,
+            action,
+            message,
+        }
+    }
+}
+
+pub async fn write_system_log(
+    conn: &mut MySqlConnection,
+    entry: &SystemLogEntry<'_>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "INSERT INTO system_log (log_type, id, log_location, sub_module, action, log_msg, log_date) VALUES (?, ?, ?, ?, ?, ?, NOW())",
+    )
+    .bind(entry.log_type.as_str())
+    .bind(&entry.id)
+    .bind(entry.location)
+    .bind(entry.sub_module)
+    .bind(entry.action)
+    .bind(&entry.message)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// A change to a bibliographic record, kept in `biblio_log` next to the
+/// `system_log` entry. `before` is `None` for creations and `after` for
+/// deletions.
+#[derive(Debug)]
+pub struct BiblioLogEntry<'a> {
+    pub biblio_id: i64,
+    pub title: &'a str,
+    pub action: &'a str,
+    pub before: Option<JsonValue>,
+    pub after: Option<JsonValue>,
+    pub message: String,
+}
+
+/// Writes `entry` to `biblio_log`. `rawdata` holds the record as JSON:
+/// `{"before": ..., "after": ...}`.
+pub async fn write_biblio_log(
+    conn: &mut MySqlConnection,
+    auth: &AuthUser,
+    ip: Option<IpAddr>,
+    entry: &BiblioLogEntry<'_>,
+) -> Result<(), AppError> {
+    let realname = if auth.claims.sub != 0 {
+        sqlx::query_scalar("SELECT realname FROM `user` WHERE user_id = ?")
+            .bind(auth.claims.sub)
+            .fetch_optional(&mut *conn)
+            .await?
+    } else {
+        None
+    };
+    let rawdata = json!({ "before": entry.before, "after": entry.after });
+
+    sqlx::query(
+        "INSERT INTO biblio_log (biblio_id, user_id, realname, title, ip, action, affectedrow, rawdata, additional_information, date) VALUES (?, ?, ?, ?, ?, ?, 'description', ?, ?, NOW())",
+    )
+    .bind(entry.biblio_id)
+    .bind(auth.claims.sub)
+    .bind(realname.unwrap_or_else(|| auth.claims.username.clone()))
+    .bind(entry.title)
+    .bind(ip.map(|ip| ip.to_string()).unwrap_or_default())
+    .bind(entry.action)
+    .bind(rawdata.to_string())
+    .bind(&entry.message)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
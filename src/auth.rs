@@ -1,23 +1,30 @@
 use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{
     Json, async_trait,
-    extract::{FromRequestParts, State},
+    extract::{ConnectInfo, FromRequestParts, State},
     http::{HeaderMap, header, request::Parts},
 };
 use bcrypt::verify;
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, QueryBuilder};
+use sqlx::{FromRow, MySqlConnection, QueryBuilder};
 use utoipa::ToSchema;
 
 use crate::{
     config::AppState,
     error::AppError,
     jsonapi::{JsonApiDocument, resource, single_document},
+    login_guard::{self, Subject},
+    permission_cache::{LiveAccess, Subject as CacheSubject},
+    refresh::{self, IssuedRefreshToken},
+    resources::api_keys,
+    tokens::constant_time_eq,
+    two_factor,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
@@ -27,6 +34,8 @@ pub enum Role {
     Librarian,
     Staff,
     Member,
+    /// An API key used by another system rather than a person.
+    Service,
 }
 
 impl TryFrom<String> for Role {
@@ -38,6 +47,7 @@ impl TryFrom<String> for Role {
             "librarian" => Ok(Role::Librarian),
             "staff" => Ok(Role::Staff),
             "member" => Ok(Role::Member),
+            "service" => Ok(Role::Service),
             other => Err(AppError::BadRequest(format!("invalid role: {other}"))),
         }
     }
@@ -50,6 +60,10 @@ pub struct Claims {
     pub role: Role,
     #[serde(default)]
     pub access: Vec<ModulePermission>,
+    /// Set on member tokens, whose `sub` is 0 because member IDs are not
+    /// numeric.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member_id: Option<String>,
     pub exp: usize,
 }
 
@@ -88,8 +102,14 @@ pub struct ModulePermission {
 
 pub struct AuthUser {
     pub claims: Claims,
+    /// Set when the request authenticated with `X-API-Key` instead of a JWT.
+    pub api_key_id: Option<i64>,
 }
 
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
 #[async_trait]
 impl FromRequestParts<AppState> for AuthUser {
     type Rejection = AppError;
@@ -98,17 +118,178 @@ impl FromRequestParts<AppState> for AuthUser {
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
+        if let Some(key) = parts.headers.get(API_KEY_HEADER) {
+            let key = key
+                .to_str()
+                .map_err(|_| AppError::Unauthorized("Invalid X-API-Key header".into()))?
+                .to_string();
+            let Ok(ClientIp(ip)) = ClientIp::from_request_parts(parts, state).await;
+            return api_keys::authenticate(state, &key, ip).await;
+        }
+
         let token = extract_bearer(&parts.headers)?;
-        let decoding_key = DecodingKey::from_secret(state.jwt_secret.as_bytes());
-        let token_data =
-            decode::<Claims>(&token, &decoding_key, &Validation::new(Algorithm::HS256))?;
+        let mut claims = state.jwt_keys.verify::<Claims>(&token)?;
+
+        // Role and access in the token are only a snapshot from login; the
+        // current ones replace them so that group changes apply right away.
+        let live = current_access(state, &claims).await?.ok_or_else(|| {
+            AppError::Unauthorized("account is disabled or no longer exists".into())
+        })?;
+        claims.role = live.role;
+        claims.access = live.access;
 
         Ok(AuthUser {
-            claims: token_data.claims,
+            claims,
+            api_key_id: None,
         })
     }
 }
 
+/// Parses a single address or a CIDR range, as used by API key allowlists
+/// and `TRUSTED_PROXIES`.
+pub fn parse_ip_range(entry: &str) -> Option<(IpAddr, u32)> {
+    let (addr, bits) = match entry.split_once('/') {
+        Some((addr, bits)) => (addr, Some(bits)),
+        None => (entry, None),
+    };
+    let addr = addr.trim().parse::<IpAddr>().ok()?.to_canonical();
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let bits = match bits {
+        Some(bits) => bits
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|bits| *bits <= max)?,
+        None => max,
+    };
+
+    Some((addr, bits))
+}
+
+pub fn ip_in_range(ip: IpAddr, (network, bits): (IpAddr, u32)) -> bool {
+    match (network, ip.to_canonical()) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Address of the client: the TCP peer, or, when `TRUST_PROXY` says a
+/// reverse proxy sits in front of the API, the right-most `X-Forwarded-For`
+/// hop that is not one of `TRUSTED_PROXIES`. Hops further left were sent by
+/// the client itself and are never trusted.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_canonical());
+
+        Ok(ClientIp(client_address(
+            peer,
+            &parts.headers,
+            state.trust_proxy,
+            &state.trusted_proxies,
+        )))
+    }
+}
+
+/// Resolves the client address behind `peer`. Every `X-Forwarded-For` line
+/// is read, in order, since proxies may append a line of their own rather
+/// than extend the one the client sent.
+fn client_address(
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    trust_proxy: bool,
+    trusted_proxies: &[(IpAddr, u32)],
+) -> Option<IpAddr> {
+    let is_proxy = |ip: IpAddr| trusted_proxies.iter().any(|range| ip_in_range(ip, *range));
+
+    // With a proxy list, only connections from a listed proxy may forward an
+    // address.
+    let peer_is_proxy = trusted_proxies.is_empty() || peer.is_some_and(is_proxy);
+    if !trust_proxy || !peer_is_proxy {
+        return peer;
+    }
+
+    let mut hops = Vec::new();
+    for line in headers.get_all("x-forwarded-for") {
+        // An unreadable line ends the trustworthy part of the chain.
+        let Ok(line) = line.to_str() else {
+            hops.clear();
+            continue;
+        };
+        hops.extend(line.split(',').map(str::trim));
+    }
+
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        let Ok(hop) = hop.parse::<IpAddr>() else {
+            break;
+        };
+        client = Some(hop.to_canonical());
+        if !is_proxy(hop) {
+            break;
+        }
+    }
+
+    client
+}
+
+/// Looks up the current role and access of a token's subject through the
+/// permission cache. `None` means the staff account was deleted or the
+/// member is missing or pending.
+async fn current_access(state: &AppState, claims: &Claims) -> Result<Option<LiveAccess>, AppError> {
+    let subject = match &claims.member_id {
+        Some(member_id) => CacheSubject::Member(member_id.clone()),
+        None => CacheSubject::Staff(claims.sub),
+    };
+    if let Some(cached) = state.permission_cache.get(&subject) {
+        return Ok(cached);
+    }
+
+    let live = match &subject {
+        CacheSubject::Member(member_id) => {
+            let is_pending: Option<i16> =
+                sqlx::query_scalar("SELECT is_pending FROM member WHERE member_id = ?")
+                    .bind(member_id)
+                    .fetch_optional(&state.pool)
+                    .await?;
+            is_pending
+                .filter(|pending| *pending == 0)
+                .map(|_| LiveAccess {
+                    role: Role::Member,
+                    access: Vec::new(),
+                })
+        }
+        CacheSubject::Staff(user_id) => {
+            fetch_staff_user(state, *user_id)
+                .await?
+                .map(|(user, access)| LiveAccess {
+                    role: user_to_role(&user),
+                    access,
+                })
+        }
+    };
+
+    state.permission_cache.insert(subject, live.clone());
+    Ok(live)
+}
+
 fn user_to_role(user: &User) -> Role {
     // Map based on group membership: group_id 1 or user_type==1 => Admin, else Staff
     let group_ids = parse_groups(user.groups.as_deref());
@@ -128,21 +309,49 @@ impl AuthUser {
         module: ModuleAccess,
         permission: Permission,
     ) -> Result<(), AppError> {
-        let module_id = module.id();
-        let can_access = self.claims.access.iter().find(|a| a.module_id == module_id);
-
-        let allowed = match (can_access, permission) {
-            (Some(access), Permission::Read) => access.read || access.write,
-            (Some(access), Permission::Write) => access.write,
-            _ => false,
-        };
-
-        if allowed {
+        if has_access(&self.claims.access, module, permission) {
             Ok(())
         } else {
             Err(AppError::Forbidden("insufficient permissions".into()))
         }
     }
+
+    /// System write for the endpoints that manage staff accounts and groups.
+    /// API keys are refused even when they hold System write, so a leaked
+    /// integration key cannot create logins or widen a group's access.
+    pub fn require_account_admin(&self) -> Result<(), AppError> {
+        if self.claims.role == Role::Service {
+            return Err(AppError::Forbidden(
+                "API keys cannot manage staff accounts or groups".into(),
+            ));
+        }
+        self.require_access(ModuleAccess::System, Permission::Write)
+    }
+
+    /// The member ID of a member token; staff tokens are rejected.
+    pub fn require_member(&self) -> Result<&str, AppError> {
+        match (&self.claims.role, self.claims.member_id.as_deref()) {
+            (Role::Member, Some(member_id)) => Ok(member_id),
+            _ => Err(AppError::Forbidden(
+                "this endpoint is only available to members".into(),
+            )),
+        }
+    }
+}
+
+pub fn has_access(
+    access: &[ModulePermission],
+    module: ModuleAccess,
+    permission: Permission,
+) -> bool {
+    let module_id = module.id();
+    let can_access = access.iter().find(|a| a.module_id == module_id);
+
+    match (can_access, permission) {
+        (Some(access), Permission::Read) => access.read || access.write,
+        (Some(access), Permission::Write) => access.write,
+        _ => false,
+    }
 }
 
 fn extract_bearer(headers: &HeaderMap) -> Result<String, AppError> {
@@ -175,6 +384,29 @@ pub struct AuthResponse {
     pub expires_at: usize,
     pub role: Role,
     pub access: Vec<ModulePermission>,
+    /// Single-use token for `POST /auth/refresh`. Not issued to members.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_expires_at: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MemberLoginRequest {
+    pub member_id: String,
+    /// Checked against `member.mpasswd`.
+    pub password: Option<String>,
+    /// Checked against `member.pin`; only accepted when `MEMBER_PIN_LOGIN`
+    /// is enabled.
+    pub pin: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct MemberCredentials {
+    member_id: String,
+    is_pending: i16,
+    mpasswd: Option<String>,
+    pin: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -184,6 +416,8 @@ pub struct User {
     pub passwd: String,
     pub groups: Option<String>,
     pub user_type: Option<i16>,
+    /// Base32 TOTP secret from the `2fa` column; empty when 2FA is off.
+    pub two_factor: Option<String>,
 }
 
 #[utoipa::path(
@@ -191,88 +425,374 @@ pub struct User {
     path = "/auth/login",
     request_body = LoginRequest,
     responses(
-        (status = 200, description = "Login success", body = JsonApiDocument),
+        (status = 200, description = "Login success, or a `2fa-challenges` resource when the account has two-factor authentication enabled", body = JsonApiDocument),
         (status = 401, description = "Invalid credentials"),
+        (status = 429, description = "Too many failed attempts; see the `Retry-After` header"),
     ),
     tag = "Auth"
 )]
 pub async fn login(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<LoginRequest>,
 ) -> Result<Json<JsonApiDocument>, AppError> {
-    let user = sqlx::query_as::<_, User>(
-        "SELECT user_id, username, passwd, `groups`, user_type FROM `user` WHERE username = ?",
-    )
-    .bind(&payload.username)
-    .fetch_optional(&state.pool)
-    .await?
-    .ok_or_else(|| AppError::Unauthorized("invalid credentials".into()))?;
+    let subject = Subject::Staff(&payload.username);
+    let (user, access) =
+        match checked_staff_login(&state, &payload.username, &payload.password, ip).await? {
+            Some(found) => found,
+            None => return Err(AppError::Unauthorized("invalid credentials".into())),
+        };
 
-    verify(&payload.password, &user.passwd)
-        .map_err(|_| AppError::Unauthorized("invalid credentials".into()))
-        .and_then(|ok| {
-            if ok {
-                Ok(())
-            } else {
-                Err(AppError::Unauthorized("invalid credentials".into()))
-            }
-        })?;
+    if two_factor::enabled_secret(&user).is_some() {
+        let challenge = two_factor::challenge(&state, user.user_id)?;
+        return Ok(Json(single_document(resource(
+            "2fa-challenges",
+            user.user_id.to_string(),
+            challenge,
+        ))));
+    }
 
-    let role = user_to_role(&user);
+    login_guard::succeeded(&state, subject, ip).await?;
+    let mut conn = state.pool.acquire().await?;
+    let response = start_session(&state, &mut conn, &user, access).await?;
 
-    let group_ids = parse_groups(user.groups.as_deref());
-    let access = fetch_group_access(&state, &group_ids).await?;
-    let exp = (SystemTime::now()
+    let token_id = response.token.clone();
+    Ok(Json(single_document(resource(
+        "tokens", token_id, response,
+    ))))
+}
+
+/// Checks a staff password behind the login throttle, counting and logging
+/// a failure. `None` means the credentials were wrong.
+pub async fn checked_staff_login(
+    state: &AppState,
+    username: &str,
+    password: &str,
+    ip: Option<IpAddr>,
+) -> Result<Option<(User, Vec<ModulePermission>)>, AppError> {
+    let subject = Subject::Staff(username);
+    let attempt = login_guard::check(state, subject, ip).await?;
+
+    match verify_staff_credentials(state, username, password).await {
+        Ok(found) => Ok(Some(found)),
+        Err(AppError::Unauthorized(_)) => {
+            login_guard::failed(state, attempt, "invalid credentials").await?;
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Issues the access and refresh tokens of a completed login.
+pub async fn start_session(
+    state: &AppState,
+    conn: &mut MySqlConnection,
+    user: &User,
+    access: Vec<ModulePermission>,
+) -> Result<AuthResponse, AppError> {
+    let issued = refresh::issue(conn, user.user_id, None).await?;
+    auth_response(state, user, access, issued)
+}
+
+fn access_token_expiry() -> usize {
+    (SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
         + Duration::from_secs(60 * 60))
-    .as_secs() as usize;
+    .as_secs() as usize
+}
+
+fn sign_claims(state: &AppState, claims: &Claims) -> Result<String, AppError> {
+    state.jwt_keys.sign(claims)
+}
+
+/// Signs a one-hour access token for `user` and pairs it with `refresh`.
+pub fn auth_response(
+    state: &AppState,
+    user: &User,
+    access: Vec<ModulePermission>,
+    refresh: IssuedRefreshToken,
+) -> Result<AuthResponse, AppError> {
+    let role = user_to_role(user);
+    let exp = access_token_expiry();
 
     let claims = Claims {
         sub: user.user_id,
-        username: user.username,
+        username: user.username.clone(),
         role: role.clone(),
         access: access.clone(),
+        member_id: None,
         exp,
     };
+    let token = sign_claims(state, &claims)?;
 
-    let token = encode(
-        &Header::new(Algorithm::HS256),
-        &claims,
-        &EncodingKey::from_secret(state.jwt_secret.as_bytes()),
-    )?;
-
-    let response = AuthResponse {
+    Ok(AuthResponse {
         token,
         expires_at: exp,
         role,
         access,
+        refresh_token: Some(refresh.token),
+        refresh_expires_at: Some(refresh.expires_at.and_utc().timestamp() as usize),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/member/login",
+    request_body = MemberLoginRequest,
+    responses(
+        (status = 200, description = "Login success", body = JsonApiDocument),
+        (status = 400, description = "Neither or both of password and pin given"),
+        (status = 401, description = "Invalid credentials"),
+        (status = 429, description = "Too many failed attempts; see the `Retry-After` header"),
+    ),
+    tag = "Auth"
+)]
+pub async fn member_login(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<MemberLoginRequest>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let subject = Subject::Member(&payload.member_id);
+    let attempt = login_guard::check(&state, subject, ip).await?;
+
+    let member = sqlx::query_as::<_, MemberCredentials>(
+        "SELECT member_id, is_pending, mpasswd, pin FROM member WHERE member_id = ?",
+    )
+    .bind(&payload.member_id)
+    .fetch_optional(&state.pool)
+    .await?;
+
+    let valid = match (payload.password.as_deref(), payload.pin.as_deref()) {
+        (Some(password), None) => member
+            .as_ref()
+            .is_some_and(|member| verify_secret(password, member.mpasswd.as_deref(), false)),
+        (None, Some(pin)) => {
+            if !state.member_pin_login {
+                return Err(AppError::BadRequest("PIN login is disabled".into()));
+            }
+            member
+                .as_ref()
+                .is_some_and(|member| verify_secret(pin, member.pin.as_deref(), true))
+        }
+        _ => {
+            return Err(AppError::BadRequest(
+                "provide either `password` or `pin`".into(),
+            ));
+        }
+    };
+    let Some(member) = member.filter(|_| valid) else {
+        login_guard::failed(&state, attempt, "invalid credentials").await?;
+        return Err(AppError::Unauthorized("invalid credentials".into()));
+    };
+    drop(attempt);
+
+    if member.is_pending != 0 {
+        return Err(AppError::Forbidden("membership is pending".into()));
+    }
+    login_guard::succeeded(&state, subject, ip).await?;
+
+    let exp = access_token_expiry();
+    let claims = Claims {
+        sub: 0,
+        username: member.member_id.clone(),
+        role: Role::Member,
+        access: Vec::new(),
+        member_id: Some(member.member_id),
+        exp,
+    };
+    let token = sign_claims(&state, &claims)?;
+
+    let response = AuthResponse {
+        token,
+        expires_at: exp,
+        role: Role::Member,
+        access: Vec::new(),
+        refresh_token: None,
+        refresh_expires_at: None,
     };
 
     let token_id = response.token.clone();
     Ok(Json(single_document(resource(
-        "tokens",
-        token_id,
-        response,
+        "tokens", token_id, response,
     ))))
 }
 
+/// The caller as `GET /auth/me` reports it.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CurrentUser {
+    /// Staff user ID; 0 for members and API keys.
+    pub user_id: i64,
+    pub username: String,
+    /// `user.realname`, `member.member_name` or the API key name.
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<i64>,
+    pub role: Role,
+    /// Effective module access as of this request, not as of login.
+    pub access: Vec<ModulePermission>,
+    /// Expiry of the presented token in Unix seconds; absent for API keys
+    /// without an expiry.
+    pub expires_at: Option<usize>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/me",
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 401, description = "Invalid token, or the account is disabled or no longer exists"),
+    ),
+    security(("bearerAuth" = []), ("apiKeyAuth" = [])),
+    tag = "Auth"
+)]
+pub async fn me(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let claims = auth.claims;
+
+    let name = if let Some(member_id) = &claims.member_id {
+        sqlx::query_scalar("SELECT member_name FROM member WHERE member_id = ?")
+            .bind(member_id)
+            .fetch_optional(&state.pool)
+            .await?
+    } else if auth.api_key_id.is_some() {
+        claims.username.strip_prefix("api-key:").map(str::to_string)
+    } else {
+        sqlx::query_scalar("SELECT realname FROM `user` WHERE user_id = ?")
+            .bind(claims.sub)
+            .fetch_optional(&state.pool)
+            .await?
+    };
+
+    let current = CurrentUser {
+        user_id: claims.sub,
+        username: claims.username.clone(),
+        name,
+        member_id: claims.member_id,
+        api_key_id: auth.api_key_id,
+        role: claims.role,
+        access: claims.access,
+        expires_at: (claims.exp != 0).then_some(claims.exp),
+    };
+
+    Ok(Json(single_document(resource(
+        "users",
+        claims.username,
+        current,
+    ))))
+}
+
+/// Hashes a new password with bcrypt in the `$2y$` form that PHP's
+/// `password_hash` writes, so SLiMS itself can still verify it.
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    bcrypt::hash_with_result(password, bcrypt::DEFAULT_COST)
+        .map(|parts| parts.format_for_version(bcrypt::Version::TwoY))
+        .map_err(|err| AppError::Internal(err.to_string()))
+}
+
+/// Checks a member password or PIN. Passwords are always bcrypt; PINs are
+/// kept in plain text by SLiMS unless they already look like a bcrypt hash.
+pub fn verify_secret(presented: &str, stored: Option<&str>, allow_plain: bool) -> bool {
+    let Some(stored) = stored.filter(|stored| !stored.is_empty()) else {
+        return false;
+    };
+    if stored.starts_with("$2") {
+        verify(presented, stored).unwrap_or(false)
+    } else {
+        allow_plain && constant_time_eq(stored, presented)
+    }
+}
+
+/// Checks a staff username and password and loads the user's module access.
+pub async fn verify_staff_credentials(
+    state: &AppState,
+    username: &str,
+    password: &str,
+) -> Result<(User, Vec<ModulePermission>), AppError> {
+    let user = sqlx::query_as::<_, User>(
+        "SELECT user_id, username, passwd, `groups`, user_type, `2fa` AS two_factor FROM `user` WHERE username = ?",
+    )
+    .bind(username)
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or_else(|| AppError::Unauthorized("invalid credentials".into()))?;
+
+    verify(password, &user.passwd)
+        .map_err(|_| AppError::Unauthorized("invalid credentials".into()))
+        .and_then(|ok| {
+            if ok {
+                Ok(())
+            } else {
+                Err(AppError::Unauthorized("invalid credentials".into()))
+            }
+        })?;
+
+    let group_ids = parse_groups(user.groups.as_deref());
+    let access = fetch_group_access(state, &group_ids).await?;
+
+    Ok((user, access))
+}
+
+/// Reloads a staff user and their current module access by ID.
+pub async fn fetch_staff_user(
+    state: &AppState,
+    user_id: i64,
+) -> Result<Option<(User, Vec<ModulePermission>)>, AppError> {
+    let Some(user) = sqlx::query_as::<_, User>(
+        "SELECT user_id, username, passwd, `groups`, user_type, `2fa` AS two_factor FROM `user` WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let group_ids = parse_groups(user.groups.as_deref());
+    let access = fetch_group_access(state, &group_ids).await?;
+
+    Ok(Some((user, access)))
+}
+
 pub fn extract_secret(secret: String) -> Arc<str> {
     Arc::from(secret.into_boxed_str())
 }
 
-fn parse_groups(raw: Option<&str>) -> Vec<i64> {
+pub fn parse_groups(raw: Option<&str>) -> Vec<i64> {
     let Some(raw) = raw else {
         return Vec::new();
     };
 
     raw.split('"')
         .enumerate()
-        .filter_map(|(idx, part)| (idx % 2 == 1).then(|| part))
+        .filter_map(|(idx, part)| (idx % 2 == 1).then_some(part))
         .filter_map(|part| part.trim().parse::<i64>().ok())
         .collect()
 }
 
+/// Writes group IDs the way SLiMS stores `user.groups`: a PHP-serialized
+/// array of strings, e.g. `a:2:{i:0;s:1:"1";i:1;s:1:"3";}`.
+pub fn serialize_groups(group_ids: &[i64]) -> String {
+    let mut out = format!("a:{}:{{", group_ids.len());
+    for (idx, group_id) in group_ids.iter().enumerate() {
+        let value = group_id.to_string();
+        out.push_str(&format!("i:{};s:{}:\"{}\";", idx, value.len(), value));
+    }
+    out.push('}');
+    out
+}
+
 #[derive(Debug, FromRow)]
 struct GroupAccessRow {
     module_id: i64,
@@ -312,3 +832,144 @@ async fn fetch_group_access(
         })
         .collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn forwarded(lines: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for line in lines {
+            headers.append("x-forwarded-for", HeaderValue::from_str(line).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn ignores_the_header_without_trust_proxy() {
+        let headers = forwarded(&["203.0.113.7"]);
+        let peer = Some(ip("10.0.0.1"));
+        assert_eq!(client_address(peer, &headers, false, &[]), peer);
+    }
+
+    #[test]
+    fn takes_the_right_most_hop_without_a_proxy_list() {
+        let headers = forwarded(&["198.51.100.1, 203.0.113.7"]);
+        let peer = Some(ip("10.0.0.1"));
+        assert_eq!(
+            client_address(peer, &headers, true, &[]),
+            Some(ip("203.0.113.7"))
+        );
+        assert_eq!(client_address(peer, &HeaderMap::new(), true, &[]), peer);
+    }
+
+    #[test]
+    fn skips_trusted_hops_and_stops_at_the_first_untrusted_one() {
+        let proxies = [parse_ip_range("10.0.0.0/8").unwrap()];
+        let headers = forwarded(&["198.51.100.1, 203.0.113.7, 10.0.0.2"]);
+        assert_eq!(
+            client_address(Some(ip("10.0.0.1")), &headers, true, &proxies),
+            Some(ip("203.0.113.7"))
+        );
+    }
+
+    #[test]
+    fn reads_every_header_line_in_order() {
+        let proxies = [parse_ip_range("10.0.0.0/8").unwrap()];
+        // The client sent the first line, the proxy appended the second.
+        let headers = forwarded(&["198.51.100.1", "203.0.113.7"]);
+        assert_eq!(
+            client_address(Some(ip("10.0.0.1")), &headers, true, &proxies),
+            Some(ip("203.0.113.7"))
+        );
+        assert_eq!(
+            client_address(Some(ip("10.0.0.1")), &headers, true, &[]),
+            Some(ip("203.0.113.7"))
+        );
+
+        let headers = forwarded(&["198.51.100.1", "203.0.113.7, 10.0.0.2"]);
+        assert_eq!(
+            client_address(Some(ip("10.0.0.1")), &headers, true, &proxies),
+            Some(ip("203.0.113.7"))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_left_most_hop_when_all_are_trusted() {
+        let proxies = [parse_ip_range("10.0.0.0/8").unwrap()];
+        let headers = forwarded(&["10.0.0.3, 10.0.0.2"]);
+        assert_eq!(
+            client_address(Some(ip("10.0.0.1")), &headers, true, &proxies),
+            Some(ip("10.0.0.3"))
+        );
+    }
+
+    #[test]
+    fn stops_at_a_garbage_hop() {
+        let proxies = [parse_ip_range("10.0.0.0/8").unwrap()];
+        let headers = forwarded(&["198.51.100.1, unknown, 10.0.0.2"]);
+        assert_eq!(
+            client_address(Some(ip("10.0.0.1")), &headers, true, &proxies),
+            Some(ip("10.0.0.2"))
+        );
+
+        let headers = forwarded(&["198.51.100.1, not-an-ip"]);
+        let peer = Some(ip("10.0.0.1"));
+        assert_eq!(client_address(peer, &headers, true, &[]), peer);
+    }
+
+    #[test]
+    fn ignores_the_header_from_an_untrusted_peer() {
+        let proxies = [parse_ip_range("10.0.0.0/8").unwrap()];
+        let headers = forwarded(&["203.0.113.7"]);
+        let peer = Some(ip("192.0.2.9"));
+        assert_eq!(client_address(peer, &headers, true, &proxies), peer);
+    }
+
+    #[test]
+    fn matches_ipv4_mapped_addresses() {
+        let proxies = [parse_ip_range("10.0.0.0/8").unwrap()];
+        let headers = forwarded(&["203.0.113.7, ::ffff:10.0.0.2"]);
+        assert_eq!(
+            client_address(Some(ip("10.0.0.1")), &headers, true, &proxies),
+            Some(ip("203.0.113.7"))
+        );
+    }
+
+    /// `user.groups` of the `admin` row in the SLiMS install dump.
+    const SLIMS_ADMIN_GROUPS: &str = r#"a:1:{i:0;s:1:"1";}"#;
+    /// As written by the SLiMS user form for groups 1, 3 and 12.
+    const SLIMS_USER_GROUPS: &str = r#"a:3:{i:0;s:1:"1";i:1;s:1:"3";i:2;s:2:"12";}"#;
+
+    #[test]
+    fn parses_slims_groups() {
+        assert_eq!(parse_groups(Some(SLIMS_ADMIN_GROUPS)), vec![1]);
+        assert_eq!(parse_groups(Some(SLIMS_USER_GROUPS)), vec![1, 3, 12]);
+        assert_eq!(parse_groups(Some("a:0:{}")), Vec::<i64>::new());
+        assert_eq!(parse_groups(Some("")), Vec::<i64>::new());
+        assert_eq!(parse_groups(None), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn serializes_groups_like_slims() {
+        assert_eq!(serialize_groups(&[1]), SLIMS_ADMIN_GROUPS);
+        assert_eq!(serialize_groups(&[1, 3, 12]), SLIMS_USER_GROUPS);
+        assert_eq!(serialize_groups(&[]), "a:0:{}");
+    }
+
+    #[test]
+    fn groups_round_trip() {
+        for groups in [vec![], vec![7], vec![2, 10, 100, 1234]] {
+            assert_eq!(parse_groups(Some(&serialize_groups(&groups))), groups);
+        }
+        for raw in [SLIMS_ADMIN_GROUPS, SLIMS_USER_GROUPS] {
+            assert_eq!(serialize_groups(&parse_groups(Some(raw))), raw);
+        }
+    }
+}
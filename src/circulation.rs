@@ -0,0 +1,1077 @@
+use std::collections::HashSet;
+
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, Weekday};
+use serde::Serialize;
+use sqlx::{FromRow, MySqlConnection};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+
+/// A circulation rule that prevented a transaction. Each variant maps to a
+/// stable `code` in the JSON:API error object so desk clients can show the
+/// reason without parsing `detail`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    #[error("member `{0}` not found")]
+    MemberNotFound(String),
+    #[error("membership expired on {0}")]
+    MemberExpired(NaiveDate),
+    #[error("membership is pending")]
+    MemberPending,
+    #[error("member type is not configured for this member")]
+    MemberTypeNotFound,
+    #[error("member already has {limit} open loan(s), the limit for this member type")]
+    LoanLimitReached { limit: i64 },
+    #[error("member already has {limit} open loan(s) under loan rule {loan_rules_id}")]
+    RuleLoanLimitReached { loan_rules_id: i64, limit: i64 },
+    #[error("item `{0}` not found")]
+    ItemNotFound(String),
+    #[error("item status `{0}` does not allow loans")]
+    ItemNotLoanable(String),
+    #[error("item is already on loan")]
+    ItemOnLoan,
+    #[error("loan has already been returned")]
+    LoanReturned,
+    #[error("loan has already been renewed {limit} time(s), the renewal limit")]
+    RenewLimitReached { limit: i64 },
+    #[error("item is reserved by another member")]
+    ItemReserved,
+    #[error("biblio {0} not found")]
+    BiblioNotFound(i64),
+    #[error("member type does not allow reservations")]
+    ReserveNotAllowed,
+    #[error("member already has {limit} reservation(s), the limit for this member type")]
+    ReserveLimitReached { limit: i64 },
+    #[error("member already has a reservation for this title or item")]
+    AlreadyReserved,
+    #[error("member already has this item on loan")]
+    ItemAlreadyBorrowed,
+    #[error("item `{0}` is not on loan")]
+    ItemNotOnLoan(String),
+}
+
+impl PolicyViolation {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PolicyViolation::MemberNotFound(_) => "member_not_found",
+            PolicyViolation::MemberExpired(_) => "member_expired",
+            PolicyViolation::MemberPending => "member_pending",
+            PolicyViolation::MemberTypeNotFound => "member_type_not_found",
+            PolicyViolation::LoanLimitReached { .. } => "loan_limit_reached",
+            PolicyViolation::RuleLoanLimitReached { .. } => "loan_rule_limit_reached",
+            PolicyViolation::ItemNotFound(_) => "item_not_found",
+            PolicyViolation::ItemNotLoanable(_) => "item_not_loanable",
+            PolicyViolation::ItemOnLoan => "item_on_loan",
+            PolicyViolation::LoanReturned => "loan_returned",
+            PolicyViolation::RenewLimitReached { .. } => "renew_limit_reached",
+            PolicyViolation::ItemReserved => "item_reserved",
+            PolicyViolation::BiblioNotFound(_) => "biblio_not_found",
+            PolicyViolation::ReserveNotAllowed => "reserve_not_allowed",
+            PolicyViolation::ReserveLimitReached { .. } => "reserve_limit_reached",
+            PolicyViolation::AlreadyReserved => "already_reserved",
+            PolicyViolation::ItemAlreadyBorrowed => "item_already_borrowed",
+            PolicyViolation::ItemNotOnLoan(_) => "item_not_on_loan",
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+pub struct Borrower {
+    pub member_id: String,
+    pub member_type_id: Option<i32>,
+    pub expire_date: NaiveDate,
+    pub is_pending: i16,
+}
+
+#[derive(Debug, FromRow)]
+pub struct LoanableItem {
+    pub item_code: String,
+    pub biblio_id: Option<i32>,
+    pub coll_type_id: Option<i32>,
+    pub item_status_id: Option<String>,
+    pub gmd_id: Option<i32>,
+    pub no_loan: Option<i16>,
+}
+
+#[derive(Debug, FromRow)]
+struct MemberTypeTerms {
+    loan_limit: i64,
+    loan_periode: i64,
+    reborrow_limit: i64,
+    fine_each_day: i64,
+    grace_periode: Option<i64>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct LoanRule {
+    pub loan_rules_id: i64,
+    pub loan_limit: Option<i64>,
+    pub loan_periode: Option<i64>,
+    pub reborrow_limit: Option<i64>,
+    pub fine_each_day: Option<i64>,
+    pub grace_periode: Option<i64>,
+}
+
+/// Terms that apply to one loan: the matching loan rule's values, the member
+/// type's where the rule leaves a column NULL. A `0` in the rule is a value
+/// like any other, so a rule can turn off fines or renewals.
+#[derive(Debug, Clone, Copy)]
+pub struct LoanTerms {
+    pub loan_rules_id: i64,
+    pub loan_periode: i64,
+    pub reborrow_limit: i64,
+    pub fine_each_day: i64,
+    pub grace_periode: i64,
+}
+
+impl LoanTerms {
+    fn merge(member_type: &MemberTypeTerms, rule: Option<&LoanRule>) -> Self {
+        let pick = |value: Option<i64>, fallback: i64| value.unwrap_or(fallback);
+        LoanTerms {
+            loan_rules_id: rule.map(|rule| rule.loan_rules_id).unwrap_or(0),
+            loan_periode: pick(
+                rule.and_then(|rule| rule.loan_periode),
+                member_type.loan_periode,
+            ),
+            reborrow_limit: pick(
+                rule.and_then(|rule| rule.reborrow_limit),
+                member_type.reborrow_limit,
+            ),
+            fine_each_day: pick(
+                rule.and_then(|rule| rule.fine_each_day),
+                member_type.fine_each_day,
+            ),
+            grace_periode: pick(
+                rule.and_then(|rule| rule.grace_periode),
+                member_type.grace_periode.unwrap_or(0),
+            ),
+        }
+    }
+
+    /// Like SLiMS, a return within the grace period is not fined at all, and
+    /// once it is exceeded every overdue day is charged.
+    pub fn fine_for(&self, overdue_days: i64) -> i64 {
+        if overdue_days <= self.grace_periode {
+            0
+        } else {
+            overdue_days * self.fine_each_day.max(0)
+        }
+    }
+}
+
+/// Everything `create_loan` needs once all circulation checks have passed.
+#[derive(Debug)]
+pub struct CheckoutPlan {
+    pub borrower: Borrower,
+    pub item: LoanableItem,
+    pub loan_rules_id: i64,
+    pub due_date: NaiveDate,
+}
+
+#[derive(Debug, FromRow)]
+struct OpenLoan {
+    loan_id: i64,
+    member_id: Option<String>,
+    item_code: Option<String>,
+    due_date: NaiveDate,
+    renewed: i64,
+    loan_rules_id: i64,
+    is_return: i64,
+}
+
+#[derive(Debug)]
+pub struct RenewalPlan {
+    pub loan_id: i64,
+    pub due_date: NaiveDate,
+}
+
+#[derive(Debug)]
+pub struct ReturnPlan {
+    pub loan_id: i64,
+    pub member_id: Option<String>,
+    pub item_code: Option<String>,
+    pub due_date: NaiveDate,
+    pub overdue_days: i64,
+    pub fine: i64,
+    pub waiting_hold: Option<WaitingHold>,
+}
+
+/// The oldest reservation a copy can satisfy: one placed on that exact
+/// item, or on its title without naming an item.
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+pub struct WaitingHold {
+    pub reserve_id: i64,
+    pub member_id: String,
+    pub reserve_date: NaiveDateTime,
+}
+
+/// A validated reservation. `item_code` is empty for title-level holds,
+/// as `reserve.item_code` is not nullable.
+#[derive(Debug)]
+pub struct HoldPlan {
+    pub member_id: String,
+    pub biblio_id: i64,
+    pub item_code: String,
+}
+
+#[derive(Debug, FromRow)]
+struct ReserveTerms {
+    enable_reserve: i64,
+    reserve_limit: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct HolidayRow {
+    holiday_dayname: String,
+    holiday_date: Option<NaiveDate>,
+}
+
+/// Closed days from the `holiday` table: rows without a `holiday_date` are
+/// weekly closures, the others close a single date.
+#[derive(Debug, Default)]
+pub struct HolidayCalendar {
+    weekly: HashSet<Weekday>,
+    dates: HashSet<NaiveDate>,
+}
+
+impl HolidayCalendar {
+    pub async fn load(conn: &mut MySqlConnection) -> Result<Self, AppError> {
+        let rows =
+            sqlx::query_as::<_, HolidayRow>("SELECT holiday_dayname, holiday_date FROM holiday")
+                .fetch_all(&mut *conn)
+                .await?;
+
+        let mut calendar = HolidayCalendar::default();
+        for row in rows {
+            match row.holiday_date {
+                Some(date) => {
+                    calendar.dates.insert(date);
+                }
+                None => {
+                    if let Ok(day) = row.holiday_dayname.trim().parse::<Weekday>() {
+                        calendar.weekly.insert(day);
+                    }
+                }
+            }
+        }
+        Ok(calendar)
+    }
+
+    pub fn is_closed(&self, date: NaiveDate) -> bool {
+        self.weekly.contains(&date.weekday()) || self.dates.contains(&date)
+    }
+
+    /// Returns `date` itself when the library is open, otherwise the first
+    /// open day after it. Gives up after a year so a calendar that closes
+    /// every weekday cannot loop forever.
+    pub fn next_open_day(&self, date: NaiveDate) -> NaiveDate {
+        let mut candidate = date;
+        for _ in 0..366 {
+            if !self.is_closed(candidate) {
+                return candidate;
+            }
+            candidate = candidate + Days::new(1);
+        }
+        date
+    }
+
+    /// Counts the days after `due_date` up to and including `returned_on`,
+    /// leaving out closed days unless `count_closed_days` is set.
+    pub fn overdue_days(
+        &self,
+        due_date: NaiveDate,
+        returned_on: NaiveDate,
+        count_closed_days: bool,
+    ) -> i64 {
+        due_date
+            .iter_days()
+            .skip(1)
+            .take_while(|day| *day <= returned_on)
+            .filter(|day| count_closed_days || !self.is_closed(*day))
+            .count() as i64
+    }
+
+    pub fn due_date(&self, loan_date: NaiveDate, loan_periode: i64) -> NaiveDate {
+        let raw = loan_date + Days::new(loan_periode.max(0) as u64);
+        self.next_open_day(raw)
+    }
+}
+
+pub async fn fetch_borrower(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+) -> Result<Borrower, AppError> {
+    sqlx::query_as::<_, Borrower>(
+        "SELECT member_id, member_type_id, expire_date, is_pending FROM member WHERE member_id = ?",
+    )
+    .bind(member_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| PolicyViolation::MemberNotFound(member_id.to_string()).into())
+}
+
+pub async fn fetch_item(
+    conn: &mut MySqlConnection,
+    item_code: &str,
+) -> Result<LoanableItem, AppError> {
+    sqlx::query_as::<_, LoanableItem>(
+        "SELECT i.item_code, i.biblio_id, i.coll_type_id, i.item_status_id, b.gmd_id, s.no_loan FROM item i LEFT JOIN biblio b ON b.biblio_id = i.biblio_id LEFT JOIN mst_item_status s ON s.item_status_id = i.item_status_id WHERE i.item_code = ?",
+    )
+    .bind(item_code)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| PolicyViolation::ItemNotFound(item_code.to_string()).into())
+}
+
+pub fn check_borrower(borrower: &Borrower, today: NaiveDate) -> Result<(), PolicyViolation> {
+    if borrower.is_pending != 0 {
+        return Err(PolicyViolation::MemberPending);
+    }
+    if borrower.expire_date < today {
+        return Err(PolicyViolation::MemberExpired(borrower.expire_date));
+    }
+    Ok(())
+}
+
+async fn fetch_member_type_terms(
+    conn: &mut MySqlConnection,
+    member_type_id: i32,
+) -> Result<MemberTypeTerms, AppError> {
+    sqlx::query_as::<_, MemberTypeTerms>(
+        "SELECT loan_limit, loan_periode, reborrow_limit, fine_each_day, grace_periode FROM mst_member_type WHERE member_type_id = ?",
+    )
+    .bind(member_type_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| PolicyViolation::MemberTypeNotFound.into())
+}
+
+/// Resolves the terms of an existing loan from the rule recorded on it. The
+/// member's current type is used, so type changes apply to later renewals.
+pub async fn loan_terms(
+    conn: &mut MySqlConnection,
+    member_type_id: i32,
+    loan_rules_id: i64,
+) -> Result<LoanTerms, AppError> {
+    let member_type = fetch_member_type_terms(conn, member_type_id).await?;
+    let rule = if loan_rules_id > 0 {
+        sqlx::query_as::<_, LoanRule>(
+            "SELECT loan_rules_id, loan_limit, loan_periode, reborrow_limit, fine_each_day, grace_periode FROM mst_loan_rules WHERE loan_rules_id = ?",
+        )
+        .bind(loan_rules_id)
+        .fetch_optional(&mut *conn)
+        .await?
+    } else {
+        None
+    };
+
+    Ok(LoanTerms::merge(&member_type, rule.as_ref()))
+}
+
+/// Picks the most specific rule for the member type, preferring an exact
+/// collection type match over an exact GMD match. A `0` (or NULL) in
+/// `coll_type_id`/`gmd_id` acts as a wildcard, mirroring SLiMS.
+pub async fn find_loan_rule(
+    conn: &mut MySqlConnection,
+    member_type_id: i32,
+    item: &LoanableItem,
+) -> Result<Option<LoanRule>, AppError> {
+    let rule = sqlx::query_as::<_, LoanRule>(
+        "SELECT loan_rules_id, loan_limit, loan_periode, reborrow_limit, fine_each_day, grace_periode FROM mst_loan_rules WHERE member_type_id = ? AND COALESCE(coll_type_id, 0) IN (?, 0) AND COALESCE(gmd_id, 0) IN (?, 0) ORDER BY COALESCE(coll_type_id, 0) DESC, COALESCE(gmd_id, 0) DESC LIMIT 1",
+    )
+    .bind(member_type_id)
+    .bind(item.coll_type_id.unwrap_or(0))
+    .bind(item.gmd_id.unwrap_or(0))
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(rule)
+}
+
+/// Locks the member's row and the items' rows until the transaction ends, so
+/// two desks lending the same item, or to the same member, wait for each
+/// other instead of both passing the on-loan and loan limit checks. The
+/// counts only see the other desk's loan when the locks are taken before the
+/// transaction's first plain read (REPEATABLE READ keeps the snapshot of that
+/// read), so call this first. Rows are locked in a fixed order to avoid
+/// deadlocks between batches.
+pub async fn lock_checkout(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+    item_codes: &[&str],
+) -> Result<(), AppError> {
+    sqlx::query("SELECT member_id FROM member WHERE member_id = ? FOR UPDATE")
+        .bind(member_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    let mut item_codes = item_codes.to_vec();
+    item_codes.sort_unstable();
+    item_codes.dedup();
+    for item_code in item_codes {
+        sqlx::query("SELECT item_code FROM item WHERE item_code = ? FOR UPDATE")
+            .bind(item_code)
+            .fetch_optional(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Runs the checkout checks. Takes the locks of `lock_checkout` itself, which
+/// only suffices when nothing else was read in the transaction before.
+pub async fn plan_checkout(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+    item_code: &str,
+    today: NaiveDate,
+) -> Result<CheckoutPlan, AppError> {
+    lock_checkout(conn, member_id, &[item_code]).await?;
+    let borrower = fetch_borrower(conn, member_id).await?;
+    check_borrower(&borrower, today)?;
+
+    let item = fetch_item(conn, item_code).await?;
+    if item.no_loan.unwrap_or(0) != 0 {
+        return Err(PolicyViolation::ItemNotLoanable(
+            item.item_status_id.clone().unwrap_or_default(),
+        )
+        .into());
+    }
+
+    let on_loan: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM loan WHERE item_code = ? AND is_return = 0")
+            .bind(&item.item_code)
+            .fetch_one(&mut *conn)
+            .await?;
+    if on_loan > 0 {
+        return Err(PolicyViolation::ItemOnLoan.into());
+    }
+
+    let member_type_id = borrower
+        .member_type_id
+        .ok_or(PolicyViolation::MemberTypeNotFound)?;
+    let terms = fetch_member_type_terms(conn, member_type_id).await?;
+
+    let open_loans: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM loan WHERE member_id = ? AND is_return = 0")
+            .bind(&borrower.member_id)
+            .fetch_one(&mut *conn)
+            .await?;
+    // Like SLiMS, a member type limit of 0 means no limit.
+    if terms.loan_limit > 0 && open_loans >= terms.loan_limit {
+        return Err(PolicyViolation::LoanLimitReached {
+            limit: terms.loan_limit,
+        }
+        .into());
+    }
+
+    let rule = find_loan_rule(conn, member_type_id, &item).await?;
+    if let Some(LoanRule {
+        loan_rules_id,
+        loan_limit: Some(limit),
+        ..
+    }) = rule.as_ref()
+        && *limit > 0
+    {
+        let open_under_rule: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM loan WHERE member_id = ? AND is_return = 0 AND loan_rules_id = ?",
+        )
+        .bind(&borrower.member_id)
+        .bind(loan_rules_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        if open_under_rule >= *limit {
+            return Err(PolicyViolation::RuleLoanLimitReached {
+                loan_rules_id: *loan_rules_id,
+                limit: *limit,
+            }
+            .into());
+        }
+    }
+
+    let loan_terms = LoanTerms::merge(&terms, rule.as_ref());
+    let calendar = HolidayCalendar::load(conn).await?;
+
+    Ok(CheckoutPlan {
+        borrower,
+        item,
+        loan_rules_id: loan_terms.loan_rules_id,
+        due_date: calendar.due_date(today, loan_terms.loan_periode),
+    })
+}
+
+/// Reads the loan with a row lock, so a concurrent return or renewal of the
+/// same loan waits and then sees it closed or renewed.
+async fn fetch_open_loan(conn: &mut MySqlConnection, loan_id: i64) -> Result<OpenLoan, AppError> {
+    let loan = sqlx::query_as::<_, OpenLoan>(
+        "SELECT loan_id, member_id, item_code, due_date, renewed, loan_rules_id, is_return FROM loan WHERE loan_id = ? FOR UPDATE",
+    )
+    .bind(loan_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    if loan.is_return != 0 {
+        return Err(PolicyViolation::LoanReturned.into());
+    }
+
+    Ok(loan)
+}
+
+/// Checks whether an open loan may be renewed and computes its new due date:
+/// the loan period is added to the current due date, or to today when the
+/// loan is already overdue.
+pub async fn plan_renewal(
+    conn: &mut MySqlConnection,
+    loan_id: i64,
+    today: NaiveDate,
+) -> Result<RenewalPlan, AppError> {
+    let loan = fetch_open_loan(conn, loan_id).await?;
+
+    let member_id = loan.member_id.unwrap_or_default();
+    let borrower = fetch_borrower(conn, &member_id).await?;
+    check_borrower(&borrower, today)?;
+
+    let member_type_id = borrower
+        .member_type_id
+        .ok_or(PolicyViolation::MemberTypeNotFound)?;
+    let terms = loan_terms(conn, member_type_id, loan.loan_rules_id).await?;
+    if loan.renewed >= terms.reborrow_limit {
+        return Err(PolicyViolation::RenewLimitReached {
+            limit: terms.reborrow_limit,
+        }
+        .into());
+    }
+
+    if let Some(item_code) = loan.item_code.as_deref() {
+        let biblio_id = item_biblio_id(conn, item_code).await?;
+        if next_hold(conn, item_code, biblio_id, Some(&borrower.member_id))
+            .await?
+            .is_some()
+        {
+            return Err(PolicyViolation::ItemReserved.into());
+        }
+    }
+
+    let calendar = HolidayCalendar::load(conn).await?;
+    let base = loan.due_date.max(today);
+
+    Ok(RenewalPlan {
+        loan_id: loan.loan_id,
+        due_date: calendar.due_date(base, terms.loan_periode),
+    })
+}
+
+/// Reads the `ignore_holidays_fine_calc` setting (a PHP-serialized boolean).
+pub async fn ignore_holidays_for_fines(conn: &mut MySqlConnection) -> Result<bool, AppError> {
+    let value: Option<Option<String>> = sqlx::query_scalar(
+        "SELECT setting_value FROM setting WHERE setting_name = 'ignore_holidays_fine_calc'",
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(matches!(value.flatten().as_deref(), Some("b:1;")))
+}
+
+/// Computes lateness and the overdue fine for an open loan being returned.
+/// A return is never refused: when the member or their type no longer
+/// exists, the loan is simply closed without a fine.
+pub async fn plan_return(
+    conn: &mut MySqlConnection,
+    loan_id: i64,
+    today: NaiveDate,
+) -> Result<ReturnPlan, AppError> {
+    let loan = fetch_open_loan(conn, loan_id).await?;
+
+    let calendar = HolidayCalendar::load(conn).await?;
+    let count_closed_days = ignore_holidays_for_fines(conn).await?;
+    let overdue_days = calendar.overdue_days(loan.due_date, today, count_closed_days);
+
+    let member_type_id: Option<i32> = match &loan.member_id {
+        Some(member_id) => {
+            sqlx::query_scalar("SELECT member_type_id FROM member WHERE member_id = ?")
+                .bind(member_id)
+                .fetch_optional(&mut *conn)
+                .await?
+                .flatten()
+        }
+        None => None,
+    };
+
+    let fine = match member_type_id {
+        Some(member_type_id) if overdue_days > 0 => {
+            match loan_terms(conn, member_type_id, loan.loan_rules_id).await {
+                Ok(terms) => terms.fine_for(overdue_days),
+                Err(AppError::Policy(_)) => 0,
+                Err(err) => return Err(err),
+            }
+        }
+        _ => 0,
+    };
+
+    let waiting_hold = match loan.item_code.as_deref() {
+        Some(item_code) => {
+            let biblio_id = item_biblio_id(conn, item_code).await?;
+            next_hold(conn, item_code, biblio_id, None).await?
+        }
+        None => None,
+    };
+
+    Ok(ReturnPlan {
+        loan_id: loan.loan_id,
+        member_id: loan.member_id,
+        item_code: loan.item_code,
+        due_date: loan.due_date,
+        overdue_days,
+        fine,
+        waiting_hold,
+    })
+}
+
+async fn item_biblio_id(
+    conn: &mut MySqlConnection,
+    item_code: &str,
+) -> Result<Option<i32>, AppError> {
+    let biblio_id: Option<Option<i32>> =
+        sqlx::query_scalar("SELECT biblio_id FROM item WHERE item_code = ?")
+            .bind(item_code)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+    Ok(biblio_id.flatten())
+}
+
+/// Finds the first hold in the queue that `item_code` can satisfy, skipping
+/// holds placed by `other_than` (the member who currently has the item).
+pub async fn next_hold(
+    conn: &mut MySqlConnection,
+    item_code: &str,
+    biblio_id: Option<i32>,
+    other_than: Option<&str>,
+) -> Result<Option<WaitingHold>, AppError> {
+    let hold = sqlx::query_as::<_, WaitingHold>(
+        "SELECT reserve_id, member_id, reserve_date FROM reserve WHERE (item_code = ? OR (item_code = '' AND biblio_id = ?)) AND member_id <> ? ORDER BY reserve_date, reserve_id LIMIT 1",
+    )
+    .bind(item_code)
+    .bind(biblio_id.unwrap_or(0))
+    .bind(other_than.unwrap_or_default())
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(hold)
+}
+
+/// Checks that a member may place a hold on a title, or on one copy of it
+/// when `item_code` is given. Locks the member's row first, like
+/// `plan_checkout`, so two requests for the same member wait for each other
+/// instead of both passing the duplicate and `reserve_limit` checks; run it
+/// at the start of the transaction that inserts the hold.
+pub async fn plan_hold(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+    biblio_id: Option<i64>,
+    item_code: Option<&str>,
+    today: NaiveDate,
+) -> Result<HoldPlan, AppError> {
+    lock_checkout(conn, member_id, &[]).await?;
+    let borrower = fetch_borrower(conn, member_id).await?;
+    check_borrower(&borrower, today)?;
+
+    let member_type_id = borrower
+        .member_type_id
+        .ok_or(PolicyViolation::MemberTypeNotFound)?;
+    let terms = sqlx::query_as::<_, ReserveTerms>(
+        "SELECT enable_reserve, reserve_limit FROM mst_member_type WHERE member_type_id = ?",
+    )
+    .bind(member_type_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(PolicyViolation::MemberTypeNotFound)?;
+    if terms.enable_reserve == 0 {
+        return Err(PolicyViolation::ReserveNotAllowed.into());
+    }
+
+    let (biblio_id, item_code) = match item_code.filter(|code| !code.is_empty()) {
+        Some(item_code) => {
+            let item = fetch_item(conn, item_code).await?;
+            let item_biblio = item.biblio_id.map(i64::from).unwrap_or(0);
+            if let Some(requested) = biblio_id
+                && requested != item_biblio
+            {
+                return Err(AppError::BadRequest(format!(
+                    "item `{}` does not belong to biblio {}",
+                    item.item_code, requested
+                )));
+            }
+
+            let borrowed: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM loan WHERE item_code = ? AND member_id = ? AND is_return = 0",
+            )
+            .bind(&item.item_code)
+            .bind(&borrower.member_id)
+            .fetch_one(&mut *conn)
+            .await?;
+            if borrowed > 0 {
+                return Err(PolicyViolation::ItemAlreadyBorrowed.into());
+            }
+
+            (item_biblio, item.item_code)
+        }
+        None => {
+            let biblio_id = biblio_id.ok_or_else(|| {
+                AppError::BadRequest("either biblio_id or item_code is required".into())
+            })?;
+            let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM biblio WHERE biblio_id = ?")
+                .bind(biblio_id)
+                .fetch_one(&mut *conn)
+                .await?;
+            if exists == 0 {
+                return Err(PolicyViolation::BiblioNotFound(biblio_id).into());
+            }
+            (biblio_id, String::new())
+        }
+    };
+
+    let held: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM reserve WHERE member_id = ? AND biblio_id = ? AND item_code IN (?, '')",
+    )
+    .bind(&borrower.member_id)
+    .bind(biblio_id)
+    .bind(&item_code)
+    .fetch_one(&mut *conn)
+    .await?;
+    if held > 0 {
+        return Err(PolicyViolation::AlreadyReserved.into());
+    }
+
+    if terms.reserve_limit > 0 {
+        let holds: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM reserve WHERE member_id = ?")
+            .bind(&borrower.member_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        if holds >= terms.reserve_limit {
+            return Err(PolicyViolation::ReserveLimitReached {
+                limit: terms.reserve_limit,
+            }
+            .into());
+        }
+    }
+
+    Ok(HoldPlan {
+        member_id: borrower.member_id,
+        biblio_id,
+        item_code,
+    })
+}
+
+/// Removes the member's holds that a checkout of `item` satisfies, so the
+/// queue moves on once the reserved copy has been picked up.
+pub async fn fulfil_holds(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+    item: &LoanableItem,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "DELETE FROM reserve WHERE member_id = ? AND (item_code = ? OR (item_code = '' AND biblio_id = ?))",
+    )
+    .bind(member_id)
+    .bind(&item.item_code)
+    .bind(item.biblio_id.unwrap_or(0))
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Copies a loan into the denormalized `loan_history` table, replacing any
+/// earlier snapshot, so reports survive later deletion of the item, biblio
+/// or member. Call it inside the transaction that changed the loan; both
+/// tables are InnoDB (see `config::check_storage_engines`), so the snapshot
+/// commits or rolls back with the loan.
+pub async fn archive_loan(conn: &mut MySqlConnection, loan_id: i64) -> Result<(), AppError> {
+    sqlx::query(
+        "REPLACE INTO loan_history (loan_id, item_code, biblio_id, title, call_number, classification, gmd_name, language_name, location_name, collection_type_name, member_id, member_name, member_type_name, loan_date, due_date, renewed, is_lent, is_return, return_date, input_date, last_update) SELECT l.loan_id, l.item_code, COALESCE(i.biblio_id, 0), LEFT(b.title, 300), COALESCE(NULLIF(i.call_number, ''), b.call_number), b.classification, g.gmd_name, lang.language_name, loc.location_name, ct.coll_type_name, l.member_id, m.member_name, mt.member_type_name, l.loan_date, l.due_date, l.renewed, l.is_lent, l.is_return, l.return_date, l.input_date, l.last_update FROM loan l LEFT JOIN item i ON i.item_code = l.item_code LEFT JOIN biblio b ON b.biblio_id = i.biblio_id LEFT JOIN mst_gmd g ON g.gmd_id = b.gmd_id LEFT JOIN mst_language lang ON lang.language_id = b.language_id LEFT JOIN mst_location loc ON loc.location_id = i.location_id LEFT JOIN mst_coll_type ct ON ct.coll_type_id = i.coll_type_id LEFT JOIN member m ON m.member_id = l.member_id LEFT JOIN mst_member_type mt ON mt.member_type_id = m.member_type_id WHERE l.loan_id = ?",
+    )
+    .bind(loan_id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Writes a planned checkout: the loan row, the member's satisfied holds and
+/// the history snapshot. Returns the new loan ID.
+pub async fn checkout(
+    conn: &mut MySqlConnection,
+    plan: &CheckoutPlan,
+    due_date: NaiveDate,
+    uid: i64,
+    today: NaiveDate,
+    now: NaiveDateTime,
+) -> Result<i64, AppError> {
+    let result = sqlx::query(
+        "INSERT INTO loan (item_code, member_id, loan_date, due_date, loan_rules_id, is_lent, is_return, input_date, last_update, uid) VALUES (?, ?, ?, ?, ?, 1, 0, ?, ?, ?)",
+    )
+    .bind(&plan.item.item_code)
+    .bind(&plan.borrower.member_id)
+    .bind(today)
+    .bind(due_date)
+    .bind(plan.loan_rules_id)
+    .bind(now)
+    .bind(now)
+    .bind(uid)
+    .execute(&mut *conn)
+    .await?;
+
+    let loan_id = result.last_insert_id() as i64;
+    fulfil_holds(conn, &plan.borrower.member_id, &plan.item).await?;
+    archive_loan(conn, loan_id).await?;
+
+    Ok(loan_id)
+}
+
+/// Writes a planned return: closes the loan, debits any overdue fine and
+/// refreshes the history snapshot.
+pub async fn checkin(
+    conn: &mut MySqlConnection,
+    plan: &ReturnPlan,
+    uid: i64,
+    today: NaiveDate,
+    now: NaiveDateTime,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "UPDATE loan SET return_date = ?, is_return = 1, actual = ?, last_update = ?, uid = ? WHERE loan_id = ?",
+    )
+    .bind(today)
+    .bind(today)
+    .bind(now)
+    .bind(uid)
+    .bind(plan.loan_id)
+    .execute(&mut *conn)
+    .await?;
+
+    if plan.fine > 0
+        && let Some(member_id) = &plan.member_id
+    {
+        sqlx::query(
+            "INSERT INTO fines (fines_date, member_id, debet, credit, description) VALUES (?, ?, ?, 0, ?)",
+        )
+        .bind(today)
+        .bind(member_id)
+        .bind(plan.fine)
+        .bind(format!(
+            "Overdue fines for item {} ({} day(s) late)",
+            plan.item_code.as_deref().unwrap_or_default(),
+            plan.overdue_days
+        ))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    archive_loan(conn, plan.loan_id).await?;
+
+    Ok(())
+}
+
+pub async fn renew(
+    conn: &mut MySqlConnection,
+    plan: &RenewalPlan,
+    uid: i64,
+    now: NaiveDateTime,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "UPDATE loan SET due_date = ?, renewed = renewed + 1, last_update = ?, uid = ? WHERE loan_id = ? AND is_return = 0",
+    )
+    .bind(plan.due_date)
+    .bind(now)
+    .bind(uid)
+    .bind(plan.loan_id)
+    .execute(&mut *conn)
+    .await?;
+
+    archive_loan(conn, plan.loan_id).await?;
+
+    Ok(())
+}
+
+/// Finds the open loan of an item, for desks and kiosks that check items in
+/// by barcode.
+pub async fn open_loan_for_item(
+    conn: &mut MySqlConnection,
+    item_code: &str,
+) -> Result<i64, AppError> {
+    sqlx::query_scalar("SELECT loan_id FROM loan WHERE item_code = ? AND is_return = 0 LIMIT 1")
+        .bind(item_code)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| PolicyViolation::ItemNotOnLoan(item_code.to_string()).into())
+}
+
+/// A checkout, return or renewal as it is recorded in `system_log`, shared
+/// by the desk endpoints, member self-service and SIP2 kiosks so every
+/// channel leaves the same trail.
+#[derive(Debug)]
+pub struct LoanEvent<'a> {
+    /// `checkout`, `return` or `renew`.
+    pub action: &'a str,
+    pub item_code: &'a str,
+    pub member_id: &'a str,
+    pub due_date: NaiveDate,
+}
+
+impl LoanEvent<'_> {
+    /// The `system_log` message, naming `actor` as whoever performed it.
+    pub fn message(&self, actor: &str) -> String {
+        match self.action {
+            "checkout" => format!(
+                "{} checked out item {} to member {}, due {}",
+                actor, self.item_code, self.member_id, self.due_date
+            ),
+            "renew" => format!(
+                "{} renewed item {} for member {}, now due {}",
+                actor, self.item_code, self.member_id, self.due_date
+            ),
+            _ => format!(
+                "{} checked in item {} from member {}",
+                actor, self.item_code, self.member_id
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    /// Closed on weekends and on Monday 2024-03-11.
+    fn calendar() -> HolidayCalendar {
+        HolidayCalendar {
+            weekly: HashSet::from([Weekday::Sat, Weekday::Sun]),
+            dates: HashSet::from([day("2024-03-11")]),
+        }
+    }
+
+    fn terms(fine_each_day: i64, grace_periode: i64) -> LoanTerms {
+        LoanTerms {
+            loan_rules_id: 0,
+            loan_periode: 7,
+            reborrow_limit: 1,
+            fine_each_day,
+            grace_periode,
+        }
+    }
+
+    #[test]
+    fn next_open_day_rolls_over_weekends_and_holidays() {
+        let calendar = calendar();
+        // Friday is open.
+        assert_eq!(calendar.next_open_day(day("2024-03-01")), day("2024-03-01"));
+        assert_eq!(calendar.next_open_day(day("2024-03-02")), day("2024-03-04"));
+        assert_eq!(calendar.next_open_day(day("2024-03-03")), day("2024-03-04"));
+        // Weekend followed by the Monday holiday.
+        assert_eq!(calendar.next_open_day(day("2024-03-09")), day("2024-03-12"));
+    }
+
+    #[test]
+    fn next_open_day_gives_up_when_every_day_is_closed() {
+        let calendar = HolidayCalendar {
+            weekly: HashSet::from([
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+                Weekday::Sat,
+                Weekday::Sun,
+            ]),
+            dates: HashSet::new(),
+        };
+        assert_eq!(calendar.next_open_day(day("2024-03-01")), day("2024-03-01"));
+    }
+
+    #[test]
+    fn due_date_skips_closed_days() {
+        let calendar = calendar();
+        assert_eq!(calendar.due_date(day("2024-03-01"), 3), day("2024-03-04"));
+        assert_eq!(calendar.due_date(day("2024-03-01"), 8), day("2024-03-12"));
+        assert_eq!(calendar.due_date(day("2024-03-04"), 7), day("2024-03-12"));
+        assert_eq!(calendar.due_date(day("2024-03-05"), 0), day("2024-03-05"));
+        assert_eq!(calendar.due_date(day("2024-03-05"), -3), day("2024-03-05"));
+    }
+
+    #[test]
+    fn returned_on_or_before_the_due_date_is_not_overdue() {
+        let calendar = calendar();
+        let due = day("2024-03-05");
+        assert_eq!(calendar.overdue_days(due, due, false), 0);
+        assert_eq!(calendar.overdue_days(due, due, true), 0);
+        assert_eq!(calendar.overdue_days(due, day("2024-03-01"), true), 0);
+        assert_eq!(terms(500, 0).fine_for(0), 0);
+    }
+
+    #[test]
+    fn overdue_days_leave_out_closed_days_unless_counted() {
+        let calendar = calendar();
+        // Due Friday, returned Tuesday after the weekend and the holiday.
+        let due = day("2024-03-08");
+        let returned = day("2024-03-12");
+        assert_eq!(calendar.overdue_days(due, returned, false), 1);
+        assert_eq!(calendar.overdue_days(due, returned, true), 4);
+        assert_eq!(
+            calendar.overdue_days(day("2024-03-05"), day("2024-03-07"), false),
+            2
+        );
+    }
+
+    #[test]
+    fn fines_start_once_the_grace_period_is_exceeded() {
+        let terms = terms(500, 2);
+        assert_eq!(terms.fine_for(1), 0);
+        assert_eq!(terms.fine_for(2), 0);
+        // Past the grace period every overdue day is charged.
+        assert_eq!(terms.fine_for(3), 1500);
+        assert_eq!(terms.fine_for(10), 5000);
+    }
+
+    #[test]
+    fn fines_without_a_daily_rate_are_zero() {
+        assert_eq!(terms(0, 0).fine_for(5), 0);
+        assert_eq!(terms(-100, 0).fine_for(5), 0);
+    }
+
+    #[test]
+    fn loan_rules_apply_zero_values_and_fall_back_on_null() {
+        let member_type = MemberTypeTerms {
+            loan_limit: 5,
+            loan_periode: 14,
+            reborrow_limit: 2,
+            fine_each_day: 500,
+            grace_periode: Some(1),
+        };
+        let rule = LoanRule {
+            loan_rules_id: 9,
+            loan_limit: None,
+            loan_periode: None,
+            reborrow_limit: Some(0),
+            fine_each_day: Some(0),
+            grace_periode: None,
+        };
+
+        let merged = LoanTerms::merge(&member_type, Some(&rule));
+        assert_eq!(merged.loan_rules_id, 9);
+        assert_eq!(merged.loan_periode, 14);
+        assert_eq!(merged.reborrow_limit, 0);
+        assert_eq!(merged.fine_each_day, 0);
+        assert_eq!(merged.grace_periode, 1);
+
+        let merged = LoanTerms::merge(&member_type, None);
+        assert_eq!(merged.loan_rules_id, 0);
+        assert_eq!(merged.reborrow_limit, 2);
+        assert_eq!(merged.fine_each_day, 500);
+    }
+}
@@ -1,20 +1,80 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
 use anyhow::Context;
 use dotenvy::dotenv;
 use sqlx::{MySqlPool, mysql::MySqlPoolOptions};
 
+use crate::{
+    auth::parse_ip_range, error::AppError, jwt::JwtKeys, login_guard::LoginThrottle, mail::Mailer,
+    permission_cache::PermissionCache,
+};
+
 #[derive(Clone)]
 pub struct AppState {
     pub pool: MySqlPool,
+    /// HS256 secret for the short-lived 2FA step tokens, which only this
+    /// API reads, and for access tokens when `JWT_KEYS` is not set.
     pub jwt_secret: Arc<str>,
+    pub jwt_keys: Arc<JwtKeys>,
+    /// Allows members to log in with `member.pin` instead of a password.
+    pub member_pin_login: bool,
+    /// Take the client address from `X-Forwarded-For`.
+    pub trust_proxy: bool,
+    /// `(network, prefix length)` of the reverse proxies whose
+    /// `X-Forwarded-For` hops are skipped, see `auth::ClientIp`.
+    pub trusted_proxies: Arc<[(IpAddr, u32)]>,
+    /// Failed login counters shared by the HTTP and SIP2 logins.
+    pub login_throttle: Arc<LoginThrottle>,
+    /// Current role and access of token subjects, see `permission_cache`.
+    pub permission_cache: Arc<PermissionCache>,
+    pub mailer: Arc<dyn Mailer>,
+    /// See `AppConfig::password_reset_url`.
+    pub password_reset_url: Option<Arc<str>>,
+    /// Tables of `TRANSACTIONAL_TABLES` that were not InnoDB at startup.
+    pub non_innodb_tables: Arc<[String]>,
+}
+
+impl AppState {
+    /// Refuses circulation writes with 503 while `non_innodb_tables` is not
+    /// empty. Reads and catalog or membership writes keep working, so a
+    /// stock SLiMS database stays usable until it has been converted.
+    pub fn require_transactional_tables(&self) -> Result<(), AppError> {
+        if self.non_innodb_tables.is_empty() {
+            return Ok(());
+        }
+        Err(AppError::ServiceUnavailable(format!(
+            "circulation is disabled until tables {} use the InnoDB engine; apply migrations/innodb_circulation.sql and restart the API",
+            self.non_innodb_tables.join(", ")
+        )))
+    }
 }
 
 #[derive(Debug)]
 pub struct AppConfig {
     pub database_url: String,
     pub jwt_secret: String,
+    /// `(kid, path)` of each PEM private key for access tokens.
+    pub jwt_keys: Vec<(String, String)>,
+    pub jwt_signing_key_id: Option<String>,
     pub bind_addr: String,
+    pub member_pin_login: bool,
+    pub trust_proxy: bool,
+    pub trusted_proxies: Vec<(IpAddr, u32)>,
+    /// SIP2 listener address; the listener only starts when this is set.
+    pub sip2_bind_addr: Option<String>,
+    pub sip2_institution_id: String,
+    /// `smtp`, `file` or `log`.
+    pub mail_transport: String,
+    pub mail_from: String,
+    pub mail_dir: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// `starttls`, `tls` or `none`.
+    pub smtp_encryption: String,
+    /// Link mailed for password resets; `{token}` is replaced by the token.
+    pub password_reset_url: Option<String>,
 }
 
 impl AppConfig {
@@ -39,12 +99,97 @@ impl AppConfig {
         });
 
         let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| "change-me-please".into());
+        let jwt_keys = match std::env::var("JWT_KEYS") {
+            Ok(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| {
+                    entry
+                        .split_once('=')
+                        .map(|(kid, path)| (kid.trim().to_string(), path.trim().to_string()))
+                        .with_context(|| format!("JWT_KEYS entry `{}` is not `kid=path`", entry))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            Err(_) => Vec::new(),
+        };
+        let jwt_signing_key_id = std::env::var("JWT_SIGNING_KEY_ID")
+            .ok()
+            .filter(|kid| !kid.trim().is_empty());
         let bind_addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".into());
 
+        let member_pin_login = std::env::var("MEMBER_PIN_LOGIN")
+            .map(|value| matches!(value.trim(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let trust_proxy = std::env::var("TRUST_PROXY")
+            .map(|value| matches!(value.trim(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let trusted_proxies = std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                parse_ip_range(entry).with_context(|| {
+                    format!(
+                        "TRUSTED_PROXIES entry `{}` is not an address or CIDR range",
+                        entry
+                    )
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let sip2_bind_addr = std::env::var("SIP2_BIND_ADDR")
+            .ok()
+            .filter(|addr| !addr.trim().is_empty());
+        let sip2_institution_id =
+            std::env::var("SIP2_INSTITUTION_ID").unwrap_or_else(|_| "SLiMS".into());
+
+        let optional = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let mail_transport = optional("MAIL_TRANSPORT")
+            .unwrap_or_else(|| "log".into())
+            .to_lowercase();
+        let mail_from = optional("MAIL_FROM").unwrap_or_else(|| "SLiMS <noreply@localhost>".into());
+        let mail_dir = optional("MAIL_DIR").unwrap_or_else(|| "mail".into());
+        let smtp_host = optional("SMTP_HOST");
+        let smtp_port = optional("SMTP_PORT")
+            .map(|port| {
+                port.parse::<u16>()
+                    .with_context(|| format!("SMTP_PORT `{}` is not a port number", port))
+            })
+            .transpose()?;
+        let smtp_username = optional("SMTP_USERNAME");
+        let smtp_password = std::env::var("SMTP_PASSWORD").ok();
+        let smtp_encryption = optional("SMTP_ENCRYPTION")
+            .unwrap_or_else(|| "starttls".into())
+            .to_lowercase();
+        let password_reset_url = optional("PASSWORD_RESET_URL");
+
         Ok(Self {
             database_url,
             jwt_secret,
+            jwt_keys,
+            jwt_signing_key_id,
             bind_addr,
+            member_pin_login,
+            trust_proxy,
+            trusted_proxies,
+            sip2_bind_addr,
+            sip2_institution_id,
+            mail_transport,
+            mail_from,
+            mail_dir,
+            smtp_host,
+            smtp_port,
+            smtp_username,
+            smtp_password,
+            smtp_encryption,
+            password_reset_url,
         })
     }
 }
@@ -57,3 +202,42 @@ pub async fn init_pool(database_url: &str) -> anyhow::Result<MySqlPool> {
         .await
         .with_context(|| "failed to connect to MySQL")
 }
+
+/// Tables that circulation writes together in one transaction, and the item
+/// and member rows it locks.
+const TRANSACTIONAL_TABLES: &[&str] = &[
+    "loan",
+    "loan_history",
+    "fines",
+    "reserve",
+    "system_log",
+    "item",
+    "member",
+];
+
+/// Lists the tables in `TRANSACTIONAL_TABLES` that are not InnoDB. SLiMS
+/// creates them as MyISAM, which silently ignores ROLLBACK, so a failed
+/// checkout, return or refused batch would leave half of its rows written;
+/// circulation writes are refused while any are left, see
+/// [`AppState::require_transactional_tables`].
+pub async fn non_innodb_tables(pool: &MySqlPool) -> anyhow::Result<Vec<String>> {
+    let engines: Vec<(String, Option<String>)> = sqlx::query_as(
+        "SELECT TABLE_NAME, ENGINE FROM information_schema.TABLES WHERE TABLE_SCHEMA = DATABASE()",
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to read table engines")?;
+
+    Ok(TRANSACTIONAL_TABLES
+        .iter()
+        .filter(|table| {
+            !engines.iter().any(|(name, engine)| {
+                name == *table
+                    && engine
+                        .as_deref()
+                        .is_some_and(|engine| engine.eq_ignore_ascii_case("InnoDB"))
+            })
+        })
+        .map(|table| table.to_string())
+        .collect())
+}
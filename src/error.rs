@@ -1,11 +1,14 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use thiserror::Error;
 
-use crate::jsonapi::{JsonApiError, JsonApiErrorDocument};
+use crate::{
+    circulation::PolicyViolation,
+    jsonapi::{JsonApiError, JsonApiErrorDocument},
+};
 
 #[derive(Error, Debug)]
 #[allow(dead_code)]
@@ -18,12 +21,25 @@ pub enum AppError {
     NotFound,
     #[error("bad request: {0}")]
     BadRequest(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+    #[error("not acceptable: {0}")]
+    NotAcceptable(String),
+    #[error("service unavailable: {0}")]
+    ServiceUnavailable(String),
     #[error("database error: {0}")]
     Database(#[from] sqlx::Error),
     #[error("internal error: {0}")]
     Internal(String),
     #[error(transparent)]
     Jwt(#[from] jsonwebtoken::errors::Error),
+    /// Carries the number of seconds to wait before retrying.
+    #[error("too many requests, retry after {0} seconds")]
+    TooManyRequests(u64),
+    #[error("policy violation: {0}")]
+    Policy(#[from] PolicyViolation),
 }
 
 impl IntoResponse for AppError {
@@ -45,6 +61,24 @@ impl IntoResponse for AppError {
                 "Bad Request",
                 Some(message.clone()),
             ),
+            AppError::Conflict(message) => {
+                (StatusCode::CONFLICT, "Conflict", Some(message.clone()))
+            }
+            AppError::UnsupportedMediaType(message) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Unsupported Media Type",
+                Some(message.clone()),
+            ),
+            AppError::NotAcceptable(message) => (
+                StatusCode::NOT_ACCEPTABLE,
+                "Not Acceptable",
+                Some(message.clone()),
+            ),
+            AppError::ServiceUnavailable(message) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service Unavailable",
+                Some(message.clone()),
+            ),
             AppError::Database(err) => {
                 if let sqlx::Error::RowNotFound = err {
                     (StatusCode::NOT_FOUND, "Not Found", Some("not found".into()))
@@ -66,15 +100,40 @@ impl IntoResponse for AppError {
                 "Internal Error",
                 None,
             ),
+            AppError::TooManyRequests(seconds) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too Many Requests",
+                Some(format!(
+                    "too many attempts; try again in {} seconds",
+                    seconds
+                )),
+            ),
+            AppError::Policy(violation) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Circulation Policy Violation",
+                Some(violation.to_string()),
+            ),
+        };
+
+        let code = match &self {
+            AppError::Policy(violation) => Some(violation.code().to_string()),
+            _ => None,
         };
 
         let error = JsonApiError {
             status: status.as_u16().to_string(),
+            code,
             title: Some(title.into()),
             detail,
         };
 
         let body = Json(JsonApiErrorDocument { errors: vec![error] });
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let AppError::TooManyRequests(seconds) = &self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(*seconds));
+        }
+        response
     }
 }
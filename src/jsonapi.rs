@@ -1,8 +1,18 @@
-use serde::Serialize;
-use serde_json::{json, Value};
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, OriginalUri, Request},
+    http::{HeaderValue, Method, Uri, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use std::collections::HashSet;
 use utoipa::ToSchema;
 
+use crate::error::AppError;
+
 #[derive(Debug, Serialize, ToSchema)]
 pub struct JsonApiDocument {
     #[schema(value_type = Object)]
@@ -13,12 +23,19 @@ pub struct JsonApiDocument {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schema(value_type = Vec<Object>, nullable)]
     pub included: Option<Vec<Value>>,
+    /// Pagination links of a collection: `self`, `first`, `prev`, `next`
+    /// and `last`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Object, nullable)]
+    pub links: Option<Value>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
 pub struct JsonApiError {
     pub status: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub detail: Option<String>,
@@ -43,38 +60,603 @@ pub fn resource_with_fields<T: Serialize>(
     attributes: T,
     fields: Option<&HashSet<String>>,
 ) -> Value {
-    let mut value = serde_json::to_value(attributes).unwrap_or(Value::Null);
-    if let (Some(allowed), Value::Object(map)) = (fields, &mut value) {
-        map.retain(|key, _| allowed.contains(key));
-    }
+    ResourceBuilder::new(resource_type, id, attributes, fields).build()
+}
 
+/// A resource identifier object, the linkage of a relationship.
+pub fn identifier(resource_type: &str, id: impl Into<String>) -> Value {
     json!({
         "type": resource_type,
         "id": id.into(),
-        "attributes": value,
     })
 }
 
+/// Builds a resource object with `relationships`. A sparse fieldset
+/// (`fields[type]`) limits attributes and relationships alike.
+pub struct ResourceBuilder<'a> {
+    resource_type: &'static str,
+    id: String,
+    attributes: Value,
+    relationships: Map<String, Value>,
+    fields: Option<&'a HashSet<String>>,
+}
+
+impl<'a> ResourceBuilder<'a> {
+    pub fn new<T: Serialize>(
+        resource_type: &'static str,
+        id: impl Into<String>,
+        attributes: T,
+        fields: Option<&'a HashSet<String>>,
+    ) -> Self {
+        ResourceBuilder {
+            resource_type,
+            id: id.into(),
+            attributes: serde_json::to_value(attributes).unwrap_or(Value::Null),
+            relationships: Map::new(),
+            fields,
+        }
+    }
+
+    /// A to-one relationship; `None` is the empty linkage `null`.
+    pub fn has_one(mut self, name: &str, linkage: Option<Value>) -> Self {
+        self.relationships
+            .insert(name.to_string(), json!({ "data": linkage }));
+        self
+    }
+
+    pub fn has_many(mut self, name: &str, linkage: Vec<Value>) -> Self {
+        self.relationships
+            .insert(name.to_string(), json!({ "data": linkage }));
+        self
+    }
+
+    pub fn build(mut self) -> Value {
+        if let Some(allowed) = self.fields {
+            if let Value::Object(map) = &mut self.attributes {
+                map.retain(|key, _| allowed.contains(key));
+            }
+            self.relationships.retain(|key, _| allowed.contains(key));
+        }
+
+        let mut resource = json!({
+            "type": self.resource_type,
+            "id": self.id,
+            "attributes": self.attributes,
+        });
+        if !self.relationships.is_empty() {
+            resource["relationships"] = Value::Object(self.relationships);
+        }
+        resource
+    }
+}
+
+/// Collects the `included` member of a compound document, keeping one
+/// resource object per type and id however many resources link to it.
+#[derive(Default)]
+pub struct Included {
+    resources: Vec<Value>,
+    seen: HashSet<(String, String)>,
+}
+
+impl Included {
+    /// Adds a resource object and returns its identifier for linkage.
+    pub fn add(&mut self, resource: Value) -> Value {
+        let key = resource_key(&resource);
+        let linkage = identifier(&key.0, key.1.clone());
+        if self.seen.insert(key) {
+            self.resources.push(resource);
+        }
+        linkage
+    }
+
+    /// Builds a related resource object with its sparse fieldset and adds it.
+    pub fn resource<T: Serialize>(
+        &mut self,
+        resource_type: &'static str,
+        id: impl Into<String>,
+        attributes: T,
+        fields: Option<&HashSet<String>>,
+    ) -> Value {
+        self.add(resource_with_fields(resource_type, id, attributes, fields))
+    }
+}
+
+fn resource_key(resource: &Value) -> (String, String) {
+    let field = |name: &str| resource[name].as_str().unwrap_or_default().to_string();
+    (field("type"), field("id"))
+}
+
+impl JsonApiDocument {
+    /// Attaches the collected resources, leaving out any that are already
+    /// primary data.
+    pub fn with_included(mut self, included: Included) -> Self {
+        let primary: HashSet<(String, String)> = match &self.data {
+            Value::Array(resources) => resources.iter().map(resource_key).collect(),
+            resource => HashSet::from([resource_key(resource)]),
+        };
+        let resources: Vec<Value> = included
+            .resources
+            .into_iter()
+            .filter(|resource| !primary.contains(&resource_key(resource)))
+            .collect();
+
+        if !resources.is_empty() {
+            self.included = Some(resources);
+        }
+        self
+    }
+}
+
 pub fn single_document(resource: Value) -> JsonApiDocument {
     JsonApiDocument {
         data: resource,
         meta: None,
         included: None,
+        links: None,
     }
 }
 
+/// A collection document. When `meta` comes from [`pagination_meta`] and the
+/// collection answers a GET request, pagination links are added as well.
+///
+/// The request URI comes from [`negotiate`], so this must only be called
+/// from routes behind that layer; debug builds panic otherwise.
 pub fn collection_document(data: Vec<Value>, meta: Value) -> JsonApiDocument {
+    let links = match REQUEST_URI
+        .try_with(|uri| uri.as_ref().and_then(|uri| pagination_links(uri, &meta)))
+    {
+        Ok(links) => links,
+        Err(_) => {
+            debug_assert!(
+                false,
+                "collection_document called outside jsonapi::negotiate"
+            );
+            tracing::warn!("collection document built outside jsonapi::negotiate, links dropped");
+            None
+        }
+    };
+
     JsonApiDocument {
         data: Value::Array(data),
         meta: Some(meta),
         included: None,
+        links,
     }
 }
 
 pub fn pagination_meta(page: u32, per_page: u32, total: i64) -> Value {
+    let total_pages = (total.max(0) as u64).div_ceil(u64::from(per_page.max(1)));
     json!({
         "page": page,
         "per_page": per_page,
         "total": total,
+        "total_pages": total_pages,
     })
 }
+
+tokio::task_local! {
+    /// The URI of the request being answered, or `None` when it is not a
+    /// GET. Set by [`negotiate`] for every request it lets through.
+    static REQUEST_URI: Option<Uri>;
+}
+
+/// Builds the links from the request URI, keeping every query parameter
+/// (filters, sort, include, fields, ...) except the page ones.
+fn pagination_links(uri: &Uri, meta: &Value) -> Option<Value> {
+    let page = meta.get("page")?.as_u64()?;
+    let per_page = meta.get("per_page")?.as_u64()?;
+    let last = meta.get("total_pages")?.as_u64()?.max(1);
+
+    let kept: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !is_page_param(pair))
+        .collect();
+    let link = |number: u64| {
+        let mut query = kept.join("&");
+        if !query.is_empty() {
+            query.push('&');
+        }
+        json!(format!(
+            "{}?{}page%5Bnumber%5D={}&page%5Bsize%5D={}",
+            uri.path(),
+            query,
+            number,
+            per_page
+        ))
+    };
+
+    Some(json!({
+        "self": link(page),
+        "first": link(1),
+        "prev": if page > 1 { link((page - 1).min(last)) } else { Value::Null },
+        "next": if page < last { link(page + 1) } else { Value::Null },
+        "last": link(last),
+    }))
+}
+
+fn is_page_param(pair: &str) -> bool {
+    let key = pair.split_once('=').map_or(pair, |(key, _)| key);
+    let key = key
+        .replace("%5B", "[")
+        .replace("%5b", "[")
+        .replace("%5D", "]")
+        .replace("%5d", "]");
+    matches!(
+        key.as_str(),
+        "page" | "per_page" | "page[number]" | "page[size]"
+    )
+}
+
+/// The JSON:API media type, required on request bodies and sent on every
+/// JSON response.
+pub const MEDIA_TYPE: &str = "application/vnd.api+json";
+
+/// Request payloads that arrive as the `attributes` of a resource object.
+pub trait ResourcePayload: DeserializeOwned {
+    /// The `type` the resource object must carry.
+    const TYPE: &'static str;
+}
+
+/// A JSON:API request document, `{"data": {"type", "id", "attributes"}}`,
+/// whose attributes deserialize into `T`. The body must be sent as
+/// `application/vnd.api+json` and `data.type` must be `T::TYPE`.
+pub struct JsonApi<T> {
+    pub attributes: T,
+    pub id: Option<String>,
+}
+
+impl<T> JsonApi<T> {
+    /// The attributes of an update, after checking that the document names
+    /// the resource being updated.
+    pub fn for_id(self, id: &str) -> Result<T, AppError> {
+        match self.id.as_deref() {
+            Some(document_id) if document_id == id => Ok(self.attributes),
+            Some(document_id) => Err(AppError::Conflict(format!(
+                "resource id `{}` does not match `{}` in the URL",
+                document_id, id
+            ))),
+            None => Err(AppError::BadRequest(
+                "the resource object must have an `id` when updating".into(),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RequestDocument {
+    data: RequestResource,
+}
+
+#[derive(Deserialize)]
+struct RequestResource {
+    #[serde(rename = "type")]
+    resource_type: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    attributes: Option<Value>,
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for JsonApi<T>
+where
+    T: ResourcePayload,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let (essence, params) = split_media_type(content_type);
+        if essence != MEDIA_TYPE {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "request bodies must be sent as {}",
+                MEDIA_TYPE
+            )));
+        }
+        if params.count() > 0 {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "{} does not take media type parameters here",
+                MEDIA_TYPE
+            )));
+        }
+
+        let body = Bytes::from_request(request, state)
+            .await
+            .map_err(|err| AppError::BadRequest(err.body_text()))?;
+        let document: RequestDocument = serde_json::from_slice(&body)
+            .map_err(|err| AppError::BadRequest(format!("invalid request document: {}", err)))?;
+        let resource = document.data;
+        if resource.resource_type != T::TYPE {
+            return Err(AppError::Conflict(format!(
+                "expected a `{}` resource, got `{}`",
+                T::TYPE,
+                resource.resource_type
+            )));
+        }
+
+        let attributes = resource
+            .attributes
+            .unwrap_or_else(|| Value::Object(Map::new()));
+        let attributes = serde_json::from_value(attributes)
+            .map_err(|err| AppError::BadRequest(format!("invalid attributes: {}", err)))?;
+
+        Ok(JsonApi {
+            attributes,
+            id: resource.id,
+        })
+    }
+}
+
+/// Splits `type/subtype; name=value` into the lowercase essence and the
+/// names of its parameters.
+fn split_media_type(value: &str) -> (String, impl Iterator<Item = &str>) {
+    let mut parts = value.split(';');
+    let essence = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+    let params = parts
+        .map(|param| param.split('=').next().unwrap_or_default().trim())
+        .filter(|name| !name.is_empty());
+    (essence, params)
+}
+
+/// Whether an `Accept` header admits a JSON:API response. Like the spec
+/// asks, the JSON:API media type only counts when it carries no media type
+/// parameters; the `q` weight is not one.
+fn accepts_json_api(accept: &str) -> bool {
+    accept.split(',').any(|range| {
+        let (essence, mut params) = split_media_type(range);
+        match essence.as_str() {
+            "*/*" | "application/*" => true,
+            MEDIA_TYPE => params.all(|name| name.eq_ignore_ascii_case("q")),
+            _ => false,
+        }
+    })
+}
+
+/// Whether an `Accept` header asks for plain `application/json`. JSON:API
+/// documents are JSON, so such clients get the same body, just labelled
+/// `application/json`.
+fn accepts_plain_json(accept: &str) -> bool {
+    accept
+        .split(',')
+        .any(|range| split_media_type(range).0 == "application/json")
+}
+
+/// Answers 406 to clients that accept neither JSON:API nor plain JSON and
+/// sends JSON responses as `application/vnd.api+json` whenever the client
+/// admits it. GET requests are answered with their URI at hand, so
+/// collections can link to their other pages.
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    let accept = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    let json_api = accept.is_none_or(accepts_json_api);
+    let acceptable = json_api || accept.is_some_and(accepts_plain_json);
+
+    let mut response = if acceptable {
+        let uri = (request.method() == Method::GET).then(|| {
+            match request.extensions().get::<OriginalUri>() {
+                Some(OriginalUri(uri)) => uri.clone(),
+                None => request.uri().clone(),
+            }
+        });
+        REQUEST_URI.scope(uri, next.run(request)).await
+    } else {
+        AppError::NotAcceptable(format!(
+            "responses are only available as {} or application/json",
+            MEDIA_TYPE
+        ))
+        .into_response()
+    };
+
+    let is_json = json_api
+        && response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|value| value == "application/json");
+    if is_json {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(MEDIA_TYPE));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json, Router,
+        body::{Body, to_bytes},
+        http::StatusCode,
+        middleware,
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Note {
+        text: String,
+    }
+
+    impl ResourcePayload for Note {
+        const TYPE: &'static str = "notes";
+    }
+
+    async fn extract(content_type: Option<&str>, body: &str) -> Result<JsonApi<Note>, AppError> {
+        let mut request = Request::builder().method(Method::POST).uri("/notes");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let request = request.body(Body::from(body.to_string())).unwrap();
+        JsonApi::<Note>::from_request(request, &()).await
+    }
+
+    const NOTE: &str = r#"{"data": {"type": "notes", "id": "7", "attributes": {"text": "hi"}}}"#;
+
+    #[tokio::test]
+    async fn reads_a_request_document() {
+        let document = extract(Some(MEDIA_TYPE), NOTE).await.unwrap();
+        assert_eq!(document.id.as_deref(), Some("7"));
+        assert_eq!(document.attributes.text, "hi");
+    }
+
+    #[tokio::test]
+    async fn rejects_other_media_types_and_parameters_with_415() {
+        for content_type in [
+            None,
+            Some("application/json"),
+            Some("application/vnd.api+json; charset=utf-8"),
+            Some("application/vnd.api+json; ext=\"https://example.org/ext\""),
+        ] {
+            assert!(
+                matches!(
+                    extract(content_type, NOTE).await,
+                    Err(AppError::UnsupportedMediaType(_))
+                ),
+                "{:?}",
+                content_type
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_a_resource_of_another_type_with_409() {
+        let body = r#"{"data": {"type": "members", "attributes": {"text": "hi"}}}"#;
+        match extract(Some(MEDIA_TYPE), body).await {
+            Err(AppError::Conflict(message)) => {
+                assert_eq!(message, "expected a `notes` resource, got `members`")
+            }
+            other => panic!("expected a conflict, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_the_id_of_an_update() {
+        let document = extract(Some(MEDIA_TYPE), NOTE).await.unwrap();
+        assert!(matches!(document.for_id("8"), Err(AppError::Conflict(_))));
+
+        let document = extract(Some(MEDIA_TYPE), NOTE).await.unwrap();
+        assert_eq!(document.for_id("7").unwrap().text, "hi");
+
+        let body = r#"{"data": {"type": "notes", "attributes": {"text": "hi"}}}"#;
+        let document = extract(Some(MEDIA_TYPE), body).await.unwrap();
+        assert!(matches!(document.for_id("7"), Err(AppError::BadRequest(_))));
+    }
+
+    async fn list_notes() -> Json<JsonApiDocument> {
+        Json(collection_document(Vec::new(), pagination_meta(2, 10, 35)))
+    }
+
+    fn app() -> Router {
+        let notes = Router::new().route("/notes", get(list_notes).post(list_notes));
+        Router::new()
+            .nest("/api", notes)
+            .layer(middleware::from_fn(negotiate))
+    }
+
+    async fn call(method: Method, uri: &str, accept: Option<&str>) -> Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response) -> Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn content_type(response: &Response) -> &str {
+        response.headers()[header::CONTENT_TYPE].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_an_unacceptable_accept_with_406() {
+        for accept in [
+            "text/html",
+            "application/xml, text/*",
+            "application/vnd.api+json; ext=\"https://example.org/ext\"",
+        ] {
+            let response = call(Method::GET, "/api/notes", Some(accept)).await;
+            assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE, "{}", accept);
+        }
+    }
+
+    #[tokio::test]
+    async fn labels_responses_by_what_the_client_accepts() {
+        for accept in [
+            None,
+            Some("*/*"),
+            Some(MEDIA_TYPE),
+            Some("application/vnd.api+json; q=0.5"),
+        ] {
+            let response = call(Method::GET, "/api/notes", accept).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(content_type(&response), MEDIA_TYPE);
+        }
+
+        let response = call(Method::GET, "/api/notes", Some("application/json")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(content_type(&response), "application/json");
+    }
+
+    #[tokio::test]
+    async fn page_links_keep_the_other_query_parameters() {
+        let response = call(
+            Method::GET,
+            "/api/notes?filter%5Bstatus%5D=open&sort=-date&page%5Bnumber%5D=2&per_page=10&include=member",
+            None,
+        )
+        .await;
+        let links = &body(response).await["links"];
+
+        let page = |number: u32| {
+            format!(
+                "/api/notes?filter%5Bstatus%5D=open&sort=-date&include=member&page%5Bnumber%5D={}&page%5Bsize%5D=10",
+                number
+            )
+        };
+        assert_eq!(links["self"], page(2));
+        assert_eq!(links["first"], page(1));
+        assert_eq!(links["prev"], page(1));
+        assert_eq!(links["next"], page(3));
+        assert_eq!(links["last"], page(4));
+    }
+
+    #[tokio::test]
+    async fn only_get_collections_get_links() {
+        let response = call(Method::POST, "/api/notes", None).await;
+        assert!(body(response).await.get("links").is_none());
+    }
+
+    #[test]
+    fn pagination_links_end_at_the_first_and_last_page() {
+        let uri: Uri = "/notes?page=1".parse().unwrap();
+        let links = pagination_links(&uri, &pagination_meta(1, 20, 0)).unwrap();
+        assert_eq!(links["self"], "/notes?page%5Bnumber%5D=1&page%5Bsize%5D=20");
+        assert_eq!(links["last"], "/notes?page%5Bnumber%5D=1&page%5Bsize%5D=20");
+        assert_eq!(links["prev"], Value::Null);
+        assert_eq!(links["next"], Value::Null);
+
+        assert!(pagination_links(&uri, &json!({ "total": 3 })).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "outside jsonapi::negotiate")]
+    fn collections_outside_negotiate_are_caught() {
+        collection_document(Vec::new(), pagination_meta(1, 20, 0));
+    }
+}
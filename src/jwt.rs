@@ -0,0 +1,197 @@
+//! Keys for signing and verifying access tokens. With `JWT_KEYS` set, tokens
+//! are signed with RS256 or EdDSA private keys read from PEM files and carry
+//! the key's `kid`, so other services can verify them against the public
+//! keys published at `/.well-known/jwks.json`. Without it, tokens fall back
+//! to HS256 with `JWT_SECRET` and no key is published.
+
+use anyhow::{Context, anyhow, bail};
+use axum::{Json, extract::State};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+};
+use ring::{
+    rsa::{KeyPair as RsaKeyPair, PublicKeyComponents},
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{config::AppState, error::AppError};
+
+struct TokenKey {
+    /// `None` only for the HS256 fallback, whose tokens have no `kid`.
+    kid: Option<String>,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// Public half for the JWKS; symmetric keys are never published.
+    jwk: Option<Jwk>,
+}
+
+/// Every key that verifies access tokens, and which one signs new ones.
+pub struct JwtKeys {
+    keys: Vec<TokenKey>,
+    signing: usize,
+}
+
+impl JwtKeys {
+    pub fn hmac(secret: &str) -> Self {
+        JwtKeys {
+            keys: vec![TokenKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                encoding: EncodingKey::from_secret(secret.as_bytes()),
+                decoding: DecodingKey::from_secret(secret.as_bytes()),
+                jwk: None,
+            }],
+            signing: 0,
+        }
+    }
+
+    /// Loads `(kid, path)` pairs. New tokens are signed with `signing_kid`,
+    /// or the first key when it is not given; the others only verify, which
+    /// lets a retiring key stay listed until its tokens have expired.
+    pub fn from_pem_files(
+        entries: &[(String, String)],
+        signing_kid: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let mut keys = Vec::with_capacity(entries.len());
+        for (kid, path) in entries {
+            if keys
+                .iter()
+                .any(|key: &TokenKey| key.kid.as_deref() == Some(kid))
+            {
+                bail!("duplicate JWT key id `{}`", kid);
+            }
+            let pem = std::fs::read(path)
+                .with_context(|| format!("failed to read JWT key `{}` from {}", kid, path))?;
+            let key = TokenKey::from_pem(kid, &pem)
+                .with_context(|| format!("failed to load JWT key `{}` from {}", kid, path))?;
+            keys.push(key);
+        }
+
+        if keys.is_empty() {
+            bail!("JWT_KEYS does not list any key");
+        }
+
+        let signing = match signing_kid {
+            Some(signing_kid) => keys
+                .iter()
+                .position(|key| key.kid.as_deref() == Some(signing_kid))
+                .ok_or_else(|| {
+                    anyhow!("JWT_SIGNING_KEY_ID `{}` is not in JWT_KEYS", signing_kid)
+                })?,
+            None => 0,
+        };
+
+        Ok(JwtKeys { keys, signing })
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, AppError> {
+        let key = &self.keys[self.signing];
+        let mut header = Header::new(key.algorithm);
+        header.kid = key.kid.clone();
+
+        Ok(encode(&header, claims, &key.encoding)?)
+    }
+
+    /// Verifies a token with the key named by its `kid`. The algorithm is
+    /// taken from the key, never from the token header.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T, AppError> {
+        let header = decode_header(token)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.kid == header.kid)
+            .ok_or_else(|| AppError::Unauthorized("unknown token signing key".into()))?;
+
+        Ok(decode::<T>(token, &key.decoding, &Validation::new(key.algorithm))?.claims)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+impl TokenKey {
+    /// Accepts RSA keys as PKCS#1 (`RSA PRIVATE KEY`) or PKCS#8 and Ed25519
+    /// keys as PKCS#8 (`PRIVATE KEY`), as written by `openssl genpkey`.
+    fn from_pem(kid: &str, pem: &[u8]) -> anyhow::Result<Self> {
+        let parsed = pem::parse(pem)?;
+        let der = parsed.contents();
+
+        let rsa = match parsed.tag() {
+            "RSA PRIVATE KEY" => Some(RsaKeyPair::from_der(der).map_err(|err| anyhow!("{}", err))?),
+            "PRIVATE KEY" => RsaKeyPair::from_pkcs8(der).ok(),
+            other => bail!("expected a private key, found `{}`", other),
+        };
+
+        if let Some(rsa) = rsa {
+            let public = PublicKeyComponents::<Vec<u8>>::from(rsa.public());
+            return Ok(TokenKey {
+                kid: Some(kid.to_string()),
+                algorithm: Algorithm::RS256,
+                encoding: EncodingKey::from_rsa_pem(pem)?,
+                decoding: DecodingKey::from_rsa_raw_components(&public.n, &public.e),
+                jwk: Some(public_jwk(
+                    kid,
+                    KeyAlgorithm::RS256,
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD.encode(&public.n),
+                        e: URL_SAFE_NO_PAD.encode(&public.e),
+                    }),
+                )),
+            });
+        }
+
+        let ed25519 = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+            .map_err(|_| anyhow!("not an RSA or Ed25519 private key"))?;
+        let public = ed25519.public_key().as_ref();
+        Ok(TokenKey {
+            kid: Some(kid.to_string()),
+            algorithm: Algorithm::EdDSA,
+            encoding: EncodingKey::from_ed_pem(pem)?,
+            decoding: DecodingKey::from_ed_der(public),
+            jwk: Some(public_jwk(
+                kid,
+                KeyAlgorithm::EdDSA,
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(public),
+                }),
+            )),
+        })
+    }
+}
+
+fn public_jwk(kid: &str, algorithm: KeyAlgorithm, parameters: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    }
+}
+
+/// Public keys for verifying access tokens, in JWK Set format rather than
+/// JSON:API so that standard JWT libraries can consume it directly.
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses((status = 200, description = "JWK Set with the public token signing keys", body = Object)),
+    tag = "Auth"
+)]
+pub async fn jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.jwt_keys.jwks())
+}
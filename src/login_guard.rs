@@ -0,0 +1,469 @@
+//! Brute-force protection for the login endpoints. Failed attempts are
+//! counted per account and per client address; past a few free attempts
+//! every further try has to wait an exponentially growing delay, and after
+//! too many failures the key is locked out for a while. Every attempt is
+//! counted as a failure up front, under the same lock as the check, so
+//! concurrent guesses cannot all slip past it; the count is given back
+//! unless the attempt turns out to be a failure. Counters live in memory,
+//! so they reset on restart and are not shared between instances.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audit::{LogType, SystemLogEntry, write_system_log},
+    config::AppState,
+    error::AppError,
+};
+
+/// Delay after the first failure beyond the free attempts; it doubles with
+/// every further failure.
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// Failures older than this are forgotten.
+const FORGET_AFTER: Duration = Duration::from_secs(15 * 60);
+/// Stale entries are swept once the map grows past this size.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Clone, Copy)]
+struct Policy {
+    free_attempts: u32,
+    lockout_after: u32,
+}
+
+const ACCOUNT_POLICY: Policy = Policy {
+    free_attempts: 3,
+    lockout_after: 10,
+};
+
+/// Addresses get more room because a whole library can sit behind one NAT.
+const IP_POLICY: Policy = Policy {
+    free_attempts: 10,
+    lockout_after: 50,
+};
+
+/// The account a login attempt is for.
+#[derive(Clone, Copy, Debug)]
+pub enum Subject<'a> {
+    /// A staff username.
+    Staff(&'a str),
+    /// A member ID.
+    Member(&'a str),
+}
+
+impl Subject<'_> {
+    /// Usernames and member IDs compare case-insensitively in MySQL, so the
+    /// counter key does too.
+    fn key(self) -> String {
+        match self {
+            Subject::Staff(username) => format!("staff:{}", username.to_lowercase()),
+            Subject::Member(member_id) => format!("member:{}", member_id.to_lowercase()),
+        }
+    }
+
+    fn log_type(self) -> LogType {
+        match self {
+            Subject::Staff(_) => LogType::Staff,
+            Subject::Member(_) => LogType::Member,
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Subject::Staff(username) => format!("user {}", username),
+            Subject::Member(member_id) => format!("member {}", member_id),
+        }
+    }
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+fn describe_ip(ip: Option<IpAddr>) -> String {
+    ip.map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown address".into())
+}
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+    /// Whether a rejected attempt was already logged for the current
+    /// lockout, so an ongoing attack writes one log row, not thousands.
+    block_logged: bool,
+}
+
+impl Attempts {
+    fn is_stale(&self, now: Instant) -> bool {
+        self.locked_until.is_none_or(|until| until <= now)
+            && now.duration_since(self.last_failure) >= FORGET_AFTER
+    }
+
+    fn retry_after(&self, policy: Policy, now: Instant) -> Option<Duration> {
+        if let Some(until) = self.locked_until
+            && until > now
+        {
+            return Some(until - now);
+        }
+        if self.failures <= policy.free_attempts {
+            return None;
+        }
+
+        let exponent = (self.failures - policy.free_attempts - 1).min(16);
+        let delay = (BASE_DELAY * 2u32.pow(exponent)).min(MAX_DELAY);
+        (self.last_failure + delay)
+            .checked_duration_since(now)
+            .filter(|wait| !wait.is_zero())
+    }
+}
+
+struct Blocked {
+    retry_after: Duration,
+    /// Set for the first rejected attempt of a lockout.
+    log: bool,
+}
+
+/// Failure counters keyed by account and by client address.
+#[derive(Default)]
+pub struct LoginThrottle {
+    attempts: Mutex<HashMap<String, Attempts>>,
+}
+
+impl LoginThrottle {
+    fn keys(subject: Subject<'_>, ip: Option<IpAddr>) -> Vec<(String, Policy)> {
+        let mut keys = vec![(subject.key(), ACCOUNT_POLICY)];
+        if let Some(ip) = ip {
+            keys.push((ip_key(ip), IP_POLICY));
+        }
+        keys
+    }
+
+    /// Checks the account and the address and, unless either is blocked,
+    /// counts the attempt as a failure in the same step. Returns the keys
+    /// it counted against and whether each started a lockout.
+    fn reserve(
+        &self,
+        subject: Subject<'_>,
+        ip: Option<IpAddr>,
+    ) -> Result<Vec<(String, bool)>, Blocked> {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap_or_else(|err| err.into_inner());
+        if attempts.len() > PRUNE_THRESHOLD {
+            attempts.retain(|_, entry| !entry.is_stale(now));
+        }
+
+        let mut blocked: Option<Blocked> = None;
+        for (key, policy) in Self::keys(subject, ip) {
+            let Some(entry) = attempts.get_mut(&key) else {
+                continue;
+            };
+            if entry.is_stale(now) {
+                attempts.remove(&key);
+                continue;
+            }
+            let Some(retry_after) = entry.retry_after(policy, now) else {
+                continue;
+            };
+
+            let log = entry.locked_until.is_some() && !entry.block_logged;
+            entry.block_logged |= log;
+            blocked = Some(match blocked {
+                Some(previous) => Blocked {
+                    retry_after: previous.retry_after.max(retry_after),
+                    log: previous.log || log,
+                },
+                None => Blocked { retry_after, log },
+            });
+        }
+
+        if let Some(blocked) = blocked {
+            return Err(blocked);
+        }
+
+        let mut reserved = Vec::new();
+        for (key, policy) in Self::keys(subject, ip) {
+            let entry = attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+                locked_until: None,
+                block_logged: false,
+            });
+            if entry.is_stale(now) {
+                entry.failures = 0;
+                entry.locked_until = None;
+            }
+
+            entry.failures += 1;
+            entry.last_failure = now;
+            let locked = entry.failures >= policy.lockout_after && entry.locked_until.is_none();
+            if locked {
+                entry.locked_until = Some(now + LOCKOUT);
+                entry.block_logged = false;
+            }
+            reserved.push((key, locked));
+        }
+
+        Ok(reserved)
+    }
+
+    /// Gives back failures counted by [`LoginThrottle::reserve`], together
+    /// with any lockout they started.
+    fn release(&self, reserved: &[(String, bool)]) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|err| err.into_inner());
+        for (key, locked) in reserved {
+            let Some(entry) = attempts.get_mut(key) else {
+                continue;
+            };
+            entry.failures = entry.failures.saturating_sub(1);
+            if *locked {
+                entry.locked_until = None;
+            }
+            if entry.failures == 0 && entry.locked_until.is_none() {
+                attempts.remove(key);
+            }
+        }
+    }
+
+    /// Clears the account counter. The address counter is left to decay so
+    /// that one valid account cannot be used to reset it.
+    fn record_success(&self, subject: Subject<'_>) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|err| err.into_inner());
+        attempts.remove(&subject.key());
+    }
+}
+
+/// A login attempt that passed [`check`] and is counted as a failure until
+/// it is dropped. [`failed`] keeps the count; dropping the attempt in any
+/// other way gives it back.
+#[must_use]
+pub struct Attempt<'a> {
+    throttle: &'a LoginThrottle,
+    subject: Subject<'a>,
+    ip: Option<IpAddr>,
+    reserved: Vec<(String, bool)>,
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        self.throttle.release(&self.reserved);
+    }
+}
+
+fn retry_seconds(wait: Duration) -> u64 {
+    (wait.as_secs() + u64::from(wait.subsec_nanos() > 0)).max(1)
+}
+
+async fn log_attempt(
+    state: &AppState,
+    subject: Subject<'_>,
+    action: &str,
+    message: String,
+) -> Result<(), AppError> {
+    let mut conn = state.pool.acquire().await?;
+    write_system_log(
+        &mut conn,
+        &SystemLogEntry {
+            log_type: subject.log_type(),
+            id: None,
+            location: "system",
+            sub_module: "login",
+            action,
+            message,
+        },
+    )
+    .await
+}
+
+/// Rejects the attempt with 429 while the account or the address is
+/// backing off or locked out, and otherwise counts it until the returned
+/// [`Attempt`] is dropped. Call before checking any credentials and keep
+/// the attempt until they have been checked.
+pub async fn check<'a>(
+    state: &'a AppState,
+    subject: Subject<'a>,
+    ip: Option<IpAddr>,
+) -> Result<Attempt<'a>, AppError> {
+    let blocked = match state.login_throttle.reserve(subject, ip) {
+        Ok(reserved) => {
+            return Ok(Attempt {
+                throttle: &state.login_throttle,
+                subject,
+                ip,
+                reserved,
+            });
+        }
+        Err(blocked) => blocked,
+    };
+
+    if blocked.log {
+        log_attempt(
+            state,
+            subject,
+            "login_blocked",
+            format!(
+                "Login for {} from {} rejected while locked out",
+                subject.describe(),
+                describe_ip(ip)
+            ),
+        )
+        .await?;
+    }
+
+    Err(AppError::TooManyRequests(retry_seconds(
+        blocked.retry_after,
+    )))
+}
+
+/// Keeps the failure counted by `attempt` and writes it to `system_log`.
+pub async fn failed(
+    state: &AppState,
+    mut attempt: Attempt<'_>,
+    reason: &str,
+) -> Result<(), AppError> {
+    let (subject, ip) = (attempt.subject, attempt.ip);
+    let locked = std::mem::take(&mut attempt.reserved)
+        .iter()
+        .any(|(_, locked)| *locked);
+    drop(attempt);
+
+    log_attempt(
+        state,
+        subject,
+        "login_failed",
+        format!(
+            "Login FAILED for {} from {}: {}",
+            subject.describe(),
+            describe_ip(ip),
+            reason
+        ),
+    )
+    .await?;
+
+    if locked {
+        log_attempt(
+            state,
+            subject,
+            "login_locked",
+            format!(
+                "Login for {} from {} locked for {} minutes after repeated failures",
+                subject.describe(),
+                describe_ip(ip),
+                LOCKOUT.as_secs() / 60
+            ),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Resets the account counter and records `last_login` and
+/// `last_login_ip` on the user or member row.
+pub async fn succeeded(
+    state: &AppState,
+    subject: Subject<'_>,
+    ip: Option<IpAddr>,
+) -> Result<(), AppError> {
+    state.login_throttle.record_success(subject);
+
+    // `user.last_login_ip` is char(15) and `member.last_login_ip`
+    // varchar(20); IPv6 addresses that do not fit are stored as NULL
+    // rather than truncated.
+    let (sql, id, width) = match subject {
+        Subject::Staff(username) => (
+            "UPDATE `user` SET last_login = NOW(), last_login_ip = ? WHERE username = ?",
+            username,
+            15,
+        ),
+        Subject::Member(member_id) => (
+            "UPDATE member SET last_login = NOW(), last_login_ip = ? WHERE member_id = ?",
+            member_id,
+            20,
+        ),
+    };
+    let ip = ip.map(|ip| ip.to_string()).filter(|ip| ip.len() <= width);
+
+    sqlx::query(sql)
+        .bind(ip)
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1)));
+
+    fn failures(throttle: &LoginThrottle, key: &str) -> u32 {
+        let attempts = throttle.attempts.lock().unwrap();
+        attempts.get(key).map_or(0, |entry| entry.failures)
+    }
+
+    #[test]
+    fn concurrent_attempts_are_blocked_before_any_failure_is_recorded() {
+        let throttle = LoginThrottle::default();
+        let subject = Subject::Staff("Admin");
+
+        let pending: Vec<_> = (0..ACCOUNT_POLICY.free_attempts + 1)
+            .map(|_| throttle.reserve(subject, IP).ok().unwrap())
+            .collect();
+        assert_eq!(failures(&throttle, "staff:admin"), 4);
+        assert!(throttle.reserve(subject, IP).is_err());
+
+        for reserved in &pending {
+            throttle.release(reserved);
+        }
+        assert_eq!(failures(&throttle, "staff:admin"), 0);
+        assert!(throttle.attempts.lock().unwrap().is_empty());
+        assert!(throttle.reserve(subject, IP).is_ok());
+    }
+
+    #[test]
+    fn releasing_undoes_the_lockout_it_started() {
+        let throttle = LoginThrottle::default();
+        let subject = Subject::Member("M1");
+        {
+            let mut attempts = throttle.attempts.lock().unwrap();
+            attempts.insert(
+                subject.key(),
+                Attempts {
+                    failures: ACCOUNT_POLICY.lockout_after - 1,
+                    last_failure: Instant::now() - MAX_DELAY,
+                    locked_until: None,
+                    block_logged: false,
+                },
+            );
+        }
+
+        let reserved = throttle.reserve(subject, None).ok().unwrap();
+        assert_eq!(reserved, vec![("member:m1".to_string(), true)]);
+        assert!(throttle.reserve(subject, None).is_err());
+
+        throttle.release(&reserved);
+        let attempts = throttle.attempts.lock().unwrap();
+        let entry = &attempts["member:m1"];
+        assert_eq!(entry.failures, ACCOUNT_POLICY.lockout_after - 1);
+        assert!(entry.locked_until.is_none());
+    }
+
+    #[test]
+    fn the_address_counter_is_shared_between_accounts() {
+        let throttle = LoginThrottle::default();
+        for n in 0..IP_POLICY.free_attempts + 1 {
+            let reserved = throttle.reserve(Subject::Member(&n.to_string()), IP);
+            assert!(reserved.is_ok());
+        }
+        assert!(throttle.reserve(Subject::Member("other"), IP).is_err());
+        assert!(throttle.reserve(Subject::Member("other"), None).is_ok());
+    }
+}
@@ -0,0 +1,151 @@
+//! Outgoing mail. Handlers build a `MailMessage` and hand it to the
+//! configured `Mailer`: SMTP for production, or a file drop or the log for
+//! development, where no mail server is around.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, bail};
+use axum::async_trait;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, header::ContentType},
+    transport::smtp::authentication::Credentials,
+};
+
+use crate::{config::AppConfig, error::AppError, tokens::random_hex};
+
+#[derive(Clone, Debug)]
+pub struct MailMessage {
+    pub to: String,
+    pub to_name: Option<String>,
+    pub subject: String,
+    /// Plain text body.
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: &MailMessage) -> Result<(), AppError>;
+}
+
+fn build_message(from: &Mailbox, message: &MailMessage) -> Result<Message, AppError> {
+    let address =
+        message.to.trim().parse().map_err(|_| {
+            AppError::BadRequest(format!("`{}` is not an email address", message.to))
+        })?;
+    let to = Mailbox::new(message.to_name.clone(), address);
+
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(&message.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(message.body.clone())
+        .map_err(|err| AppError::Internal(err.to_string()))
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, message: &MailMessage) -> Result<(), AppError> {
+        let email = build_message(&self.from, message)?;
+        self.transport
+            .send(email)
+            .await
+            .map_err(|err| AppError::Internal(format!("smtp: {}", err)))?;
+
+        Ok(())
+    }
+}
+
+/// Writes each message as an `.eml` file into a directory.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: Mailbox,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, message: &MailMessage) -> Result<(), AppError> {
+        let email = build_message(&self.from, message)?;
+        let name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            random_hex(4)
+        );
+        let path = self.dir.join(name);
+        tokio::fs::write(&path, email.formatted())
+            .await
+            .map_err(|err| AppError::Internal(format!("{}: {}", path.display(), err)))?;
+
+        tracing::info!("mail to {} written to {}", message.to, path.display());
+        Ok(())
+    }
+}
+
+/// Prints messages to the log instead of sending them. Only meant for
+/// development, since reset links end up in the log.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, message: &MailMessage) -> Result<(), AppError> {
+        tracing::info!(
+            "mail to {} <{}>: {}\n{}",
+            message.to_name.as_deref().unwrap_or_default(),
+            message.to,
+            message.subject,
+            message.body
+        );
+        Ok(())
+    }
+}
+
+/// Builds the mailer selected by `MAIL_TRANSPORT`.
+pub fn from_config(config: &AppConfig) -> anyhow::Result<Arc<dyn Mailer>> {
+    let from: Mailbox = config
+        .mail_from
+        .parse()
+        .with_context(|| format!("MAIL_FROM `{}` is not a valid mailbox", config.mail_from))?;
+
+    match config.mail_transport.as_str() {
+        "smtp" => {
+            let host = config
+                .smtp_host
+                .as_deref()
+                .context("SMTP_HOST is required when MAIL_TRANSPORT is smtp")?;
+            let mut builder = match config.smtp_encryption.as_str() {
+                "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+                "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+                "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+                other => bail!("SMTP_ENCRYPTION `{}` is not tls, starttls or none", other),
+            };
+            if let Some(port) = config.smtp_port {
+                builder = builder.port(port);
+            }
+            if let Some(username) = &config.smtp_username {
+                builder = builder.credentials(Credentials::new(
+                    username.clone(),
+                    config.smtp_password.clone().unwrap_or_default(),
+                ));
+            }
+
+            Ok(Arc::new(SmtpMailer {
+                transport: builder.build(),
+                from,
+            }))
+        }
+        "file" => {
+            let dir = PathBuf::from(&config.mail_dir);
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("failed to create MAIL_DIR {}", dir.display()))?;
+            Ok(Arc::new(FileMailer { dir, from }))
+        }
+        "log" => Ok(Arc::new(LogMailer)),
+        other => bail!("MAIL_TRANSPORT `{}` is not smtp, file or log", other),
+    }
+}
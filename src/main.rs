@@ -1,50 +1,120 @@
+mod audit;
 mod auth;
+mod circulation;
 mod config;
 mod error;
 mod jsonapi;
+mod jwt;
+mod login_guard;
+mod mail;
+mod password;
+mod permission_cache;
+mod refresh;
 mod resources;
+mod sip2;
+mod tokens;
+mod two_factor;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
-use axum::{Json, Router, routing::{get, post}};
+use axum::{
+    Json, Router, middleware,
+    routing::{get, post},
+};
 use serde_json::json;
 use tokio::net::TcpListener;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing_subscriber::EnvFilter;
-use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa::{Modify, OpenApi};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
     auth::extract_secret,
     auth::login,
-    config::{AppConfig, AppState, init_pool},
+    config::{AppConfig, AppState, init_pool, non_innodb_tables},
     jsonapi::{JsonApiDocument, resource, single_document},
+    jwt::JwtKeys,
 };
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         auth::login,
+        refresh::refresh,
+        refresh::logout,
+        refresh::logout_all,
+        auth::member_login,
+        auth::me,
+        password::change_password,
+        password::forgot_password,
+        password::forgot_member_password,
+        password::reset_password,
+        two_factor::verify_login,
+        two_factor::enroll,
+        two_factor::activate,
+        two_factor::regenerate_recovery_codes,
+        two_factor::disable,
+        jwt::jwks,
         health,
         resources::members::list_members,
         resources::members::get_member,
         resources::members::create_member,
+        resources::members::replace_member,
         resources::members::update_member,
         resources::members::delete_member,
         resources::items::list_items,
         resources::items::get_item,
         resources::items::create_item,
+        resources::items::replace_item,
         resources::items::update_item,
         resources::items::delete_item,
         resources::loans::list_loans,
+        resources::loans::list_loan_history,
+        resources::loans::list_overdue_loans,
         resources::loans::create_loan,
         resources::loans::return_loan,
+        resources::loans::renew_loan,
+        resources::loans::batch_checkout,
+        resources::loans::batch_checkin,
+        resources::fines::list_fines,
+        resources::fines::get_fine,
+        resources::fines::record_payment,
+        resources::fines::waive_fine,
+        resources::members::member_balance,
+        resources::reserves::list_reserves,
+        resources::reserves::get_reserve,
+        resources::reserves::create_reserve,
+        resources::reserves::cancel_reserve,
+        resources::api_keys::list_api_keys,
+        resources::api_keys::get_api_key,
+        resources::api_keys::create_api_key,
+        resources::api_keys::revoke_api_key,
+        resources::users::list_users,
+        resources::users::get_user,
+        resources::users::create_user,
+        resources::users::update_user,
+        resources::users::delete_user,
+        resources::groups::list_groups,
+        resources::groups::get_group,
+        resources::groups::create_group,
+        resources::groups::update_group,
+        resources::groups::delete_group,
+        resources::groups::get_group_access,
+        resources::groups::update_group_access,
+        resources::system_logs::list_system_logs,
+        resources::system_logs::get_system_log,
+        resources::me::get_profile,
+        resources::me::list_own_loans,
+        resources::me::list_own_fines,
+        resources::me::list_own_reservations,
+        resources::me::renew_own_loan,
         resources::biblios::list_biblios,
         resources::biblios::simple_search_biblios,
         resources::biblios::advanced_search_biblios,
         resources::biblios::get_biblio,
         resources::biblios::create_biblio,
+        resources::biblios::replace_biblio,
         resources::biblios::update_biblio,
         resources::biblios::delete_biblio,
         resources::contents::list_contents,
@@ -76,7 +146,20 @@ use crate::{
     ),
     components(schemas(
         auth::LoginRequest,
+        auth::MemberLoginRequest,
+        refresh::RefreshRequest,
+        two_factor::TwoFactorChallenge,
+        two_factor::VerifyLogin,
+        two_factor::TwoFactorEnrollment,
+        two_factor::ActivateTwoFactor,
+        two_factor::TwoFactorCode,
+        two_factor::RecoveryCodes,
         auth::AuthResponse,
+        auth::CurrentUser,
+        password::ChangePasswordRequest,
+        password::ForgotPasswordRequest,
+        password::ForgotMemberPasswordRequest,
+        password::ResetPasswordRequest,
         auth::Role,
         auth::ModuleAccess,
         auth::Permission,
@@ -85,9 +168,11 @@ use crate::{
         resources::members::Member,
         resources::members::MemberTypeInfo,
         resources::members::MemberResponse,
+        resources::members::UpdateMember,
         resources::members::CreateMember,
         resources::items::Item,
         resources::items::ItemResponse,
+        resources::items::UpdateItem,
         resources::items::CreateItem,
         resources::items::BiblioSummary,
         resources::items::CollTypeSummary,
@@ -95,12 +180,45 @@ use crate::{
         resources::items::ItemStatusSummary,
         resources::items::LoanStatusSummary,
         resources::loans::Loan,
-        resources::loans::LoanResponse,
+        resources::loans::ReturnedLoan,
+        resources::loans::LoanHistory,
+        resources::loans::OverdueLoan,
+        resources::loans::OverdueMember,
+        resources::loans::OverdueItem,
+        resources::loans::OverdueMemberGroup,
+        resources::loans::BatchCheckout,
+        resources::loans::BatchCheckin,
+        resources::loans::BatchItemStatus,
+        resources::loans::BatchItemError,
+        resources::loans::BatchItemResult,
         resources::loans::CreateLoan,
         resources::loans::LoanMember,
         resources::loans::LoanItem,
+        resources::fines::Fine,
+        resources::fines::FineBalance,
+        resources::fines::RecordPayment,
+        resources::fines::WaiveFine,
+        resources::reserves::Reserve,
+        resources::me::MemberLoan,
+        resources::api_keys::ApiKey,
+        resources::api_keys::CreatedApiKey,
+        resources::api_keys::CreateApiKey,
+        resources::users::StaffUser,
+        resources::users::CreateUser,
+        resources::users::UpdateUser,
+        resources::groups::Group,
+        resources::groups::CreateGroup,
+        resources::groups::ModuleGrant,
+        resources::groups::GroupAccess,
+        resources::groups::GrantRequest,
+        resources::groups::UpdateGroupAccess,
+        resources::system_logs::SystemLog,
+        resources::me::RenewOwnLoan,
+        resources::reserves::CreateReserve,
+        circulation::WaitingHold,
         resources::biblios::Biblio,
         resources::biblios::BiblioResponse,
+        resources::biblios::UpdateBiblio,
         resources::biblios::UpsertBiblio,
         resources::biblios::GmdInfo,
         resources::biblios::PublisherInfo,
@@ -118,7 +236,6 @@ use crate::{
         resources::contents::Content,
         resources::files::FileObject,
         resources::files::FileBiblioAttachment,
-        resources::files::FileResponse,
         resources::lookups::MemberType,
         resources::lookups::CollType,
         resources::lookups::Location,
@@ -147,6 +264,13 @@ use crate::{
         (name = "Members", description = "Manajemen member"),
         (name = "Items", description = "Manajemen item"),
         (name = "Loans", description = "Sirkulasi"),
+        (name = "Fines", description = "Denda"),
+        (name = "Reserves", description = "Reservasi"),
+        (name = "Me", description = "Layanan mandiri anggota"),
+        (name = "API Keys", description = "Kunci API integrasi"),
+        (name = "Users", description = "Manajemen pengguna staf"),
+        (name = "Groups", description = "Grup pengguna dan hak akses modul"),
+        (name = "System Logs", description = "Jejak audit perubahan data"),
         (name = "Biblios", description = "Bibliografi"),
         (name = "Contents", description = "Konten halaman"),
         (name = "Files", description = "Manajemen berkas"),
@@ -173,6 +297,13 @@ impl Modify for SecurityAddon {
                     .build(),
             ),
         );
+        components.add_security_scheme(
+            "apiKeyAuth",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-API-Key",
+                "API key for service integrations",
+            ))),
+        );
     }
 }
 
@@ -184,15 +315,59 @@ async fn main() -> anyhow::Result<()> {
 
     let config = AppConfig::from_env()?;
     let pool = init_pool(&config.database_url).await?;
+    let non_innodb = non_innodb_tables(&pool).await?;
+    if !non_innodb.is_empty() {
+        tracing::error!(
+            "tables {} are not InnoDB, so circulation writes are refused; apply migrations/innodb_circulation.sql and restart",
+            non_innodb.join(", ")
+        );
+    }
+    let jwt_keys = if config.jwt_keys.is_empty() {
+        JwtKeys::hmac(&config.jwt_secret)
+    } else {
+        JwtKeys::from_pem_files(&config.jwt_keys, config.jwt_signing_key_id.as_deref())?
+    };
+    let mailer = mail::from_config(&config)?;
     let jwt_secret = extract_secret(config.jwt_secret);
-    let state = AppState { pool, jwt_secret };
+    let state = AppState {
+        pool,
+        jwt_secret,
+        jwt_keys: Arc::new(jwt_keys),
+        member_pin_login: config.member_pin_login,
+        trust_proxy: config.trust_proxy,
+        trusted_proxies: config.trusted_proxies.clone().into(),
+        login_throttle: Default::default(),
+        permission_cache: Default::default(),
+        mailer,
+        password_reset_url: config.password_reset_url.clone().map(extract_secret),
+        non_innodb_tables: non_innodb.into(),
+    };
+
+    if let Some(sip2_addr) = &config.sip2_bind_addr {
+        let addr: SocketAddr = sip2_addr.parse()?;
+        let listener = TcpListener::bind(addr).await?;
+        tracing::info!("sip2 listening on {}", listener.local_addr()?);
+        let settings = sip2::Sip2Settings {
+            institution_id: config.sip2_institution_id.clone(),
+        };
+        let sip2_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(err) = sip2::serve(listener, sip2_state, settings).await {
+                tracing::error!("sip2 listener stopped: {}", err);
+            }
+        });
+    }
 
     let app = build_router(state.clone());
 
     let addr: SocketAddr = config.bind_addr.parse()?;
     let listener = TcpListener::bind(addr).await?;
     tracing::info!("listening on {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -203,15 +378,45 @@ fn build_router(state: AppState) -> Router {
     Router::new()
         .route("/health", get(health))
         .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh::refresh))
+        .route("/auth/logout", post(refresh::logout))
+        .route("/auth/logout-all", post(refresh::logout_all))
+        .route("/auth/member/login", post(auth::member_login))
+        .route("/auth/me", get(auth::me))
+        .route("/auth/password", post(password::change_password))
+        .route("/auth/password/forgot", post(password::forgot_password))
+        .route("/auth/password/reset", post(password::reset_password))
+        .route(
+            "/auth/member/password/forgot",
+            post(password::forgot_member_password),
+        )
+        .route("/auth/login/2fa", post(two_factor::verify_login))
+        .route("/auth/2fa/enroll", post(two_factor::enroll))
+        .route("/auth/2fa/activate", post(two_factor::activate))
+        .route(
+            "/auth/2fa/recovery-codes",
+            post(two_factor::regenerate_recovery_codes),
+        )
+        .route("/auth/2fa/disable", post(two_factor::disable))
         .nest("/members", resources::members::router())
         .nest("/items", resources::items::router())
         .nest("/loans", resources::loans::router())
+        .nest("/fines", resources::fines::router())
+        .nest("/reserves", resources::reserves::router())
+        .nest("/me", resources::me::router())
+        .nest("/api-keys", resources::api_keys::router())
+        .nest("/users", resources::users::router())
+        .nest("/groups", resources::groups::router())
+        .nest("/system-logs", resources::system_logs::router())
         .nest("/biblios", resources::biblios::router())
         .nest("/lookups", resources::lookups::router())
         .nest("/visitors", resources::visitors::router())
         .nest("/files", resources::files::router())
         .nest("/contents", resources::contents::router())
         .nest("/settings", resources::settings::router())
+        .layer(middleware::from_fn(jsonapi::negotiate))
+        // The key set and the API description are not JSON:API documents.
+        .route("/.well-known/jwks.json", get(jwt::jwks))
         .merge(SwaggerUi::new("/docs").url("/api-docs/openapi.json", ApiDoc::openapi()))
         .layer(TraceLayer::new_for_http())
         .layer(cors)
@@ -0,0 +1,421 @@
+//! Password changes for signed-in accounts and the forgotten password flow.
+//! A reset request mails a one-time `{selector}.{validator}` token; only the
+//! SHA-256 of the validator is stored, the token expires after
+//! `RESET_TOKEN_TTL_MINUTES` and all of the account's tokens are dropped
+//! once one of them is used.
+
+use axum::{Json, extract::State, http::StatusCode};
+use bcrypt::verify;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Deserialize;
+use sqlx::{FromRow, MySqlConnection};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, AuthUser, ClientIp},
+    config::AppState,
+    error::AppError,
+    login_guard::{self, Subject},
+    mail::MailMessage,
+    tokens::{constant_time_eq, random_hex, sha256_hex},
+    two_factor::{RECOVERY_SELECTOR_PREFIX, TOTP_STEP_SELECTOR_PREFIX},
+};
+
+const RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+/// A new reset mail is only sent once this long after the previous one, so
+/// the forgot endpoints cannot be used to flood someone's inbox.
+const RESEND_AFTER_MINUTES: i64 = 5;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ForgotPasswordRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ForgotMemberPasswordRequest {
+    pub member_id: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ResetPasswordRequest {
+    /// The token from the reset mail.
+    pub token: String,
+    pub new_password: String,
+}
+
+/// Mirrors the `account_type` enum of the `password_reset` table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AccountType {
+    Staff,
+    Member,
+}
+
+impl AccountType {
+    fn as_str(self) -> &'static str {
+        match self {
+            AccountType::Staff => "staff",
+            AccountType::Member => "member",
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct StoredResetToken {
+    account_type: String,
+    account_id: String,
+    hashed_validator: String,
+    expires_at: NaiveDateTime,
+}
+
+/// Who a reset mail goes to.
+#[derive(Debug, FromRow)]
+struct Recipient {
+    account_id: String,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+fn invalid_reset_token() -> AppError {
+    AppError::BadRequest("invalid or expired reset token".into())
+}
+
+/// Staff refresh tokens are revoked after a password change so that other
+/// signed-in devices have to log in again; 2FA recovery codes stay.
+pub async fn revoke_refresh_tokens(
+    conn: &mut MySqlConnection,
+    user_id: i64,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "DELETE FROM user_tokens WHERE user_id = ? AND selector NOT LIKE ? AND selector NOT LIKE ?",
+    )
+    .bind(user_id)
+    .bind(format!("{}%", RECOVERY_SELECTOR_PREFIX))
+    .bind(format!("{}%", TOTP_STEP_SELECTOR_PREFIX))
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn store_password(
+    conn: &mut MySqlConnection,
+    account_type: AccountType,
+    account_id: &str,
+    hash: &str,
+) -> Result<(), AppError> {
+    match account_type {
+        AccountType::Staff => {
+            let user_id: i64 = account_id.parse().map_err(|_| invalid_reset_token())?;
+            sqlx::query("UPDATE `user` SET passwd = ?, last_update = CURDATE() WHERE user_id = ?")
+                .bind(hash)
+                .bind(user_id)
+                .execute(&mut *conn)
+                .await?;
+            revoke_refresh_tokens(conn, user_id).await?;
+        }
+        AccountType::Member => {
+            sqlx::query(
+                "UPDATE member SET mpasswd = ?, last_update = CURDATE() WHERE member_id = ?",
+            )
+            .bind(hash)
+            .bind(account_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/password",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 204, description = "Password changed"),
+        (status = 400, description = "New password too short or unchanged"),
+        (status = 403, description = "Current password is wrong, or the caller is an API key"),
+        (status = 429, description = "Too many failed attempts; see the `Retry-After` header"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Auth"
+)]
+pub async fn change_password(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    auth: AuthUser,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    if auth.api_key_id.is_some() {
+        return Err(AppError::Forbidden("API keys have no password".into()));
+    }
+    if payload.new_password == payload.current_password {
+        return Err(AppError::BadRequest(
+            "new password must differ from the current one".into(),
+        ));
+    }
+    let hash = auth::hash_password(&payload.new_password)?;
+
+    let mut conn = state.pool.acquire().await?;
+    let (account_type, account_id, subject, valid) = match &auth.claims.member_id {
+        Some(member_id) => {
+            let stored: Option<Option<String>> =
+                sqlx::query_scalar("SELECT mpasswd FROM member WHERE member_id = ?")
+                    .bind(member_id)
+                    .fetch_optional(&mut *conn)
+                    .await?;
+            let stored =
+                stored.ok_or_else(|| AppError::Unauthorized("account no longer exists".into()))?;
+            let valid = auth::verify_secret(&payload.current_password, stored.as_deref(), false);
+            (
+                AccountType::Member,
+                member_id.clone(),
+                Subject::Member(member_id),
+                valid,
+            )
+        }
+        None => {
+            let stored: String = sqlx::query_scalar("SELECT passwd FROM `user` WHERE user_id = ?")
+                .bind(auth.claims.sub)
+                .fetch_optional(&mut *conn)
+                .await?
+                .ok_or_else(|| AppError::Unauthorized("account no longer exists".into()))?;
+            let valid = verify(&payload.current_password, &stored).unwrap_or(false);
+            (
+                AccountType::Staff,
+                auth.claims.sub.to_string(),
+                Subject::Staff(&auth.claims.username),
+                valid,
+            )
+        }
+    };
+
+    // Guessing the current password through this endpoint counts against
+    // the same limits as guessing it at login.
+    let attempt = login_guard::check(&state, subject, ip).await?;
+    if !valid {
+        login_guard::failed(&state, attempt, "invalid current password").await?;
+        return Err(AppError::Forbidden("current password is incorrect".into()));
+    }
+    drop(attempt);
+
+    store_password(&mut conn, account_type, &account_id, &hash).await?;
+    // A reset link requested earlier must not undo this change.
+    delete_reset_tokens(&mut conn, account_type, &account_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_reset_tokens(
+    conn: &mut MySqlConnection,
+    account_type: AccountType,
+    account_id: &str,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "DELETE FROM password_reset WHERE account_type = ? AND (account_id = ? OR expires_at < ?)",
+    )
+    .bind(account_type.as_str())
+    .bind(account_id)
+    .bind(Utc::now().naive_utc())
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Stores a reset token for the recipient and mails it in the background,
+/// so the response time does not reveal whether an account exists.
+async fn send_reset_token(
+    state: &AppState,
+    account_type: AccountType,
+    recipient: Recipient,
+) -> Result<(), AppError> {
+    let Some(email) = recipient.email.filter(|email| !email.trim().is_empty()) else {
+        tracing::info!(
+            "password reset for {} {} skipped: no email address",
+            account_type.as_str(),
+            recipient.account_id
+        );
+        return Ok(());
+    };
+
+    let now = Utc::now().naive_utc();
+    let mut conn = state.pool.acquire().await?;
+    let recent: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM password_reset WHERE account_type = ? AND account_id = ? AND created_at > ?",
+    )
+    .bind(account_type.as_str())
+    .bind(&recipient.account_id)
+    .bind(now - Duration::minutes(RESEND_AFTER_MINUTES))
+    .fetch_one(&mut *conn)
+    .await?;
+    if recent > 0 {
+        return Ok(());
+    }
+
+    let selector = random_hex(16);
+    let validator = random_hex(32);
+    sqlx::query(
+        "INSERT INTO password_reset (account_type, account_id, selector, hashed_validator, expires_at, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(account_type.as_str())
+    .bind(&recipient.account_id)
+    .bind(&selector)
+    .bind(sha256_hex(&validator))
+    .bind(now + Duration::minutes(RESET_TOKEN_TTL_MINUTES))
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+
+    let token = format!("{}.{}", selector, validator);
+    let link = match state.password_reset_url.as_deref() {
+        Some(url) if url.contains("{token}") => url.replace("{token}", &token),
+        Some(url) => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            format!("{}{}token={}", url, separator, token)
+        }
+        None => token.clone(),
+    };
+    let message = MailMessage {
+        to: email,
+        to_name: recipient.name.clone(),
+        subject: "Password reset".into(),
+        body: format!(
+            "Hello {},\n\n\
+             A password reset was requested for your library account. Use the \
+             following to choose a new password:\n\n{}\n\n\
+             It expires in {} minutes and works once. If you did not ask for \
+             this, ignore this message; your password stays unchanged.\n",
+            recipient.name.as_deref().unwrap_or(&recipient.account_id),
+            link,
+            RESET_TOKEN_TTL_MINUTES
+        ),
+    };
+
+    let mailer = state.mailer.clone();
+    tokio::spawn(async move {
+        if let Err(err) = mailer.send(&message).await {
+            tracing::error!("failed to send password reset mail: {:?}", err);
+        }
+    });
+
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/password/forgot",
+    request_body = ForgotPasswordRequest,
+    responses((status = 202, description = "A reset mail is sent if the account exists and has an email address")),
+    tag = "Auth"
+)]
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let recipient = sqlx::query_as::<_, Recipient>(
+        "SELECT CAST(user_id AS CHAR) AS account_id, realname AS name, email FROM `user` WHERE username = ?",
+    )
+    .bind(payload.username.trim())
+    .fetch_optional(&state.pool)
+    .await?;
+
+    if let Some(recipient) = recipient {
+        send_reset_token(&state, AccountType::Staff, recipient).await?;
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/member/password/forgot",
+    request_body = ForgotMemberPasswordRequest,
+    responses((status = 202, description = "A reset mail is sent if the member exists and has an email address")),
+    tag = "Auth"
+)]
+pub async fn forgot_member_password(
+    State(state): State<AppState>,
+    Json(payload): Json<ForgotMemberPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    // Pending members cannot log in, so they get no reset mail either.
+    let recipient = sqlx::query_as::<_, Recipient>(
+        "SELECT member_id AS account_id, member_name AS name, member_email AS email FROM member WHERE member_id = ? AND is_pending = 0",
+    )
+    .bind(payload.member_id.trim())
+    .fetch_optional(&state.pool)
+    .await?;
+
+    if let Some(recipient) = recipient {
+        send_reset_token(&state, AccountType::Member, recipient).await?;
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/password/reset",
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 204, description = "Password changed"),
+        (status = 400, description = "Invalid or expired token, or new password too short"),
+    ),
+    tag = "Auth"
+)]
+pub async fn reset_password(
+    State(state): State<AppState>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let (selector, validator) = payload
+        .token
+        .trim()
+        .split_once('.')
+        .ok_or_else(invalid_reset_token)?;
+    // Checked before the token is used up, so a too short password does
+    // not cost the user their link.
+    let hash = auth::hash_password(&payload.new_password)?;
+
+    let mut tx = state.pool.begin().await?;
+    let stored = sqlx::query_as::<_, StoredResetToken>(
+        "SELECT account_type, account_id, hashed_validator, expires_at FROM password_reset WHERE selector = ?",
+    )
+    .bind(selector)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(invalid_reset_token)?;
+
+    if !constant_time_eq(&stored.hashed_validator, &sha256_hex(validator))
+        || stored.expires_at < Utc::now().naive_utc()
+    {
+        return Err(invalid_reset_token());
+    }
+    let account_type = match stored.account_type.as_str() {
+        "staff" => AccountType::Staff,
+        "member" => AccountType::Member,
+        _ => return Err(invalid_reset_token()),
+    };
+
+    // Deleting the row claims the token; a concurrent reset with the same
+    // token finds nothing left to delete.
+    let claimed = sqlx::query("DELETE FROM password_reset WHERE selector = ?")
+        .bind(selector)
+        .execute(&mut *tx)
+        .await?;
+    if claimed.rows_affected() == 0 {
+        return Err(invalid_reset_token());
+    }
+
+    store_password(&mut tx, account_type, &stored.account_id, &hash).await?;
+    delete_reset_tokens(&mut tx, account_type, &stored.account_id).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,75 @@
+//! Short-lived cache of each token subject's current role and module
+//! access. Access tokens are re-checked against it on every request, so a
+//! group change or a removed account takes effect within `TTL` instead of
+//! when the token expires, without a database round trip per request.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::auth::{ModulePermission, Role};
+
+pub const TTL: Duration = Duration::from_secs(30);
+
+/// The role and module access a subject has right now.
+#[derive(Clone, Debug)]
+pub struct LiveAccess {
+    pub role: Role,
+    pub access: Vec<ModulePermission>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Subject {
+    Staff(i64),
+    Member(String),
+}
+
+struct Entry {
+    loaded_at: Instant,
+    /// `None` when the account no longer exists or may not log in.
+    access: Option<LiveAccess>,
+}
+
+#[derive(Default)]
+pub struct PermissionCache {
+    entries: Mutex<HashMap<Subject, Entry>>,
+}
+
+impl PermissionCache {
+    /// The cached lookup, if it is younger than `TTL`. The outer `None`
+    /// means the caller has to load it.
+    pub fn get(&self, subject: &Subject) -> Option<Option<LiveAccess>> {
+        let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries
+            .get(subject)
+            .filter(|entry| entry.loaded_at.elapsed() < TTL)
+            .map(|entry| entry.access.clone())
+    }
+
+    pub fn insert(&self, subject: Subject, access: Option<LiveAccess>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries.retain(|_, entry| entry.loaded_at.elapsed() < TTL);
+        entries.insert(
+            subject,
+            Entry {
+                loaded_at: Instant::now(),
+                access,
+            },
+        );
+    }
+
+    /// Drops one subject, after its account or group membership changed.
+    pub fn invalidate(&self, subject: &Subject) {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries.remove(subject);
+    }
+
+    /// Drops everything, after a change that can affect many subjects such
+    /// as a group's access matrix.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries.clear();
+    }
+}
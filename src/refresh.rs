@@ -0,0 +1,270 @@
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Deserialize;
+use sqlx::{FromRow, MySqlConnection};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, AuthUser},
+    config::AppState,
+    error::AppError,
+    jsonapi::{JsonApiDocument, resource, single_document},
+    tokens::{constant_time_eq, random_hex, sha256_hex},
+    two_factor::{RECOVERY_SELECTOR_PREFIX, TOTP_STEP_SELECTOR_PREFIX},
+};
+
+/// Lifetime of each refresh token. Every rotation issues a token with a
+/// fresh lifetime, so an active session stays signed in.
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+/// Prefix written in front of `hashed_validator` once a token has been
+/// exchanged. The row is kept until it expires so that a second use of the
+/// same token can be recognised as reuse.
+const ROTATED_PREFIX: &str = "rotated:";
+
+/// Refresh tokens are `{selector}.{validator}`. The selector is
+/// `{family}-{nonce}`, where the family is shared by every token rotated
+/// from the same login, so one `LIKE` on the selector finds the whole chain
+/// without changing the `user_tokens` schema.
+pub struct IssuedRefreshToken {
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, FromRow)]
+struct StoredToken {
+    id: i64,
+    hashed_validator: String,
+    user_id: i64,
+    expires_at: NaiveDateTime,
+}
+
+struct ParsedToken<'a> {
+    selector: &'a str,
+    validator: &'a str,
+    family: &'a str,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+fn invalid_token() -> AppError {
+    AppError::Unauthorized("invalid refresh token".into())
+}
+
+fn parse_token(token: &str) -> Result<ParsedToken<'_>, AppError> {
+    let (selector, validator) = token.split_once('.').ok_or_else(invalid_token)?;
+    let (family, nonce) = selector.split_once('-').ok_or_else(invalid_token)?;
+
+    let is_hex = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_hexdigit());
+    if !is_hex(family) || !is_hex(nonce) || !is_hex(validator) {
+        return Err(invalid_token());
+    }
+
+    Ok(ParsedToken {
+        selector,
+        validator,
+        family,
+    })
+}
+
+/// Stores a new refresh token for `user_id`. Passing `family` continues an
+/// existing chain on rotation; `None` starts a new one at login.
+pub async fn issue(
+    conn: &mut MySqlConnection,
+    user_id: i64,
+    family: Option<&str>,
+) -> Result<IssuedRefreshToken, AppError> {
+    let now = Utc::now().naive_utc();
+    let expires_at = now + Duration::days(REFRESH_TOKEN_TTL_DAYS);
+    let family = family.map(str::to_string).unwrap_or_else(|| random_hex(8));
+    let selector = format!("{}-{}", family, random_hex(8));
+    let validator = random_hex(32);
+
+    sqlx::query("DELETE FROM user_tokens WHERE user_id = ? AND expires_at < ?")
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO user_tokens (selector, hashed_validator, user_id, expires_at, created_at) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(&selector)
+    .bind(sha256_hex(&validator))
+    .bind(user_id)
+    .bind(expires_at)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(IssuedRefreshToken {
+        token: format!("{}.{}", selector, validator),
+        expires_at,
+    })
+}
+
+/// Exchanges a refresh token, returning its user and family. Presenting a
+/// token that was already exchanged revokes the whole family, since either
+/// the client or an attacker is holding a stolen copy.
+async fn rotate(conn: &mut MySqlConnection, token: &str) -> Result<(i64, String), AppError> {
+    let parsed = parse_token(token)?;
+    let stored = sqlx::query_as::<_, StoredToken>(
+        "SELECT id, hashed_validator, user_id, expires_at FROM user_tokens WHERE selector = ?",
+    )
+    .bind(parsed.selector)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(invalid_token)?;
+
+    let presented = sha256_hex(parsed.validator);
+    if let Some(previous) = stored.hashed_validator.strip_prefix(ROTATED_PREFIX) {
+        if constant_time_eq(previous, &presented) {
+            revoke_family(conn, parsed.family).await?;
+            tracing::warn!(
+                "refresh token reuse for user {}; revoked token family {}",
+                stored.user_id,
+                parsed.family
+            );
+            return Err(AppError::Unauthorized(
+                "refresh token was already used; please log in again".into(),
+            ));
+        }
+        return Err(invalid_token());
+    }
+    if !constant_time_eq(&stored.hashed_validator, &presented) {
+        return Err(invalid_token());
+    }
+
+    if stored.expires_at < Utc::now().naive_utc() {
+        sqlx::query("DELETE FROM user_tokens WHERE id = ?")
+            .bind(stored.id)
+            .execute(&mut *conn)
+            .await?;
+        return Err(AppError::Unauthorized("refresh token expired".into()));
+    }
+
+    // The hash condition makes the exchange single-use even when two
+    // requests race with the same token: the loser is treated as reuse.
+    let result = sqlx::query(
+        "UPDATE user_tokens SET hashed_validator = CONCAT(?, hashed_validator) WHERE id = ? AND hashed_validator = ?",
+    )
+    .bind(ROTATED_PREFIX)
+    .bind(stored.id)
+    .bind(&stored.hashed_validator)
+    .execute(&mut *conn)
+    .await?;
+    if result.rows_affected() == 0 {
+        revoke_family(conn, parsed.family).await?;
+        return Err(AppError::Unauthorized(
+            "refresh token was already used; please log in again".into(),
+        ));
+    }
+
+    Ok((stored.user_id, parsed.family.to_string()))
+}
+
+async fn revoke_family(conn: &mut MySqlConnection, family: &str) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM user_tokens WHERE selector LIKE ?")
+        .bind(format!("{}-%", family))
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New access and refresh tokens", body = JsonApiDocument),
+        (status = 401, description = "Invalid, expired or reused refresh token"),
+    ),
+    tag = "Auth"
+)]
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let mut conn = state.pool.acquire().await?;
+    let (user_id, family) = rotate(&mut conn, &payload.refresh_token).await?;
+
+    let Some((user, access)) = auth::fetch_staff_user(&state, user_id).await? else {
+        revoke_family(&mut conn, &family).await?;
+        return Err(invalid_token());
+    };
+
+    let issued = issue(&mut conn, user.user_id, Some(&family)).await?;
+    let response = auth::auth_response(&state, &user, access, issued)?;
+
+    let token_id = response.token.clone();
+    Ok(Json(single_document(resource(
+        "tokens", token_id, response,
+    ))))
+}
+
+/// Ends the session the refresh token belongs to. Unknown tokens are
+/// accepted so that logging out twice is harmless.
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    request_body = RefreshRequest,
+    responses((status = 204, description = "Session ended")),
+    tag = "Auth"
+)]
+pub async fn logout(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<StatusCode, AppError> {
+    let parsed = parse_token(&payload.refresh_token)?;
+    let mut conn = state.pool.acquire().await?;
+
+    let stored: Option<String> =
+        sqlx::query_scalar("SELECT hashed_validator FROM user_tokens WHERE selector = ?")
+            .bind(parsed.selector)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+    if let Some(hashed) = stored {
+        let hashed = hashed.strip_prefix(ROTATED_PREFIX).unwrap_or(&hashed);
+        if !constant_time_eq(hashed, &sha256_hex(parsed.validator)) {
+            return Err(invalid_token());
+        }
+        revoke_family(&mut conn, parsed.family).await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Revokes every refresh token of the current user, keeping 2FA recovery
+/// codes. Access tokens already issued stay valid until they expire.
+#[utoipa::path(
+    post,
+    path = "/auth/logout-all",
+    responses((status = 204, description = "All sessions ended")),
+    security(("bearerAuth" = [])),
+    tag = "Auth"
+)]
+pub async fn logout_all(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    // Member tokens have no refresh tokens behind them.
+    if auth.claims.member_id.is_some() {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    sqlx::query(
+        "DELETE FROM user_tokens WHERE user_id = ? AND selector NOT LIKE ? AND selector NOT LIKE ?",
+    )
+    .bind(auth.claims.sub)
+    .bind(format!("{}%", RECOVERY_SELECTOR_PREFIX))
+    .bind(format!("{}%", TOTP_STEP_SELECTOR_PREFIX))
+    .execute(&state.pool)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
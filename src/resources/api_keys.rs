@@ -0,0 +1,446 @@
+use std::net::IpAddr;
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, MySqlConnection};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{
+        AuthUser, Claims, ModuleAccess, ModulePermission, Permission, Role, ip_in_range,
+        parse_ip_range,
+    },
+    config::AppState,
+    error::AppError,
+    jsonapi::{
+        JsonApi, JsonApiDocument, ResourcePayload, collection_document, pagination_meta, resource,
+        resource_with_fields, single_document,
+    },
+    resources::{
+        FilterField, FilterOperator, FilterValueType, ListParams, SortField, bind_filters_to_query,
+        bind_filters_to_scalar, where_clause,
+    },
+    tokens::{constant_time_eq, random_hex, sha256_hex},
+};
+
+/// Keys look like `slk_{prefix}_{secret}`. The prefix is stored in clear to
+/// find the row and to tell keys apart in listings; only a hash of the
+/// whole key is stored.
+const KEY_SCHEME: &str = "slk";
+
+/// `last_used_at` is only rewritten when it is older than this, so busy
+/// integrations do not turn every request into a write.
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+#[derive(Debug, FromRow)]
+struct ApiKeyRow {
+    api_key_id: i64,
+    key_name: String,
+    key_prefix: String,
+    hashed_key: String,
+    permissions: String,
+    allowed_ips: Option<String>,
+    created_by: i64,
+    created_at: NaiveDateTime,
+    expires_at: Option<NaiveDateTime>,
+    last_used_at: Option<NaiveDateTime>,
+    last_used_ip: Option<String>,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiKey {
+    pub api_key_id: i64,
+    pub key_name: String,
+    /// Public part of the key, shown so admins can tell keys apart.
+    pub key_prefix: String,
+    pub permissions: Vec<ModulePermission>,
+    /// IP addresses or CIDR ranges; empty means any address.
+    pub allowed_ips: Vec<String>,
+    pub created_by: i64,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub last_used_ip: Option<String>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    /// The full key. It is only returned here and cannot be recovered later.
+    pub api_key: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateApiKey {
+    pub key_name: String,
+    pub permissions: Vec<ModulePermission>,
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl ResourcePayload for CreateApiKey {
+    const TYPE: &'static str = "api-keys";
+}
+
+const API_KEY_SELECT: &str = "SELECT api_key_id, key_name, key_prefix, hashed_key, permissions, allowed_ips, created_by, created_at, expires_at, last_used_at, last_used_ip, revoked_at FROM api_key";
+
+const API_KEY_SORTS: &[SortField<'_>] = &[
+    SortField::new("api_key_id", "api_key.api_key_id"),
+    SortField::new("key_name", "api_key.key_name"),
+    SortField::new("created_at", "api_key.created_at"),
+    SortField::new("last_used_at", "api_key.last_used_at"),
+];
+
+const API_KEY_FILTERS: &[FilterField<'_>] = &[FilterField::new(
+    "key_name",
+    "api_key.key_name",
+    FilterOperator::Like,
+    FilterValueType::Text,
+)];
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_api_keys).post(create_api_key))
+        .route("/:api_key_id", get(get_api_key).delete(revoke_api_key))
+}
+
+impl ApiKeyRow {
+    fn into_api_key(self) -> ApiKey {
+        ApiKey {
+            api_key_id: self.api_key_id,
+            key_name: self.key_name,
+            key_prefix: self.key_prefix,
+            permissions: serde_json::from_str(&self.permissions).unwrap_or_default(),
+            allowed_ips: split_allowed_ips(self.allowed_ips.as_deref()),
+            created_by: self.created_by,
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            last_used_at: self.last_used_at,
+            last_used_ip: self.last_used_ip,
+            revoked_at: self.revoked_at,
+        }
+    }
+}
+
+fn split_allowed_ips(raw: Option<&str>) -> Vec<String> {
+    raw.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn invalid_key() -> AppError {
+    AppError::Unauthorized("invalid API key".into())
+}
+
+/// Resolves an `X-API-Key` header into an [`AuthUser`] carrying the key's
+/// own permissions. Called by the `AuthUser` extractor.
+pub async fn authenticate(
+    state: &AppState,
+    key: &str,
+    ip: Option<IpAddr>,
+) -> Result<AuthUser, AppError> {
+    let prefix = key
+        .strip_prefix(KEY_SCHEME)
+        .and_then(|rest| rest.strip_prefix('_'))
+        .and_then(|rest| rest.split_once('_'))
+        .map(|(prefix, _)| prefix)
+        .ok_or_else(invalid_key)?;
+
+    let sql = format!("{} WHERE key_prefix = ?", API_KEY_SELECT);
+    let row = sqlx::query_as::<_, ApiKeyRow>(&sql)
+        .bind(prefix)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or_else(invalid_key)?;
+
+    if !constant_time_eq(&row.hashed_key, &sha256_hex(key)) {
+        return Err(invalid_key());
+    }
+    if row.revoked_at.is_some() {
+        return Err(AppError::Unauthorized("API key has been revoked".into()));
+    }
+    let now = Utc::now().naive_utc();
+    if row.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(AppError::Unauthorized("API key has expired".into()));
+    }
+
+    let allowlist = split_allowed_ips(row.allowed_ips.as_deref());
+    if !allowlist.is_empty() {
+        let allowed = ip.is_some_and(|ip| {
+            allowlist
+                .iter()
+                .filter_map(|entry| parse_ip_range(entry))
+                .any(|range| ip_in_range(ip, range))
+        });
+        if !allowed {
+            return Err(AppError::Forbidden(
+                "API key is not allowed from this address".into(),
+            ));
+        }
+    }
+
+    sqlx::query(
+        "UPDATE api_key SET last_used_at = ?, last_used_ip = ? WHERE api_key_id = ? AND (last_used_at IS NULL OR last_used_at < ?)",
+    )
+    .bind(now)
+    .bind(ip.map(|ip| ip.to_string()))
+    .bind(row.api_key_id)
+    .bind(now - Duration::seconds(LAST_USED_RESOLUTION_SECS))
+    .execute(&state.pool)
+    .await?;
+
+    let key = row.into_api_key();
+    let claims = Claims {
+        sub: 0,
+        username: format!("api-key:{}", key.key_name),
+        role: Role::Service,
+        access: key.permissions,
+        member_id: None,
+        exp: key
+            .expires_at
+            .map(|expires_at| expires_at.and_utc().timestamp() as usize)
+            .unwrap_or_default(),
+    };
+
+    Ok(AuthUser {
+        claims,
+        api_key_id: Some(key.api_key_id),
+    })
+}
+
+/// Key management stays with people: a key cannot mint or revoke keys,
+/// even one holding System write.
+fn require_key_admin(auth: &AuthUser, permission: Permission) -> Result<(), AppError> {
+    if auth.api_key_id.is_some() {
+        return Err(AppError::Forbidden(
+            "API keys cannot manage API keys".into(),
+        ));
+    }
+    auth.require_access(ModuleAccess::System, permission)
+}
+
+async fn fetch_api_key(conn: &mut MySqlConnection, api_key_id: i64) -> Result<ApiKey, AppError> {
+    let sql = format!("{} WHERE api_key_id = ?", API_KEY_SELECT);
+    let row = sqlx::query_as::<_, ApiKeyRow>(&sql)
+        .bind(api_key_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(row.into_api_key())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api-keys",
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "API Keys"
+)]
+async fn list_api_keys(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    require_key_admin(&auth, Permission::Read)?;
+
+    let pagination = params.pagination();
+    let key_fields = params.fieldset("api-keys");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause = params.sort_clause(API_KEY_SORTS, "api_key.api_key_id DESC")?;
+    let filters = params.filter_clauses(API_KEY_FILTERS)?;
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM api_key {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "{} {} ORDER BY {} LIMIT ? OFFSET ?",
+        API_KEY_SELECT, where_sql, sort_clause
+    );
+    let rows = bind_filters_to_query(sqlx::query_as::<_, ApiKeyRow>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = rows
+        .into_iter()
+        .map(|row| {
+            let key = row.into_api_key();
+            resource_with_fields("api-keys", key.api_key_id.to_string(), key, key_fields)
+        })
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api-keys/{api_key_id}",
+    params(("api_key_id" = i64, Path, description = "API key ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "API Keys"
+)]
+async fn get_api_key(
+    State(state): State<AppState>,
+    Path(api_key_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    require_key_admin(&auth, Permission::Read)?;
+
+    let mut conn = state.pool.acquire().await?;
+    let key = fetch_api_key(&mut conn, api_key_id).await?;
+
+    Ok(Json(single_document(resource(
+        "api-keys",
+        key.api_key_id.to_string(),
+        key,
+    ))))
+}
+
+/// Creates a key. Admins can only hand out permissions they hold
+/// themselves.
+#[utoipa::path(
+    post,
+    path = "/api-keys",
+    request_body(content = CreateApiKey, content_type = "application/vnd.api+json"),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 400, description = "Invalid permissions or IP allowlist"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "API Keys"
+)]
+async fn create_api_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<CreateApiKey>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    require_key_admin(&auth, Permission::Write)?;
+
+    let key_name = payload.key_name.trim();
+    if key_name.is_empty() {
+        return Err(AppError::BadRequest("key_name cannot be empty".into()));
+    }
+    if payload.permissions.is_empty() {
+        return Err(AppError::BadRequest("permissions cannot be empty".into()));
+    }
+    for permission in &payload.permissions {
+        let held = auth
+            .claims
+            .access
+            .iter()
+            .find(|access| access.module_id == permission.module_id);
+        let allowed = held.is_some_and(|held| {
+            (!permission.write || held.write) && (!permission.read || held.read || held.write)
+        });
+        if !allowed {
+            return Err(AppError::BadRequest(format!(
+                "cannot grant access to module {} beyond your own",
+                permission.module_id
+            )));
+        }
+    }
+    if let Some(invalid) = payload
+        .allowed_ips
+        .iter()
+        .find(|entry| parse_ip_range(entry).is_none())
+    {
+        return Err(AppError::BadRequest(format!(
+            "`{}` is not an IP address or CIDR range",
+            invalid
+        )));
+    }
+
+    let now = Utc::now().naive_utc();
+    let prefix = random_hex(6);
+    let api_key = format!("{}_{}_{}", KEY_SCHEME, prefix, random_hex(24));
+    let permissions = serde_json::to_string(&payload.permissions)
+        .map_err(|err| AppError::Internal(err.to_string()))?;
+    let allowed_ips = (!payload.allowed_ips.is_empty()).then(|| {
+        payload
+            .allowed_ips
+            .iter()
+            .map(|entry| entry.trim())
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+
+    let mut conn = state.pool.acquire().await?;
+    let result = sqlx::query(
+        "INSERT INTO api_key (key_name, key_prefix, hashed_key, permissions, allowed_ips, created_by, created_at, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(key_name)
+    .bind(&prefix)
+    .bind(sha256_hex(&api_key))
+    .bind(permissions)
+    .bind(allowed_ips)
+    .bind(auth.claims.sub)
+    .bind(now)
+    .bind(payload.expires_at)
+    .execute(&mut *conn)
+    .await?;
+
+    let key = fetch_api_key(&mut conn, result.last_insert_id() as i64).await?;
+
+    Ok(Json(single_document(resource(
+        "api-keys",
+        key.api_key_id.to_string(),
+        CreatedApiKey { key, api_key },
+    ))))
+}
+
+/// Revokes a key. The row is kept so its usage history stays visible.
+#[utoipa::path(
+    delete,
+    path = "/api-keys/{api_key_id}",
+    params(("api_key_id" = i64, Path, description = "API key ID")),
+    responses((status = 204, description = "API key revoked")),
+    security(("bearerAuth" = [])),
+    tag = "API Keys"
+)]
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path(api_key_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    require_key_admin(&auth, Permission::Write)?;
+
+    let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM api_key WHERE api_key_id = ?")
+        .bind(api_key_id)
+        .fetch_one(&state.pool)
+        .await?;
+    if exists == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    sqlx::query("UPDATE api_key SET revoked_at = ? WHERE api_key_id = ? AND revoked_at IS NULL")
+        .bind(Utc::now().naive_utc())
+        .bind(api_key_id)
+        .execute(&state.pool)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -6,23 +6,26 @@ use axum::{
 };
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use serde_json::Value as JsonValue;
+use serde_json::{Value as JsonValue, json};
 use sqlx::mysql::MySqlRow;
-use sqlx::{Column, FromRow, Row};
+use sqlx::{Column, FromRow, MySqlConnection, Row};
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use utoipa::ToSchema;
 
 use crate::{
-    auth::{AuthUser, ModuleAccess, Permission},
+    audit::{BiblioLogEntry, SystemLogEntry, write_biblio_log, write_system_log},
+    auth::{AuthUser, ClientIp, ModuleAccess, Permission},
     config::AppState,
     error::AppError,
     jsonapi::{
-        JsonApiDocument, collection_document, pagination_meta, resource, resource_with_fields,
-        single_document,
+        Included, JsonApi, JsonApiDocument, ResourceBuilder, ResourcePayload, collection_document,
+        identifier, pagination_meta, resource, single_document,
     },
     resources::{
-        bind_filters_to_query, bind_filters_to_scalar, where_clause, FilterField, FilterOperator,
-        FilterValueType, ListParams, SortField,
+        FilterField, FilterOperator, FilterValueType, ListParams, NULLABLE_MATCH_OPERATORS,
+        NULLABLE_RANGE_OPERATORS, PatchSet, SortField, bind_filters_to_query,
+        bind_filters_to_scalar, nullable, where_clause,
     },
 };
 
@@ -60,6 +63,65 @@ pub struct UpsertBiblio {
     pub promoted: Option<i16>,
 }
 
+impl ResourcePayload for UpsertBiblio {
+    const TYPE: &'static str = "biblios";
+}
+
+/// PATCH attributes: only those present are written, and `null` clears a
+/// nullable column.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateBiblio {
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>, nullable)]
+    pub gmd_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>, nullable)]
+    pub publisher_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub publish_year: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub language_id: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub classification: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub call_number: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i16>)]
+    pub opac_hide: Option<Option<i16>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i16>)]
+    pub promoted: Option<Option<i16>>,
+}
+
+impl ResourcePayload for UpdateBiblio {
+    const TYPE: &'static str = "biblios";
+}
+
+/// A PUT replaces the whole record: attributes left out are cleared, and
+/// the OPAC flags fall back to `0`.
+impl From<UpsertBiblio> for UpdateBiblio {
+    fn from(biblio: UpsertBiblio) -> Self {
+        UpdateBiblio {
+            title: Some(Some(biblio.title)),
+            gmd_id: Some(biblio.gmd_id),
+            publisher_id: Some(biblio.publisher_id),
+            publish_year: Some(biblio.publish_year),
+            language_id: Some(biblio.language_id),
+            classification: Some(biblio.classification),
+            call_number: Some(biblio.call_number),
+            opac_hide: Some(Some(biblio.opac_hide.unwrap_or(0))),
+            promoted: Some(Some(biblio.promoted.unwrap_or(0))),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow, ToSchema)]
 pub struct GmdInfo {
     pub gmd_id: i64,
@@ -157,41 +219,54 @@ pub struct TopicInfo {
     pub topic_type: String,
 }
 
+/// Attributes of a `biblios` resource. Related records are relationships;
+/// `custom` is part of the record itself.
 #[derive(Debug, Serialize, ToSchema)]
 pub struct BiblioResponse {
     #[serde(flatten)]
     pub biblio: Biblio,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub gmd: Option<GmdInfo>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub publisher: Option<PublisherInfo>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub language: Option<LanguageInfo>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub content_type: Option<ContentTypeInfo>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub media_type: Option<MediaTypeInfo>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub carrier_type: Option<CarrierTypeInfo>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub frequency: Option<FrequencyInfo>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub place: Option<PlaceInfo>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub authors: Option<Vec<AuthorInfo>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub topics: Option<Vec<TopicInfo>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub items: Option<Vec<ItemSummary>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub relations: Option<Vec<BiblioRelationInfo>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub attachments: Option<Vec<AttachmentInfo>>,
     #[schema(value_type = Object)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub custom: Option<JsonValue>,
 }
 
+/// Records loaded for `include`, each turned into a relationship and an
+/// entry of `included`.
+#[derive(Default)]
+struct BiblioRelated {
+    gmd: Option<GmdInfo>,
+    publisher: Option<PublisherInfo>,
+    language: Option<LanguageInfo>,
+    content_type: Option<ContentTypeInfo>,
+    media_type: Option<MediaTypeInfo>,
+    carrier_type: Option<CarrierTypeInfo>,
+    frequency: Option<FrequencyInfo>,
+    place: Option<PlaceInfo>,
+    authors: Option<Vec<AuthorInfo>>,
+    topics: Option<Vec<TopicInfo>>,
+    items: Option<Vec<ItemSummary>>,
+    relations: Option<Vec<BiblioRelationInfo>>,
+    attachments: Option<Vec<AttachmentInfo>>,
+}
+
+const BIBLIO_INCLUDES: &[&str] = &[
+    "gmd",
+    "publisher",
+    "language",
+    "content_type",
+    "media_type",
+    "carrier_type",
+    "frequency",
+    "place",
+    "authors",
+    "topics",
+    "items",
+    "relations",
+    "attachments",
+    "files",
+    "custom",
+];
+
 const BIBLIO_SORTS: &[SortField<'_>] = &[
     SortField::new("biblio_id", "biblio.biblio_id"),
     SortField::new("title", "biblio.title"),
@@ -211,12 +286,59 @@ const BIBLIO_FILTERS: &[FilterField<'_>] = &[
         "biblio.gmd_id",
         FilterOperator::Equals,
         FilterValueType::Integer,
-    ),
+    )
+    .with_operators(NULLABLE_MATCH_OPERATORS),
     FilterField::new(
         "language_id",
         "biblio.language_id",
         FilterOperator::Equals,
         FilterValueType::Text,
+    )
+    .with_operators(NULLABLE_MATCH_OPERATORS),
+    FilterField::new(
+        "publisher_id",
+        "biblio.publisher_id",
+        FilterOperator::Equals,
+        FilterValueType::Integer,
+    )
+    .with_operators(NULLABLE_MATCH_OPERATORS),
+    FilterField::new(
+        "publish_year",
+        "biblio.publish_year",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    )
+    .with_operators(&[
+        FilterOperator::GreaterOrEqual,
+        FilterOperator::LessOrEqual,
+        FilterOperator::In,
+        FilterOperator::Between,
+    ]),
+    FilterField::new(
+        "input_date",
+        "biblio.input_date",
+        FilterOperator::Equals,
+        FilterValueType::DateTime,
+    )
+    .with_operators(NULLABLE_RANGE_OPERATORS),
+    FilterField::new(
+        "last_update",
+        "biblio.last_update",
+        FilterOperator::Equals,
+        FilterValueType::DateTime,
+    )
+    .with_operators(NULLABLE_RANGE_OPERATORS),
+    FilterField::new(
+        "opac_hide",
+        "biblio.opac_hide",
+        FilterOperator::Equals,
+        FilterValueType::Boolean,
+    ),
+    FilterField::new(
+        "promoted",
+        "biblio.promoted",
+        FilterOperator::Equals,
+        FilterValueType::Boolean,
     ),
 ];
 
@@ -227,7 +349,10 @@ pub fn router() -> Router<AppState> {
         .route("/search/advanced", post(advanced_search_biblios))
         .route(
             "/:biblio_id",
-            get(get_biblio).put(update_biblio).delete(delete_biblio),
+            get(get_biblio)
+                .put(replace_biblio)
+                .patch(update_biblio)
+                .delete(delete_biblio),
         )
 }
 
@@ -240,17 +365,13 @@ pub struct SimpleSearchParams {
 
 #[derive(Debug, Deserialize, Clone, Copy, ToSchema)]
 #[serde(rename_all = "lowercase")]
+#[derive(Default)]
 pub enum BooleanOp {
+    #[default]
     And,
     Or,
 }
 
-impl Default for BooleanOp {
-    fn default() -> Self {
-        BooleanOp::And
-    }
-}
-
 impl BooleanOp {
     fn as_sql(&self) -> &'static str {
         match self {
@@ -262,19 +383,15 @@ impl BooleanOp {
 
 #[derive(Debug, Deserialize, Clone, Copy, ToSchema)]
 #[serde(rename_all = "snake_case")]
+#[derive(Default)]
 pub enum MatchType {
+    #[default]
     Contains,
     Exact,
     StartsWith,
     EndsWith,
 }
 
-impl Default for MatchType {
-    fn default() -> Self {
-        MatchType::Contains
-    }
-}
-
 #[derive(Debug, Deserialize, Clone, Copy, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum SearchField {
@@ -308,7 +425,7 @@ async fn enrich_biblios(
     state: &AppState,
     includes: &HashSet<String>,
     rows: Vec<Biblio>,
-) -> Result<Vec<BiblioResponse>, AppError> {
+) -> Result<Vec<(BiblioResponse, BiblioRelated)>, AppError> {
     let mut gmd_cache: HashMap<i32, GmdInfo> = HashMap::new();
     let mut publisher_cache: HashMap<i32, PublisherInfo> = HashMap::new();
     let mut language_cache: HashMap<String, LanguageInfo> = HashMap::new();
@@ -321,170 +438,161 @@ async fn enrich_biblios(
 
     for biblio in rows {
         let custom = if includes.contains("custom") {
-            if let Some(row) = sqlx::query("SELECT * FROM biblio_custom WHERE biblio_id = ?")
+            sqlx::query("SELECT * FROM biblio_custom WHERE biblio_id = ?")
                 .bind(biblio.biblio_id)
                 .fetch_optional(&state.pool)
                 .await?
-            {
-                Some(row_to_json(&row))
-            } else {
-                None
-            }
+                .map(|row| row_to_json(&row))
         } else {
             None
         };
 
         let mut gmd = None;
-        if includes.contains("gmd") {
-            if let Some(gmd_id) = biblio.gmd_id {
-                if let Some(existing) = gmd_cache.get(&gmd_id) {
-                    gmd = Some(existing.clone());
-                } else if let Some(row) = sqlx::query_as::<_, GmdInfo>(
-                    "SELECT gmd_id, gmd_name FROM mst_gmd WHERE gmd_id = ?",
-                )
-                .bind(gmd_id)
-                .fetch_optional(&state.pool)
-                .await?
-                {
-                    gmd_cache.insert(gmd_id, row.clone());
-                    gmd = Some(row);
-                }
+        if includes.contains("gmd")
+            && let Some(gmd_id) = biblio.gmd_id
+        {
+            if let Some(existing) = gmd_cache.get(&gmd_id) {
+                gmd = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, GmdInfo>(
+                "SELECT gmd_id, gmd_name FROM mst_gmd WHERE gmd_id = ?",
+            )
+            .bind(gmd_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                gmd_cache.insert(gmd_id, row.clone());
+                gmd = Some(row);
             }
         }
 
         let mut publisher = None;
-        if includes.contains("publisher") {
-            if let Some(pub_id) = biblio.publisher_id {
-                if let Some(existing) = publisher_cache.get(&pub_id) {
-                    publisher = Some(existing.clone());
-                } else if let Some(row) = sqlx::query_as::<_, PublisherInfo>(
-                    "SELECT publisher_id, publisher_name FROM mst_publisher WHERE publisher_id = ?",
-                )
-                .bind(pub_id)
-                .fetch_optional(&state.pool)
-                .await?
-                {
-                    publisher_cache.insert(pub_id, row.clone());
-                    publisher = Some(row);
-                }
+        if includes.contains("publisher")
+            && let Some(pub_id) = biblio.publisher_id
+        {
+            if let Some(existing) = publisher_cache.get(&pub_id) {
+                publisher = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, PublisherInfo>(
+                "SELECT publisher_id, publisher_name FROM mst_publisher WHERE publisher_id = ?",
+            )
+            .bind(pub_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                publisher_cache.insert(pub_id, row.clone());
+                publisher = Some(row);
             }
         }
 
         let mut language = None;
-        if includes.contains("language") {
-            if let Some(lang_id) = biblio.language_id.clone() {
-                if let Some(existing) = language_cache.get(&lang_id) {
-                    language = Some(existing.clone());
-                } else if let Some(row) = sqlx::query_as::<_, LanguageInfo>(
-                    "SELECT language_id, language_name FROM mst_language WHERE language_id = ?",
-                )
-                .bind(&lang_id)
-                .fetch_optional(&state.pool)
-                .await?
-                {
-                    language_cache.insert(lang_id.clone(), row.clone());
-                    language = Some(row);
-                }
+        if includes.contains("language")
+            && let Some(lang_id) = biblio.language_id.clone()
+        {
+            if let Some(existing) = language_cache.get(&lang_id) {
+                language = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, LanguageInfo>(
+                "SELECT language_id, language_name FROM mst_language WHERE language_id = ?",
+            )
+            .bind(&lang_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                language_cache.insert(lang_id.clone(), row.clone());
+                language = Some(row);
             }
         }
 
         let mut content_type = None;
-        if includes.contains("content_type") {
-            if let Some(ct_id) = biblio.content_type_id {
-                if ct_id > 0 {
-                    if let Some(existing) = content_type_cache.get(&ct_id) {
-                        content_type = Some(existing.clone());
-                    } else if let Some(row) = sqlx::query_as::<_, ContentTypeInfo>(
-                        "SELECT id, content_type, code FROM mst_content_type WHERE id = ?",
-                    )
-                    .bind(ct_id)
-                    .fetch_optional(&state.pool)
-                    .await?
-                    {
-                        content_type_cache.insert(ct_id, row.clone());
-                        content_type = Some(row);
-                    }
-                }
+        if includes.contains("content_type")
+            && let Some(ct_id) = biblio.content_type_id
+            && ct_id > 0
+        {
+            if let Some(existing) = content_type_cache.get(&ct_id) {
+                content_type = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, ContentTypeInfo>(
+                "SELECT id, content_type, code FROM mst_content_type WHERE id = ?",
+            )
+            .bind(ct_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                content_type_cache.insert(ct_id, row.clone());
+                content_type = Some(row);
             }
         }
 
         let mut media_type = None;
-        if includes.contains("media_type") {
-            if let Some(mt_id) = biblio.media_type_id {
-                if mt_id > 0 {
-                    if let Some(existing) = media_type_cache.get(&mt_id) {
-                        media_type = Some(existing.clone());
-                    } else if let Some(row) = sqlx::query_as::<_, MediaTypeInfo>(
-                        "SELECT id, media_type, code FROM mst_media_type WHERE id = ?",
-                    )
-                    .bind(mt_id)
-                    .fetch_optional(&state.pool)
-                    .await?
-                    {
-                        media_type_cache.insert(mt_id, row.clone());
-                        media_type = Some(row);
-                    }
-                }
+        if includes.contains("media_type")
+            && let Some(mt_id) = biblio.media_type_id
+            && mt_id > 0
+        {
+            if let Some(existing) = media_type_cache.get(&mt_id) {
+                media_type = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, MediaTypeInfo>(
+                "SELECT id, media_type, code FROM mst_media_type WHERE id = ?",
+            )
+            .bind(mt_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                media_type_cache.insert(mt_id, row.clone());
+                media_type = Some(row);
             }
         }
 
         let mut carrier_type = None;
-        if includes.contains("carrier_type") {
-            if let Some(ct_id) = biblio.carrier_type_id {
-                if ct_id > 0 {
-                    if let Some(existing) = carrier_type_cache.get(&ct_id) {
-                        carrier_type = Some(existing.clone());
-                    } else if let Some(row) = sqlx::query_as::<_, CarrierTypeInfo>(
-                        "SELECT id, carrier_type, code FROM mst_carrier_type WHERE id = ?",
-                    )
-                    .bind(ct_id)
-                    .fetch_optional(&state.pool)
-                    .await?
-                    {
-                        carrier_type_cache.insert(ct_id, row.clone());
-                        carrier_type = Some(row);
-                    }
-                }
+        if includes.contains("carrier_type")
+            && let Some(ct_id) = biblio.carrier_type_id
+            && ct_id > 0
+        {
+            if let Some(existing) = carrier_type_cache.get(&ct_id) {
+                carrier_type = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, CarrierTypeInfo>(
+                "SELECT id, carrier_type, code FROM mst_carrier_type WHERE id = ?",
+            )
+            .bind(ct_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                carrier_type_cache.insert(ct_id, row.clone());
+                carrier_type = Some(row);
             }
         }
 
         let mut frequency = None;
-        if includes.contains("frequency") {
-            if let Some(freq_id) = biblio.frequency_id {
-                if freq_id > 0 {
-                    if let Some(existing) = frequency_cache.get(&freq_id) {
-                        frequency = Some(existing.clone());
-                    } else if let Some(row) = sqlx::query_as::<_, FrequencyInfo>(
-                        "SELECT frequency_id, frequency, language_prefix FROM mst_frequency WHERE frequency_id = ?",
-                    )
-                    .bind(freq_id)
-                    .fetch_optional(&state.pool)
-                    .await?
-                    {
-                        frequency_cache.insert(freq_id, row.clone());
-                        frequency = Some(row);
-                    }
-                }
+        if includes.contains("frequency")
+            && let Some(freq_id) = biblio.frequency_id
+            && freq_id > 0
+        {
+            if let Some(existing) = frequency_cache.get(&freq_id) {
+                frequency = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, FrequencyInfo>(
+                "SELECT frequency_id, frequency, language_prefix FROM mst_frequency WHERE frequency_id = ?",
+            )
+            .bind(freq_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                frequency_cache.insert(freq_id, row.clone());
+                frequency = Some(row);
             }
         }
 
         let mut place = None;
-        if includes.contains("place") {
-            if let Some(place_id) = biblio.publish_place_id {
-                if place_id > 0 {
-                    if let Some(existing) = place_cache.get(&place_id) {
-                        place = Some(existing.clone());
-                    } else if let Some(row) = sqlx::query_as::<_, PlaceInfo>(
-                        "SELECT place_id, place_name FROM mst_place WHERE place_id = ?",
-                    )
-                    .bind(place_id)
-                    .fetch_optional(&state.pool)
-                    .await?
-                    {
-                        place_cache.insert(place_id, row.clone());
-                        place = Some(row);
-                    }
-                }
+        if includes.contains("place")
+            && let Some(place_id) = biblio.publish_place_id
+            && place_id > 0
+        {
+            if let Some(existing) = place_cache.get(&place_id) {
+                place = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, PlaceInfo>(
+                "SELECT place_id, place_name FROM mst_place WHERE place_id = ?",
+            )
+            .bind(place_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                place_cache.insert(place_id, row.clone());
+                place = Some(row);
             }
         }
 
@@ -548,28 +656,216 @@ async fn enrich_biblios(
             None
         };
 
-        data.push(BiblioResponse {
-            biblio,
-            gmd,
-            publisher,
-            language,
-            content_type,
-            media_type,
-            carrier_type,
-            frequency,
-            place,
-            authors,
-            topics,
-            items,
-            relations,
-            attachments,
-            custom,
-        });
+        data.push((
+            BiblioResponse { biblio, custom },
+            BiblioRelated {
+                gmd,
+                publisher,
+                language,
+                content_type,
+                media_type,
+                carrier_type,
+                frequency,
+                place,
+                authors,
+                topics,
+                items,
+                relations,
+                attachments,
+            },
+        ));
     }
 
     Ok(data)
 }
 
+/// Builds the `biblios` resource object. To-one relationships carry linkage
+/// from the foreign key even when they are not included; to-many ones only
+/// appear when included.
+fn biblio_resource(
+    response: BiblioResponse,
+    related: BiblioRelated,
+    params: &ListParams,
+    included: &mut Included,
+) -> JsonValue {
+    let biblio = &response.biblio;
+    let foreign_key = |id: Option<i32>| id.filter(|id| *id > 0).map(|id| id.to_string());
+
+    let gmd = match related.gmd {
+        Some(gmd) => {
+            Some(included.resource("gmd", gmd.gmd_id.to_string(), &gmd, params.fieldset("gmd")))
+        }
+        None => foreign_key(biblio.gmd_id).map(|id| identifier("gmd", id)),
+    };
+    let publisher = match related.publisher {
+        Some(publisher) => Some(included.resource(
+            "publishers",
+            publisher.publisher_id.to_string(),
+            &publisher,
+            params.fieldset("publishers"),
+        )),
+        None => foreign_key(biblio.publisher_id).map(|id| identifier("publishers", id)),
+    };
+    let language = match related.language {
+        Some(language) => Some(included.resource(
+            "languages",
+            language.language_id.clone(),
+            &language,
+            params.fieldset("languages"),
+        )),
+        None => biblio
+            .language_id
+            .as_deref()
+            .filter(|id| !id.is_empty())
+            .map(|id| identifier("languages", id)),
+    };
+    let content_type = match related.content_type {
+        Some(content_type) => Some(included.resource(
+            "content-types",
+            content_type.id.to_string(),
+            &content_type,
+            params.fieldset("content-types"),
+        )),
+        None => foreign_key(biblio.content_type_id).map(|id| identifier("content-types", id)),
+    };
+    let media_type = match related.media_type {
+        Some(media_type) => Some(included.resource(
+            "media-types",
+            media_type.id.to_string(),
+            &media_type,
+            params.fieldset("media-types"),
+        )),
+        None => foreign_key(biblio.media_type_id).map(|id| identifier("media-types", id)),
+    };
+    let carrier_type = match related.carrier_type {
+        Some(carrier_type) => Some(included.resource(
+            "carrier-types",
+            carrier_type.id.to_string(),
+            &carrier_type,
+            params.fieldset("carrier-types"),
+        )),
+        None => foreign_key(biblio.carrier_type_id).map(|id| identifier("carrier-types", id)),
+    };
+    let frequency = match related.frequency {
+        Some(frequency) => Some(included.resource(
+            "frequencies",
+            frequency.frequency_id.to_string(),
+            &frequency,
+            params.fieldset("frequencies"),
+        )),
+        None => foreign_key(biblio.frequency_id).map(|id| identifier("frequencies", id)),
+    };
+    let place = match related.place {
+        Some(place) => Some(included.resource(
+            "places",
+            place.place_id.to_string(),
+            &place,
+            params.fieldset("places"),
+        )),
+        None => foreign_key(biblio.publish_place_id).map(|id| identifier("places", id)),
+    };
+
+    let id = biblio.biblio_id.to_string();
+    let mut builder = ResourceBuilder::new("biblios", id, response, params.fieldset("biblios"))
+        .has_one("gmd", gmd)
+        .has_one("publisher", publisher)
+        .has_one("language", language)
+        .has_one("content_type", content_type)
+        .has_one("media_type", media_type)
+        .has_one("carrier_type", carrier_type)
+        .has_one("frequency", frequency)
+        .has_one("place", place);
+
+    if let Some(authors) = related.authors {
+        let linkage = authors
+            .into_iter()
+            .map(|author| {
+                included.resource(
+                    "authors",
+                    author.author_id.to_string(),
+                    &author,
+                    params.fieldset("authors"),
+                )
+            })
+            .collect();
+        builder = builder.has_many("authors", linkage);
+    }
+    if let Some(topics) = related.topics {
+        let linkage = topics
+            .into_iter()
+            .map(|topic| {
+                included.resource(
+                    "topics",
+                    topic.topic_id.to_string(),
+                    &topic,
+                    params.fieldset("topics"),
+                )
+            })
+            .collect();
+        builder = builder.has_many("topics", linkage);
+    }
+    if let Some(items) = related.items {
+        let linkage = items
+            .into_iter()
+            .map(|item| {
+                included.resource(
+                    "items",
+                    item.item_id.to_string(),
+                    &item,
+                    params.fieldset("items"),
+                )
+            })
+            .collect();
+        builder = builder.has_many("items", linkage);
+    }
+    // The relation type and the attachment settings describe the link rather
+    // than the linked record, so they go into the identifier's `meta`.
+    if let Some(relations) = related.relations {
+        let linkage = relations
+            .into_iter()
+            .map(|relation| {
+                let mut linkage = included.resource(
+                    "biblios",
+                    relation.biblio_id.to_string(),
+                    json!({ "title": relation.title }),
+                    params.fieldset("biblios"),
+                );
+                linkage["meta"] = json!({ "rel_type": relation.rel_type });
+                linkage
+            })
+            .collect();
+        builder = builder.has_many("relations", linkage);
+    }
+    if let Some(attachments) = related.attachments {
+        let linkage = attachments
+            .into_iter()
+            .map(|attachment| {
+                let mut linkage = included.resource(
+                    "files",
+                    attachment.file_id.to_string(),
+                    json!({
+                        "file_title": attachment.file_title,
+                        "file_name": attachment.file_name,
+                        "file_url": attachment.file_url,
+                        "file_dir": attachment.file_dir,
+                        "mime_type": attachment.mime_type,
+                    }),
+                    params.fieldset("files"),
+                );
+                linkage["meta"] = json!({
+                    "placement": attachment.placement,
+                    "access_type": attachment.access_type,
+                    "access_limit": attachment.access_limit,
+                });
+                linkage
+            })
+            .collect();
+        builder = builder.has_many("attachments", linkage);
+    }
+
+    builder.build()
+}
+
 #[utoipa::path(
     get,
     path = "/biblios",
@@ -585,8 +881,7 @@ async fn list_biblios(
     auth.require_access(ModuleAccess::Bibliography, Permission::Read)?;
 
     let pagination = params.pagination();
-    let includes = params.includes();
-    let biblio_fields = params.fieldset("biblios");
+    let includes = params.checked_includes(BIBLIO_INCLUDES)?;
     let (limit, offset, page, per_page) = pagination.limit_offset();
     let sort_clause = params.sort_clause(BIBLIO_SORTS, "biblio.biblio_id DESC")?;
     let filters = params.filter_clauses(BIBLIO_FILTERS)?;
@@ -608,22 +903,16 @@ async fn list_biblios(
         .await?;
 
     let data = enrich_biblios(&state, &includes, rows).await?;
+    let mut included = Included::default();
     let documents = data
         .into_iter()
-        .map(|biblio| {
-            resource_with_fields(
-                "biblios",
-                biblio.biblio.biblio_id.to_string(),
-                biblio,
-                biblio_fields,
-            )
-        })
+        .map(|(biblio, related)| biblio_resource(biblio, related, &params, &mut included))
         .collect();
 
-    Ok(Json(collection_document(
-        documents,
-        pagination_meta(page, per_page, total),
-    )))
+    Ok(Json(
+        collection_document(documents, pagination_meta(page, per_page, total))
+            .with_included(included),
+    ))
 }
 
 #[utoipa::path(
@@ -647,8 +936,7 @@ async fn simple_search_biblios(
     }
 
     let pagination = params.list.pagination();
-    let includes = params.list.includes();
-    let biblio_fields = params.list.fieldset("biblios");
+    let includes = params.list.checked_includes(BIBLIO_INCLUDES)?;
     let (limit, offset, page, per_page) = pagination.limit_offset();
     let pattern = format!("%{}%", keyword);
 
@@ -699,22 +987,16 @@ async fn simple_search_biblios(
         .await?;
 
     let data = enrich_biblios(&state, &includes, rows).await?;
+    let mut included = Included::default();
     let documents = data
         .into_iter()
-        .map(|biblio| {
-            resource_with_fields(
-                "biblios",
-                biblio.biblio.biblio_id.to_string(),
-                biblio,
-                biblio_fields,
-            )
-        })
+        .map(|(biblio, related)| biblio_resource(biblio, related, &params.list, &mut included))
         .collect();
 
-    Ok(Json(collection_document(
-        documents,
-        pagination_meta(page, per_page, total),
-    )))
+    Ok(Json(
+        collection_document(documents, pagination_meta(page, per_page, total))
+            .with_included(included),
+    ))
 }
 
 fn match_pattern(value: &str, matcher: MatchType) -> String {
@@ -752,8 +1034,7 @@ async fn advanced_search_biblios(
     }
 
     let pagination = payload.list.pagination();
-    let includes = payload.list.includes();
-    let biblio_fields = payload.list.fieldset("biblios");
+    let includes = payload.list.checked_includes(BIBLIO_INCLUDES)?;
     let (limit, offset, page, per_page) = pagination.limit_offset();
 
     let mut joins = String::new();
@@ -840,22 +1121,16 @@ async fn advanced_search_biblios(
         .await?;
 
     let data = enrich_biblios(&state, &includes, rows).await?;
+    let mut included = Included::default();
     let documents = data
         .into_iter()
-        .map(|biblio| {
-            resource_with_fields(
-                "biblios",
-                biblio.biblio.biblio_id.to_string(),
-                biblio,
-                biblio_fields,
-            )
-        })
+        .map(|(biblio, related)| biblio_resource(biblio, related, &payload.list, &mut included))
         .collect();
 
-    Ok(Json(collection_document(
-        documents,
-        pagination_meta(page, per_page, total),
-    )))
+    Ok(Json(
+        collection_document(documents, pagination_meta(page, per_page, total))
+            .with_included(included),
+    ))
 }
 
 #[utoipa::path(
@@ -881,226 +1156,15 @@ async fn get_biblio(
     .fetch_one(&state.pool)
     .await?;
 
-    let includes = params.includes();
-    let biblio_fields = params.fieldset("biblios");
-
-    let gmd = if includes.contains("gmd") {
-        if let Some(gmd_id) = row.gmd_id {
-            sqlx::query_as::<_, GmdInfo>("SELECT gmd_id, gmd_name FROM mst_gmd WHERE gmd_id = ?")
-                .bind(gmd_id)
-                .fetch_optional(&state.pool)
-                .await?
-        } else {
-            None
-        }
-    } else {
-        None
-    };
+    let includes = params.checked_includes(BIBLIO_INCLUDES)?;
+    let (biblio, related) = enrich_biblios(&state, &includes, vec![row])
+        .await?
+        .pop()
+        .expect("one biblio in, one out");
+    let mut included = Included::default();
+    let resource = biblio_resource(biblio, related, &params, &mut included);
 
-    let publisher = if includes.contains("publisher") {
-        if let Some(pub_id) = row.publisher_id {
-            sqlx::query_as::<_, PublisherInfo>(
-                "SELECT publisher_id, publisher_name FROM mst_publisher WHERE publisher_id = ?",
-            )
-            .bind(pub_id)
-            .fetch_optional(&state.pool)
-            .await?
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let language = if includes.contains("language") {
-        if let Some(lang_id) = row.language_id.clone() {
-            sqlx::query_as::<_, LanguageInfo>(
-                "SELECT language_id, language_name FROM mst_language WHERE language_id = ?",
-            )
-            .bind(&lang_id)
-            .fetch_optional(&state.pool)
-            .await?
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let content_type = if includes.contains("content_type") {
-        if let Some(ct_id) = row.content_type_id {
-            sqlx::query_as::<_, ContentTypeInfo>(
-                "SELECT id, content_type, code FROM mst_content_type WHERE id = ?",
-            )
-            .bind(ct_id)
-            .fetch_optional(&state.pool)
-            .await?
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let media_type = if includes.contains("media_type") {
-        if let Some(mt_id) = row.media_type_id {
-            sqlx::query_as::<_, MediaTypeInfo>(
-                "SELECT id, media_type, code FROM mst_media_type WHERE id = ?",
-            )
-            .bind(mt_id)
-            .fetch_optional(&state.pool)
-            .await?
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let carrier_type = if includes.contains("carrier_type") {
-        if let Some(ct_id) = row.carrier_type_id {
-            sqlx::query_as::<_, CarrierTypeInfo>(
-                "SELECT id, carrier_type, code FROM mst_carrier_type WHERE id = ?",
-            )
-            .bind(ct_id)
-            .fetch_optional(&state.pool)
-            .await?
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let frequency = if includes.contains("frequency") {
-        if let Some(freq_id) = row.frequency_id {
-            sqlx::query_as::<_, FrequencyInfo>(
-                "SELECT frequency_id, frequency, language_prefix FROM mst_frequency WHERE frequency_id = ?",
-            )
-            .bind(freq_id)
-            .fetch_optional(&state.pool)
-            .await?
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let place = if includes.contains("place") {
-        if let Some(place_id) = row.publish_place_id {
-            sqlx::query_as::<_, PlaceInfo>(
-                "SELECT place_id, place_name FROM mst_place WHERE place_id = ?",
-            )
-            .bind(place_id)
-            .fetch_optional(&state.pool)
-            .await?
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let authors = if includes.contains("authors") {
-        let rows = sqlx::query_as::<_, AuthorInfo>(
-            "SELECT a.author_id, a.author_name, a.authority_type FROM biblio_author ba JOIN mst_author a ON ba.author_id = a.author_id WHERE ba.biblio_id = ?",
-        )
-        .bind(row.biblio_id)
-        .fetch_all(&state.pool)
-        .await?;
-        Some(rows)
-    } else {
-        None
-    };
-
-    let topics = if includes.contains("topics") {
-        let rows = sqlx::query_as::<_, TopicInfo>(
-            "SELECT t.topic_id, t.topic, t.topic_type FROM biblio_topic bt JOIN mst_topic t ON bt.topic_id = t.topic_id WHERE bt.biblio_id = ?",
-        )
-        .bind(row.biblio_id)
-        .fetch_all(&state.pool)
-        .await?;
-        Some(rows)
-    } else {
-        None
-    };
-
-    let items = if includes.contains("items") {
-        let rows = sqlx::query_as::<_, ItemSummary>(
-            "SELECT item_id, item_code, call_number, coll_type_id, location_id, item_status_id, last_update FROM item WHERE biblio_id = ? ORDER BY item_id DESC",
-        )
-        .bind(row.biblio_id)
-        .fetch_all(&state.pool)
-        .await?;
-        Some(rows)
-    } else {
-        None
-    };
-
-    let attachments = if includes.contains("attachments") || includes.contains("files") {
-        let rows = sqlx::query_as::<_, AttachmentInfo>(
-            "SELECT f.file_id, f.file_title, f.file_name, f.file_url, f.file_dir, f.mime_type, ba.placement, ba.access_type, ba.access_limit FROM biblio_attachment ba JOIN files f ON f.file_id = ba.file_id WHERE ba.biblio_id = ? ORDER BY ba.file_id DESC",
-        )
-        .bind(row.biblio_id)
-        .fetch_all(&state.pool)
-        .await?;
-        Some(rows)
-    } else {
-        None
-    };
-
-    let custom = if includes.contains("custom") {
-        if let Some(row) = sqlx::query("SELECT * FROM biblio_custom WHERE biblio_id = ?")
-            .bind(row.biblio_id)
-            .fetch_optional(&state.pool)
-            .await?
-        {
-            Some(row_to_json(&row))
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let relations = if includes.contains("relations") {
-        let rows = sqlx::query_as::<_, BiblioRelationInfo>(
-            "SELECT br.rel_biblio_id AS biblio_id, b.title, br.rel_type FROM biblio_relation br JOIN biblio b ON b.biblio_id = br.rel_biblio_id WHERE br.biblio_id = ?",
-        )
-        .bind(row.biblio_id)
-        .fetch_all(&state.pool)
-        .await?;
-        Some(rows)
-    } else {
-        None
-    };
-
-    let response = BiblioResponse {
-        biblio: row,
-        gmd,
-        publisher,
-        language,
-        content_type,
-        media_type,
-        carrier_type,
-        frequency,
-        place,
-        authors,
-        topics,
-        items,
-        relations,
-        attachments,
-        custom,
-    };
-
-    let id = response.biblio.biblio_id.to_string();
-    Ok(Json(single_document(resource_with_fields(
-        "biblios",
-        id,
-        response,
-        biblio_fields,
-    ))))
+    Ok(Json(single_document(resource).with_included(included)))
 }
 
 fn row_to_json(row: &MySqlRow) -> JsonValue {
@@ -1113,23 +1177,103 @@ fn row_to_json(row: &MySqlRow) -> JsonValue {
     JsonValue::Object(map)
 }
 
+const BIBLIO_SELECT: &str = "SELECT biblio_id, title, gmd_id, publisher_id, publish_year, language_id, content_type_id, media_type_id, carrier_type_id, frequency_id, publish_place_id, classification, call_number, opac_hide, promoted, input_date, last_update FROM biblio";
+
+async fn fetch_biblio(
+    conn: &mut MySqlConnection,
+    biblio_id: i64,
+) -> Result<Option<Biblio>, AppError> {
+    let sql = format!("{} WHERE biblio_id = ?", BIBLIO_SELECT);
+    Ok(sqlx::query_as::<_, Biblio>(&sql)
+        .bind(biblio_id)
+        .fetch_optional(&mut *conn)
+        .await?)
+}
+
+/// Like [`fetch_biblio`], but locks the row until the transaction ends, so
+/// the `before` snapshot of a change is still current when it is logged.
+async fn lock_biblio(
+    conn: &mut MySqlConnection,
+    biblio_id: i64,
+) -> Result<Option<Biblio>, AppError> {
+    let sql = format!("{} WHERE biblio_id = ? FOR UPDATE", BIBLIO_SELECT);
+    Ok(sqlx::query_as::<_, Biblio>(&sql)
+        .bind(biblio_id)
+        .fetch_optional(&mut *conn)
+        .await?)
+}
+
+/// Records a biblio change in both `system_log` and `biblio_log`.
+async fn log_biblio_change(
+    conn: &mut MySqlConnection,
+    auth: &AuthUser,
+    ip: Option<IpAddr>,
+    action: &str,
+    before: Option<&Biblio>,
+    after: Option<&Biblio>,
+) -> Result<(), AppError> {
+    let Some(current) = after.or(before) else {
+        return Ok(());
+    };
+    let verb = match action {
+        "create" => "created",
+        "update" => "updated",
+        _ => "deleted",
+    };
+    let message = format!(
+        "{} {} biblio {} ({})",
+        auth.claims.username, verb, current.biblio_id, current.title
+    );
+
+    write_system_log(
+        conn,
+        &SystemLogEntry::by(auth, "bibliography", "biblio", action, message.clone()),
+    )
+    .await?;
+    let snapshot = |biblio: Option<&Biblio>| {
+        biblio
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|err| AppError::Internal(err.to_string()))
+    };
+    write_biblio_log(
+        conn,
+        auth,
+        ip,
+        &BiblioLogEntry {
+            biblio_id: current.biblio_id,
+            title: &current.title,
+            action,
+            before: snapshot(before)?,
+            after: snapshot(after)?,
+            message,
+        },
+    )
+    .await
+}
+
 #[utoipa::path(
     post,
     path = "/biblios",
-    request_body = UpsertBiblio,
+    request_body(content = UpsertBiblio, content_type = "application/vnd.api+json"),
     responses((status = 200, body = JsonApiDocument)),
     security(("bearerAuth" = [])),
     tag = "Biblios"
 )]
 async fn create_biblio(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     auth: AuthUser,
-    Json(payload): Json<UpsertBiblio>,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<UpsertBiblio>,
 ) -> Result<Json<JsonApiDocument>, AppError> {
     auth.require_access(ModuleAccess::Bibliography, Permission::Write)?;
 
     let now = chrono::Utc::now().naive_utc();
 
+    let mut tx = state.pool.begin().await?;
     let result = sqlx::query(
         "INSERT INTO biblio (title, gmd_id, publisher_id, publish_year, language_id, classification, call_number, opac_hide, promoted, input_date, last_update) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
     )
@@ -1144,13 +1288,14 @@ async fn create_biblio(
     .bind(payload.promoted.unwrap_or(0))
     .bind(now)
     .bind(now)
-    .execute(&state.pool)
+    .execute(&mut *tx)
     .await?;
 
-    let rec = sqlx::query_as::<_, Biblio>("SELECT biblio_id, title, gmd_id, publisher_id, publish_year, language_id, content_type_id, media_type_id, carrier_type_id, frequency_id, publish_place_id, classification, call_number, opac_hide, promoted, input_date, last_update FROM biblio WHERE biblio_id = ?")
-        .bind(result.last_insert_id() as i64)
-        .fetch_one(&state.pool)
-        .await?;
+    let rec = fetch_biblio(&mut tx, result.last_insert_id() as i64)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    log_biblio_change(&mut tx, &auth, ip, "create", None, Some(&rec)).await?;
+    tx.commit().await?;
 
     Ok(Json(single_document(resource(
         "biblios",
@@ -1163,47 +1308,91 @@ async fn create_biblio(
     put,
     path = "/biblios/{biblio_id}",
     params(("biblio_id" = i64, Path, description = "Biblio ID")),
-    request_body = UpsertBiblio,
+    request_body(content = UpsertBiblio, content_type = "application/vnd.api+json"),
     responses((status = 200, body = JsonApiDocument)),
     security(("bearerAuth" = [])),
     tag = "Biblios"
 )]
-async fn update_biblio(
+async fn replace_biblio(
     State(state): State<AppState>,
     Path(biblio_id): Path<i64>,
+    ClientIp(ip): ClientIp,
     auth: AuthUser,
-    Json(payload): Json<UpsertBiblio>,
+    payload: JsonApi<UpsertBiblio>,
 ) -> Result<Json<JsonApiDocument>, AppError> {
     auth.require_access(ModuleAccess::Bibliography, Permission::Write)?;
+    let payload = payload.for_id(&biblio_id.to_string())?;
 
-    let now = chrono::Utc::now().naive_utc();
+    apply_biblio_update(&state, biblio_id, ip, &auth, payload.into()).await
+}
 
-    let updated = sqlx::query(
-        "UPDATE biblio SET title = ?, gmd_id = ?, publisher_id = ?, publish_year = ?, language_id = ?, classification = ?, call_number = ?, opac_hide = ?, promoted = ?, last_update = ? WHERE biblio_id = ?",
-    )
-    .bind(&payload.title)
-    .bind(payload.gmd_id)
-    .bind(payload.publisher_id)
-    .bind(&payload.publish_year)
-    .bind(&payload.language_id)
-    .bind(&payload.classification)
-    .bind(&payload.call_number)
-    .bind(payload.opac_hide.unwrap_or(0))
-    .bind(payload.promoted.unwrap_or(0))
-    .bind(now)
-    .bind(biblio_id)
-    .execute(&state.pool)
-    .await?;
+#[utoipa::path(
+    patch,
+    path = "/biblios/{biblio_id}",
+    params(("biblio_id" = i64, Path, description = "Biblio ID")),
+    request_body(content = UpdateBiblio, content_type = "application/vnd.api+json"),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Biblios"
+)]
+async fn update_biblio(
+    State(state): State<AppState>,
+    Path(biblio_id): Path<i64>,
+    ClientIp(ip): ClientIp,
+    auth: AuthUser,
+    payload: JsonApi<UpdateBiblio>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Bibliography, Permission::Write)?;
+    let payload = payload.for_id(&biblio_id.to_string())?;
 
-    if updated.rows_affected() == 0 {
-        return Err(AppError::NotFound);
+    apply_biblio_update(&state, biblio_id, ip, &auth, payload).await
+}
+
+async fn apply_biblio_update(
+    state: &AppState,
+    biblio_id: i64,
+    ip: Option<IpAddr>,
+    auth: &AuthUser,
+    payload: UpdateBiblio,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let mut set = PatchSet::default();
+    set.required("title", payload.title)?;
+    set.nullable("gmd_id", payload.gmd_id);
+    set.nullable("publisher_id", payload.publisher_id);
+    set.nullable("publish_year", payload.publish_year);
+    set.nullable("language_id", payload.language_id);
+    set.nullable("classification", payload.classification);
+    set.nullable("call_number", payload.call_number);
+    set.required("opac_hide", payload.opac_hide)?;
+    set.required("promoted", payload.promoted)?;
+
+    let mut tx = state.pool.begin().await?;
+    let before = lock_biblio(&mut tx, biblio_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    // An empty PATCH changes nothing and just returns the biblio.
+    if set.is_empty() {
+        return Ok(Json(single_document(resource(
+            "biblios",
+            before.biblio_id.to_string(),
+            before,
+        ))));
     }
 
-    let rec = sqlx::query_as::<_, Biblio>("SELECT biblio_id, title, gmd_id, publisher_id, publish_year, language_id, content_type_id, media_type_id, carrier_type_id, frequency_id, publish_place_id, classification, call_number, opac_hide, promoted, input_date, last_update FROM biblio WHERE biblio_id = ?")
+    set.set("last_update", chrono::Utc::now().naive_utc());
+    let sql = set.update_sql("biblio", "biblio_id");
+    set.bind(sqlx::query(&sql))
         .bind(biblio_id)
-        .fetch_one(&state.pool)
+        .execute(&mut *tx)
         .await?;
 
+    let rec = fetch_biblio(&mut tx, biblio_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    log_biblio_change(&mut tx, auth, ip, "update", Some(&before), Some(&rec)).await?;
+    tx.commit().await?;
+
     Ok(Json(single_document(resource(
         "biblios",
         rec.biblio_id.to_string(),
@@ -1222,14 +1411,22 @@ async fn update_biblio(
 async fn delete_biblio(
     State(state): State<AppState>,
     Path(biblio_id): Path<i64>,
+    ClientIp(ip): ClientIp,
     auth: AuthUser,
 ) -> Result<StatusCode, AppError> {
     auth.require_access(ModuleAccess::Bibliography, Permission::Write)?;
 
-    sqlx::query("DELETE FROM biblio WHERE biblio_id = ?")
+    let mut tx = state.pool.begin().await?;
+    let before = lock_biblio(&mut tx, biblio_id).await?;
+    let deleted = sqlx::query("DELETE FROM biblio WHERE biblio_id = ?")
         .bind(biblio_id)
-        .execute(&state.pool)
+        .execute(&mut *tx)
         .await?;
 
+    if deleted.rows_affected() > 0 {
+        log_biblio_change(&mut tx, &auth, ip, "delete", before.as_ref(), None).await?;
+    }
+    tx.commit().await?;
+
     Ok(StatusCode::NO_CONTENT)
 }
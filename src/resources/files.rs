@@ -4,6 +4,7 @@ use axum::{
     routing::get,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 use sqlx::FromRow;
 use utoipa::ToSchema;
 
@@ -12,7 +13,7 @@ use crate::{
     config::AppState,
     error::AppError,
     jsonapi::{
-        JsonApiDocument, collection_document, pagination_meta, resource_with_fields,
+        Included, JsonApiDocument, ResourceBuilder, collection_document, pagination_meta,
         single_document,
     },
     resources::ListParams,
@@ -42,12 +43,43 @@ pub struct FileBiblioAttachment {
     pub access_limit: Option<String>,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct FileResponse {
-    #[serde(flatten)]
-    pub file: FileObject,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub biblios: Option<Vec<FileBiblioAttachment>>,
+const FILE_INCLUDES: &[&str] = &["biblios"];
+
+/// Builds the `files` resource object. The attachment settings describe the
+/// link to each biblio, so they go into the identifier's `meta`.
+fn file_resource(
+    file: FileObject,
+    biblios: Option<Vec<FileBiblioAttachment>>,
+    params: &ListParams,
+    included: &mut Included,
+) -> Value {
+    let mut builder = ResourceBuilder::new(
+        "files",
+        file.file_id.to_string(),
+        file,
+        params.fieldset("files"),
+    );
+    if let Some(biblios) = biblios {
+        let linkage = biblios
+            .into_iter()
+            .map(|biblio| {
+                let mut linkage = included.resource(
+                    "biblios",
+                    biblio.biblio_id.to_string(),
+                    json!({ "title": biblio.title }),
+                    params.fieldset("biblios"),
+                );
+                linkage["meta"] = json!({
+                    "placement": biblio.placement,
+                    "access_type": biblio.access_type,
+                    "access_limit": biblio.access_limit,
+                });
+                linkage
+            })
+            .collect();
+        builder = builder.has_many("biblios", linkage);
+    }
+    builder.build()
 }
 
 pub fn router() -> Router<AppState> {
@@ -71,8 +103,7 @@ async fn list_files(
     auth.require_access(ModuleAccess::Bibliography, Permission::Read)?;
 
     let pagination = params.pagination();
-    let includes = params.includes();
-    let file_fields = params.fieldset("files");
+    let includes = params.checked_includes(FILE_INCLUDES)?;
     let (limit, offset, page, per_page) = pagination.limit_offset();
 
     let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files")
@@ -87,6 +118,7 @@ async fn list_files(
     .fetch_all(&state.pool)
     .await?;
 
+    let mut included = Included::default();
     let mut data = Vec::with_capacity(files.len());
 
     for file in files {
@@ -102,25 +134,12 @@ async fn list_files(
             None
         };
 
-        data.push(FileResponse { file, biblios });
+        data.push(file_resource(file, biblios, &params, &mut included));
     }
 
-    let documents = data
-        .into_iter()
-        .map(|file| {
-            resource_with_fields(
-                "files",
-                file.file.file_id.to_string(),
-                file,
-                file_fields,
-            )
-        })
-        .collect();
-
-    Ok(Json(collection_document(
-        documents,
-        pagination_meta(page, per_page, total),
-    )))
+    Ok(Json(
+        collection_document(data, pagination_meta(page, per_page, total)).with_included(included),
+    ))
 }
 
 #[utoipa::path(
@@ -146,7 +165,7 @@ async fn get_file(
     .fetch_one(&state.pool)
     .await?;
 
-    let includes = params.includes();
+    let includes = params.checked_includes(FILE_INCLUDES)?;
     let biblios = if includes.contains("biblios") {
         let rows = sqlx::query_as::<_, FileBiblioAttachment>(
             "SELECT ba.biblio_id, b.title, ba.placement, ba.access_type, ba.access_limit FROM biblio_attachment ba JOIN biblio b ON b.biblio_id = ba.biblio_id WHERE ba.file_id = ?",
@@ -159,12 +178,7 @@ async fn get_file(
         None
     };
 
-    let response = FileResponse { file, biblios };
-    let file_fields = params.fieldset("files");
-    Ok(Json(single_document(resource_with_fields(
-        "files",
-        response.file.file_id.to_string(),
-        response,
-        file_fields,
-    ))))
+    let mut included = Included::default();
+    let resource = file_resource(file, biblios, &params, &mut included);
+    Ok(Json(single_document(resource).with_included(included)))
 }
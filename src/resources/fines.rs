@@ -0,0 +1,347 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    routing::{get, post},
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, MySqlConnection};
+use utoipa::ToSchema;
+
+use crate::{
+    audit::{SystemLogEntry, write_system_log},
+    auth::{AuthUser, ModuleAccess, Permission},
+    config::AppState,
+    error::AppError,
+    jsonapi::{
+        JsonApi, JsonApiDocument, ResourcePayload, collection_document, pagination_meta, resource,
+        resource_with_fields, single_document,
+    },
+    resources::{
+        FilterField, FilterOperator, FilterValueType, ListParams, RANGE_OPERATORS, SortField,
+        bind_filters_to_query, bind_filters_to_scalar, where_clause,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Fine {
+    pub fines_id: i64,
+    pub fines_date: NaiveDate,
+    pub member_id: String,
+    pub debet: Option<i64>,
+    pub credit: Option<i64>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RecordPayment {
+    pub member_id: String,
+    pub amount: i64,
+    pub description: Option<String>,
+}
+
+impl ResourcePayload for RecordPayment {
+    const TYPE: &'static str = "payments";
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct WaiveFine {
+    pub member_id: String,
+    pub amount: i64,
+    pub reason: String,
+}
+
+impl ResourcePayload for WaiveFine {
+    const TYPE: &'static str = "waivers";
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct FineBalance {
+    pub member_id: String,
+    pub total_debet: i64,
+    pub total_credit: i64,
+    /// Outstanding amount: debits minus credits.
+    pub balance: i64,
+}
+
+const FINE_SORTS: &[SortField<'_>] = &[
+    SortField::new("fines_id", "fines.fines_id"),
+    SortField::new("fines_date", "fines.fines_date"),
+];
+
+const FINE_FILTERS: &[FilterField<'_>] = &[
+    FilterField::new(
+        "member_id",
+        "fines.member_id",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "fines_date",
+        "fines.fines_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(RANGE_OPERATORS),
+];
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_fines))
+        .route("/payments", post(record_payment))
+        .route("/waivers", post(waive_fine))
+        .route("/:fines_id", get(get_fine))
+}
+
+pub async fn fetch_balance(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+) -> Result<FineBalance, AppError> {
+    let balance = sqlx::query_as::<_, FineBalance>(
+        "SELECT ? AS member_id, CAST(COALESCE(SUM(debet), 0) AS SIGNED) AS total_debet, CAST(COALESCE(SUM(credit), 0) AS SIGNED) AS total_credit, CAST(COALESCE(SUM(debet), 0) - COALESCE(SUM(credit), 0) AS SIGNED) AS balance FROM fines WHERE member_id = ?",
+    )
+    .bind(member_id)
+    .bind(member_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(balance)
+}
+
+#[utoipa::path(
+    get,
+    path = "/fines",
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Fines"
+)]
+async fn list_fines(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Read)?;
+
+    let pagination = params.pagination();
+    let fine_fields = params.fieldset("fines");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause =
+        params.sort_clause(FINE_SORTS, "fines.fines_date DESC, fines.fines_id DESC")?;
+    let filters = params.filter_clauses(FINE_FILTERS)?;
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM fines {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "SELECT fines_id, fines_date, member_id, debet, credit, description FROM fines {} ORDER BY {} LIMIT ? OFFSET ?",
+        where_sql, sort_clause
+    );
+    let fines = bind_filters_to_query(sqlx::query_as::<_, Fine>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = fines
+        .into_iter()
+        .map(|fine| resource_with_fields("fines", fine.fines_id.to_string(), fine, fine_fields))
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/fines/{fines_id}",
+    params(("fines_id" = i64, Path, description = "Fine ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Fines"
+)]
+async fn get_fine(
+    State(state): State<AppState>,
+    Path(fines_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Read)?;
+
+    let fine = sqlx::query_as::<_, Fine>(
+        "SELECT fines_id, fines_date, member_id, debet, credit, description FROM fines WHERE fines_id = ?",
+    )
+    .bind(fines_id)
+    .fetch_one(&state.pool)
+    .await?;
+
+    Ok(Json(single_document(resource(
+        "fines",
+        fine.fines_id.to_string(),
+        fine,
+    ))))
+}
+
+/// Inserts a credit row after checking it does not exceed what the member
+/// still owes. Run it first in its transaction: it locks the member's row, so
+/// concurrent payments and waivers for one member are checked against the
+/// balance one at a time, and only a snapshot taken after that lock sees the
+/// other credit.
+async fn insert_credit(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+    amount: i64,
+    description: String,
+) -> Result<Fine, AppError> {
+    if amount <= 0 {
+        return Err(AppError::BadRequest("amount must be positive".into()));
+    }
+
+    sqlx::query_scalar::<_, String>("SELECT member_id FROM member WHERE member_id = ? FOR UPDATE")
+        .bind(member_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let balance = fetch_balance(conn, member_id).await?;
+    if amount > balance.balance {
+        return Err(AppError::BadRequest(format!(
+            "amount exceeds the outstanding balance of {}",
+            balance.balance
+        )));
+    }
+
+    let today = chrono::Utc::now().date_naive();
+    let result = sqlx::query(
+        "INSERT INTO fines (fines_date, member_id, debet, credit, description) VALUES (?, ?, 0, ?, ?)",
+    )
+    .bind(today)
+    .bind(member_id)
+    .bind(amount)
+    .bind(description)
+    .execute(&mut *conn)
+    .await?;
+
+    let fine = sqlx::query_as::<_, Fine>(
+        "SELECT fines_id, fines_date, member_id, debet, credit, description FROM fines WHERE fines_id = ?",
+    )
+    .bind(result.last_insert_id() as i64)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(fine)
+}
+
+#[utoipa::path(
+    post,
+    path = "/fines/payments",
+    request_body(content = RecordPayment, content_type = "application/vnd.api+json"),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Fines"
+)]
+async fn record_payment(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<RecordPayment>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    state.require_transactional_tables()?;
+
+    let description = payload
+        .description
+        .filter(|text| !text.trim().is_empty())
+        .unwrap_or_else(|| "Fine payment".into());
+
+    let mut tx = state.pool.begin().await?;
+    let fine = insert_credit(&mut tx, &payload.member_id, payload.amount, description).await?;
+    write_system_log(
+        &mut tx,
+        &SystemLogEntry::by(
+            &auth,
+            "circulation",
+            "fines",
+            "payment",
+            format!(
+                "{} recorded a payment of {} for member {}",
+                auth.claims.username, payload.amount, payload.member_id
+            ),
+        ),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(single_document(resource(
+        "fines",
+        fine.fines_id.to_string(),
+        fine,
+    ))))
+}
+
+/// Waivers write off fines instead of collecting them, so on top of
+/// Circulation write they need System write, which SLiMS reserves for
+/// supervisors. SLiMS group access only knows read and write per module, so
+/// there is no dedicated waiver permission to check; a desk account that
+/// should take payments but not waive them gets Circulation write only.
+#[utoipa::path(
+    post,
+    path = "/fines/waivers",
+    request_body(content = WaiveFine, content_type = "application/vnd.api+json"),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Fines"
+)]
+async fn waive_fine(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<WaiveFine>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    auth.require_access(ModuleAccess::System, Permission::Write)?;
+    state.require_transactional_tables()?;
+
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return Err(AppError::BadRequest("reason cannot be empty".into()));
+    }
+
+    let mut tx = state.pool.begin().await?;
+    let fine = insert_credit(
+        &mut tx,
+        &payload.member_id,
+        payload.amount,
+        format!("Waived: {}", reason),
+    )
+    .await?;
+
+    write_system_log(
+        &mut tx,
+        &SystemLogEntry::by(
+            &auth,
+            "circulation",
+            "fines",
+            "waive",
+            format!(
+                "{} waived {} of fines for member {}: {}",
+                auth.claims.username, payload.amount, payload.member_id, reason
+            ),
+        ),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(single_document(resource(
+        "fines",
+        fine.fines_id.to_string(),
+        fine,
+    ))))
+}
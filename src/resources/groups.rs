@@ -0,0 +1,495 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, MySqlConnection};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, AuthUser, ModuleAccess, Permission},
+    config::AppState,
+    error::AppError,
+    jsonapi::{
+        JsonApi, JsonApiDocument, ResourcePayload, collection_document, pagination_meta, resource,
+        resource_with_fields, single_document,
+    },
+    resources::{
+        FilterField, FilterOperator, FilterValueType, ListParams, SortField, bind_filters_to_query,
+        bind_filters_to_scalar, where_clause,
+    },
+};
+
+/// The Administrator group created by the SLiMS installer. It cannot be
+/// deleted, so there is always a group that can manage the others.
+const ADMIN_GROUP_ID: i64 = 1;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Group {
+    pub group_id: i64,
+    pub group_name: String,
+    pub input_date: Option<NaiveDate>,
+    pub last_update: Option<NaiveDate>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateGroup {
+    pub group_name: String,
+}
+
+impl ResourcePayload for CreateGroup {
+    const TYPE: &'static str = "groups";
+}
+
+/// One row of a group's access matrix. Modules without a `group_access`
+/// row are listed with both flags off.
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+pub struct ModuleGrant {
+    pub module_id: i64,
+    pub module_name: String,
+    pub read: bool,
+    pub write: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GroupAccess {
+    pub group_id: i64,
+    pub modules: Vec<ModuleGrant>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GrantRequest {
+    pub module_id: i64,
+    #[serde(default)]
+    pub read: bool,
+    #[serde(default)]
+    pub write: bool,
+}
+
+/// Replaces the whole matrix; modules left out lose all access.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateGroupAccess {
+    pub modules: Vec<GrantRequest>,
+}
+
+impl ResourcePayload for UpdateGroupAccess {
+    const TYPE: &'static str = "group-access";
+}
+
+const GROUP_SELECT: &str = "SELECT group_id, group_name, input_date, last_update FROM user_group";
+
+const GROUP_SORTS: &[SortField<'_>] = &[
+    SortField::new("group_id", "user_group.group_id"),
+    SortField::new("group_name", "user_group.group_name"),
+];
+
+const GROUP_FILTERS: &[FilterField<'_>] = &[FilterField::new(
+    "group_name",
+    "user_group.group_name",
+    FilterOperator::Like,
+    FilterValueType::Text,
+)];
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_groups).post(create_group))
+        .route(
+            "/:group_id",
+            get(get_group).put(update_group).delete(delete_group),
+        )
+        .route(
+            "/:group_id/access",
+            get(get_group_access).put(update_group_access),
+        )
+}
+
+async fn fetch_group(conn: &mut MySqlConnection, group_id: i64) -> Result<Group, AppError> {
+    let sql = format!("{} WHERE group_id = ?", GROUP_SELECT);
+    let group = sqlx::query_as::<_, Group>(&sql)
+        .bind(group_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(group)
+}
+
+async fn fetch_group_access(
+    conn: &mut MySqlConnection,
+    group_id: i64,
+) -> Result<GroupAccess, AppError> {
+    let modules = sqlx::query_as::<_, ModuleGrant>(
+        "SELECT m.module_id, m.module_name, COALESCE(ga.r, 0) <> 0 AS `read`, COALESCE(ga.w, 0) <> 0 AS `write` FROM mst_module m LEFT JOIN group_access ga ON ga.module_id = m.module_id AND ga.group_id = ? ORDER BY m.module_id",
+    )
+    .bind(group_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(GroupAccess { group_id, modules })
+}
+
+fn validate_group_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("group_name cannot be empty".into()));
+    }
+    // `user_group.group_name` is varchar(30).
+    if name.chars().count() > 30 {
+        return Err(AppError::BadRequest(
+            "group_name cannot be longer than 30 characters".into(),
+        ));
+    }
+    Ok(name)
+}
+
+async fn ensure_group_name_free(
+    conn: &mut MySqlConnection,
+    name: &str,
+    except_group_id: Option<i64>,
+) -> Result<(), AppError> {
+    let taken: Option<i64> =
+        sqlx::query_scalar("SELECT group_id FROM user_group WHERE group_name = ?")
+            .bind(name)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+    match taken {
+        Some(group_id) if Some(group_id) != except_group_id => Err(AppError::BadRequest(format!(
+            "group `{}` already exists",
+            name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/groups",
+    responses((status = 200, description = "Paginated user groups", body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Groups"
+)]
+async fn list_groups(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::System, Permission::Read)?;
+
+    let pagination = params.pagination();
+    let group_fields = params.fieldset("groups");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause = params.sort_clause(GROUP_SORTS, "user_group.group_name ASC")?;
+    let filters = params.filter_clauses(GROUP_FILTERS)?;
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM user_group {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "{} {} ORDER BY {} LIMIT ? OFFSET ?",
+        GROUP_SELECT, where_sql, sort_clause
+    );
+    let groups = bind_filters_to_query(sqlx::query_as::<_, Group>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = groups
+        .into_iter()
+        .map(|group| {
+            resource_with_fields("groups", group.group_id.to_string(), group, group_fields)
+        })
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/groups/{group_id}",
+    params(("group_id" = i64, Path, description = "Group ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Groups"
+)]
+async fn get_group(
+    State(state): State<AppState>,
+    Path(group_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::System, Permission::Read)?;
+
+    let mut conn = state.pool.acquire().await?;
+    let group = fetch_group(&mut conn, group_id).await?;
+
+    Ok(Json(single_document(resource(
+        "groups",
+        group.group_id.to_string(),
+        group,
+    ))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/groups",
+    request_body(content = CreateGroup, content_type = "application/vnd.api+json"),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 400, description = "Empty or duplicate group name"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Groups"
+)]
+async fn create_group(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<CreateGroup>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_account_admin()?;
+
+    let name = validate_group_name(&payload.group_name)?;
+    let mut conn = state.pool.acquire().await?;
+    ensure_group_name_free(&mut conn, name, None).await?;
+
+    let result = sqlx::query(
+        "INSERT INTO user_group (group_name, input_date, last_update) VALUES (?, CURDATE(), CURDATE())",
+    )
+    .bind(name)
+    .execute(&mut *conn)
+    .await?;
+
+    let group = fetch_group(&mut conn, result.last_insert_id() as i64).await?;
+
+    Ok(Json(single_document(resource(
+        "groups",
+        group.group_id.to_string(),
+        group,
+    ))))
+}
+
+#[utoipa::path(
+    put,
+    path = "/groups/{group_id}",
+    request_body(content = CreateGroup, content_type = "application/vnd.api+json"),
+    params(("group_id" = i64, Path, description = "Group ID")),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 400, description = "Empty or duplicate group name"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Groups"
+)]
+async fn update_group(
+    State(state): State<AppState>,
+    Path(group_id): Path<i64>,
+    auth: AuthUser,
+    payload: JsonApi<CreateGroup>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_account_admin()?;
+    let payload = payload.for_id(&group_id.to_string())?;
+
+    let name = validate_group_name(&payload.group_name)?;
+    let mut conn = state.pool.acquire().await?;
+    fetch_group(&mut conn, group_id).await?;
+    ensure_group_name_free(&mut conn, name, Some(group_id)).await?;
+
+    sqlx::query("UPDATE user_group SET group_name = ?, last_update = CURDATE() WHERE group_id = ?")
+        .bind(name)
+        .bind(group_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let group = fetch_group(&mut conn, group_id).await?;
+
+    Ok(Json(single_document(resource(
+        "groups",
+        group.group_id.to_string(),
+        group,
+    ))))
+}
+
+/// Deletes a group with its access matrix and removes it from every user's
+/// `groups` list.
+#[utoipa::path(
+    delete,
+    path = "/groups/{group_id}",
+    params(("group_id" = i64, Path, description = "Group ID")),
+    responses(
+        (status = 204, description = "Group deleted"),
+        (status = 400, description = "The Administrator group"),
+        (status = 404, description = "No such group"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Groups"
+)]
+async fn delete_group(
+    State(state): State<AppState>,
+    Path(group_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.require_account_admin()?;
+
+    if group_id == ADMIN_GROUP_ID {
+        return Err(AppError::BadRequest(
+            "the Administrator group cannot be deleted".into(),
+        ));
+    }
+
+    let mut tx = state.pool.begin().await?;
+    let deleted = sqlx::query("DELETE FROM user_group WHERE group_id = ?")
+        .bind(group_id)
+        .execute(&mut *tx)
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    sqlx::query("DELETE FROM group_access WHERE group_id = ?")
+        .bind(group_id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Group IDs are serialized as quoted strings, so the LIKE only matches
+    // whole IDs; `parse_groups` has the final say.
+    let members: Vec<(i64, Option<String>)> =
+        sqlx::query_as("SELECT user_id, `groups` FROM `user` WHERE `groups` LIKE ?")
+            .bind(format!("%\"{}\"%", group_id))
+            .fetch_all(&mut *tx)
+            .await?;
+    for (user_id, groups) in members {
+        let remaining: Vec<i64> = auth::parse_groups(groups.as_deref())
+            .into_iter()
+            .filter(|id| *id != group_id)
+            .collect();
+        sqlx::query("UPDATE `user` SET `groups` = ?, last_update = CURDATE() WHERE user_id = ?")
+            .bind(auth::serialize_groups(&remaining))
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+
+    state.permission_cache.clear();
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/groups/{group_id}/access",
+    params(("group_id" = i64, Path, description = "Group ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Groups"
+)]
+async fn get_group_access(
+    State(state): State<AppState>,
+    Path(group_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::System, Permission::Read)?;
+
+    let mut conn = state.pool.acquire().await?;
+    fetch_group(&mut conn, group_id).await?;
+    let access = fetch_group_access(&mut conn, group_id).await?;
+
+    Ok(Json(single_document(resource(
+        "group-access",
+        group_id.to_string(),
+        access,
+    ))))
+}
+
+/// Replaces the group's access matrix. Existing `group_access` rows keep
+/// their `menus`, which only the PHP admin edits.
+#[utoipa::path(
+    put,
+    path = "/groups/{group_id}/access",
+    request_body(content = UpdateGroupAccess, content_type = "application/vnd.api+json"),
+    params(("group_id" = i64, Path, description = "Group ID")),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 400, description = "Unknown or repeated module"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Groups"
+)]
+async fn update_group_access(
+    State(state): State<AppState>,
+    Path(group_id): Path<i64>,
+    auth: AuthUser,
+    payload: JsonApi<UpdateGroupAccess>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_account_admin()?;
+    let payload = payload.for_id(&group_id.to_string())?;
+
+    let mut tx = state.pool.begin().await?;
+    fetch_group(&mut tx, group_id).await?;
+
+    let modules: Vec<i64> = sqlx::query_scalar("SELECT module_id FROM mst_module")
+        .fetch_all(&mut *tx)
+        .await?;
+    for (idx, grant) in payload.modules.iter().enumerate() {
+        if !modules.contains(&grant.module_id) {
+            return Err(AppError::BadRequest(format!(
+                "module {} does not exist",
+                grant.module_id
+            )));
+        }
+        if payload.modules[..idx]
+            .iter()
+            .any(|other| other.module_id == grant.module_id)
+        {
+            return Err(AppError::BadRequest(format!(
+                "module {} is listed more than once",
+                grant.module_id
+            )));
+        }
+    }
+
+    sqlx::query("UPDATE group_access SET r = 0, w = 0 WHERE group_id = ?")
+        .bind(group_id)
+        .execute(&mut *tx)
+        .await?;
+    for grant in &payload.modules {
+        // Write access implies read, as `auth::has_access` already assumes.
+        let read = grant.read || grant.write;
+        sqlx::query(
+            "INSERT INTO group_access (group_id, module_id, r, w) VALUES (?, ?, ?, ?) ON DUPLICATE KEY UPDATE r = VALUES(r), w = VALUES(w)",
+        )
+        .bind(group_id)
+        .bind(grant.module_id)
+        .bind(i32::from(read))
+        .bind(i32::from(grant.write))
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query(
+        "DELETE FROM group_access WHERE group_id = ? AND r = 0 AND w = 0 AND menus IS NULL",
+    )
+    .bind(group_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let access = fetch_group_access(&mut tx, group_id).await?;
+    tx.commit().await?;
+
+    state.permission_cache.clear();
+
+    Ok(Json(single_document(resource(
+        "group-access",
+        group_id.to_string(),
+        access,
+    ))))
+}
@@ -9,20 +9,21 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use sqlx::mysql::MySqlRow;
 use sqlx::{Column, FromRow, Row};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use utoipa::ToSchema;
 
 use crate::{
+    audit::{SystemLogEntry, write_system_log},
     auth::{AuthUser, ModuleAccess, Permission},
     config::AppState,
     error::AppError,
     jsonapi::{
-        JsonApiDocument, collection_document, pagination_meta, resource, resource_with_fields,
-        single_document,
+        Included, JsonApi, JsonApiDocument, ResourceBuilder, ResourcePayload, collection_document,
+        identifier, pagination_meta, resource, single_document,
     },
     resources::{
-        bind_filters_to_query, bind_filters_to_scalar, where_clause, FilterField, FilterOperator,
-        FilterValueType, ListParams, SortField,
+        FilterField, FilterOperator, FilterValueType, ListParams, PatchSet, SortField,
+        bind_filters_to_query, bind_filters_to_scalar, nullable, where_clause,
     },
 };
 
@@ -48,6 +49,52 @@ pub struct CreateItem {
     pub item_status_id: Option<String>,
 }
 
+impl ResourcePayload for CreateItem {
+    const TYPE: &'static str = "items";
+}
+
+/// PATCH attributes: only those present are written, and `null` clears the
+/// column.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateItem {
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub item_code: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>, nullable)]
+    pub biblio_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub call_number: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>, nullable)]
+    pub coll_type_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub location_id: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub item_status_id: Option<Option<String>>,
+}
+
+impl ResourcePayload for UpdateItem {
+    const TYPE: &'static str = "items";
+}
+
+/// A PUT replaces the whole record, so attributes left out are cleared.
+impl From<CreateItem> for UpdateItem {
+    fn from(item: CreateItem) -> Self {
+        UpdateItem {
+            item_code: Some(item.item_code),
+            biblio_id: Some(item.biblio_id),
+            call_number: Some(item.call_number),
+            coll_type_id: Some(item.coll_type_id),
+            location_id: Some(item.location_id),
+            item_status_id: Some(item.item_status_id),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow, ToSchema)]
 pub struct BiblioSummary {
     pub biblio_id: i64,
@@ -73,25 +120,36 @@ pub struct ItemStatusSummary {
     pub no_loan: i16,
 }
 
+/// Attributes of an `items` resource; related records are relationships.
 #[derive(Debug, Serialize, ToSchema)]
 pub struct ItemResponse {
     #[serde(flatten)]
     pub item: Item,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub biblio: Option<BiblioSummary>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub coll_type: Option<CollTypeSummary>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub location: Option<LocationSummary>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub item_status: Option<ItemStatusSummary>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub loan_status: Option<LoanStatusSummary>,
     #[schema(value_type = Object)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub custom: Option<JsonValue>,
 }
 
+/// Records loaded for `include`.
+#[derive(Default)]
+struct ItemRelated {
+    biblio: Option<BiblioSummary>,
+    coll_type: Option<CollTypeSummary>,
+    location: Option<LocationSummary>,
+    item_status: Option<ItemStatusSummary>,
+    /// `Some(None)` when included but the item is not on loan.
+    loan_status: Option<Option<LoanStatusSummary>>,
+}
+
+const ITEM_INCLUDES: &[&str] = &[
+    "biblio",
+    "coll_type",
+    "location",
+    "item_status",
+    "loan_status",
+    "custom",
+];
+
 const ITEM_SORTS: &[SortField<'_>] = &[
     SortField::new("item_id", "item.item_id"),
     SortField::new("item_code", "item.item_code"),
@@ -141,7 +199,10 @@ pub fn router() -> Router<AppState> {
         .route("/", get(list_items).post(create_item))
         .route(
             "/:item_id",
-            get(get_item).put(update_item).delete(delete_item),
+            get(get_item)
+                .put(replace_item)
+                .patch(update_item)
+                .delete(delete_item),
         )
 }
 
@@ -160,8 +221,7 @@ async fn list_items(
     auth.require_access(ModuleAccess::Bibliography, Permission::Read)?;
 
     let pagination = params.pagination();
-    let includes = params.includes();
-    let item_fields = params.fieldset("items");
+    let includes = params.checked_includes(ITEM_INCLUDES)?;
     let (limit, offset, page, per_page) = pagination.limit_offset();
     let sort_clause = params.sort_clause(ITEM_SORTS, "item.item_id DESC")?;
     let filters = params.filter_clauses(ITEM_FILTERS)?;
@@ -182,6 +242,26 @@ async fn list_items(
         .fetch_all(&state.pool)
         .await?;
 
+    let data = enrich_items(&state, &includes, items).await?;
+    let mut included = Included::default();
+    let documents = data
+        .into_iter()
+        .map(|(item, related)| item_resource(item, related, &params, &mut included))
+        .collect();
+
+    Ok(Json(
+        collection_document(documents, pagination_meta(page, per_page, total))
+            .with_included(included),
+    ))
+}
+
+/// Loads the included records of each item, caching lookups shared by
+/// several items of the page.
+async fn enrich_items(
+    state: &AppState,
+    includes: &HashSet<String>,
+    items: Vec<Item>,
+) -> Result<Vec<(ItemResponse, ItemRelated)>, AppError> {
     let mut biblio_cache: HashMap<i32, BiblioSummary> = HashMap::new();
     let mut coll_type_cache: HashMap<i32, CollTypeSummary> = HashMap::new();
     let mut location_cache: HashMap<String, LocationSummary> = HashMap::new();
@@ -191,77 +271,74 @@ async fn list_items(
 
     for item in items {
         let custom = if includes.contains("custom") {
-            if let Some(row) = sqlx::query("SELECT * FROM item_custom WHERE item_id = ?")
+            sqlx::query("SELECT * FROM item_custom WHERE item_id = ?")
                 .bind(item.item_id)
                 .fetch_optional(&state.pool)
                 .await?
-            {
-                Some(row_to_json(&row))
-            } else {
-                None
-            }
+                .map(|row| row_to_json(&row))
         } else {
             None
         };
 
         let mut biblio = None;
-        if includes.contains("biblio") {
-            if let Some(biblio_id) = item.biblio_id {
-                if let Some(existing) = biblio_cache.get(&biblio_id) {
-                    biblio = Some(existing.clone());
-                } else if let Some(row) = sqlx::query_as::<_, BiblioSummary>(
-                    "SELECT biblio_id, title FROM biblio WHERE biblio_id = ?",
-                )
-                .bind(biblio_id)
-                .fetch_optional(&state.pool)
-                .await?
-                {
-                    biblio_cache.insert(biblio_id, row.clone());
-                    biblio = Some(row);
-                }
+        if includes.contains("biblio")
+            && let Some(biblio_id) = item.biblio_id
+        {
+            if let Some(existing) = biblio_cache.get(&biblio_id) {
+                biblio = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, BiblioSummary>(
+                "SELECT biblio_id, title FROM biblio WHERE biblio_id = ?",
+            )
+            .bind(biblio_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                biblio_cache.insert(biblio_id, row.clone());
+                biblio = Some(row);
             }
         }
 
         let mut coll_type = None;
-        if includes.contains("coll_type") {
-            if let Some(coll_type_id) = item.coll_type_id {
-                if let Some(existing) = coll_type_cache.get(&coll_type_id) {
-                    coll_type = Some(existing.clone());
-                } else if let Some(row) = sqlx::query_as::<_, CollTypeSummary>(
-                    "SELECT coll_type_id, coll_type_name FROM mst_coll_type WHERE coll_type_id = ?",
-                )
-                .bind(coll_type_id)
-                .fetch_optional(&state.pool)
-                .await?
-                {
-                    coll_type_cache.insert(coll_type_id, row.clone());
-                    coll_type = Some(row);
-                }
+        if includes.contains("coll_type")
+            && let Some(coll_type_id) = item.coll_type_id
+        {
+            if let Some(existing) = coll_type_cache.get(&coll_type_id) {
+                coll_type = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, CollTypeSummary>(
+                "SELECT coll_type_id, coll_type_name FROM mst_coll_type WHERE coll_type_id = ?",
+            )
+            .bind(coll_type_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                coll_type_cache.insert(coll_type_id, row.clone());
+                coll_type = Some(row);
             }
         }
 
         let mut location = None;
-        if includes.contains("location") {
-            if let Some(loc_id) = item.location_id.clone() {
-                if let Some(existing) = location_cache.get(&loc_id) {
-                    location = Some(existing.clone());
-                } else if let Some(row) = sqlx::query_as::<_, LocationSummary>(
-                    "SELECT location_id, location_name FROM mst_location WHERE location_id = ?",
-                )
-                .bind(&loc_id)
-                .fetch_optional(&state.pool)
-                .await?
-                {
-                    location_cache.insert(loc_id.clone(), row.clone());
-                    location = Some(row);
-                }
+        if includes.contains("location")
+            && let Some(loc_id) = item.location_id.clone()
+        {
+            if let Some(existing) = location_cache.get(&loc_id) {
+                location = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, LocationSummary>(
+                "SELECT location_id, location_name FROM mst_location WHERE location_id = ?",
+            )
+            .bind(&loc_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                location_cache.insert(loc_id.clone(), row.clone());
+                location = Some(row);
             }
         }
 
         let mut item_status = None;
-        if includes.contains("item_status") {
-            if let Some(status_id) = item.item_status_id.clone() {
-                if let Some(existing) = status_cache.get(&status_id) {
+        if includes.contains("item_status")
+            && let Some(status_id) = item.item_status_id.clone()
+        {
+            if let Some(existing) = status_cache.get(&status_id) {
                     item_status = Some(existing.clone());
                 } else if let Some(row) = sqlx::query_as::<_, ItemStatusSummary>(
                     "SELECT item_status_id, item_status_name, no_loan FROM mst_item_status WHERE item_status_id = ?",
@@ -273,13 +350,13 @@ async fn list_items(
                     status_cache.insert(status_id.clone(), row.clone());
                     item_status = Some(row);
                 }
-            }
         }
 
         let mut loan_status = None;
-        if includes.contains("loan_status") {
-            if let Some(code) = item.item_code.clone() {
-                if let Some(existing) = loan_status_cache.get(&code) {
+        if includes.contains("loan_status")
+            && let Some(code) = item.item_code.clone()
+        {
+            if let Some(existing) = loan_status_cache.get(&code) {
                     loan_status = Some(existing.clone());
                 } else if let Some(row) = sqlx::query_as::<_, LoanStatusSummary>(
                     "SELECT loan_id, item_code, member_id, loan_date, due_date, is_return, return_date FROM loan WHERE item_code = ? AND is_return = 0 ORDER BY loan_date DESC LIMIT 1",
@@ -291,31 +368,101 @@ async fn list_items(
                     loan_status_cache.insert(code.clone(), row.clone());
                     loan_status = Some(row);
                 }
-            }
         }
 
-        let response = ItemResponse {
-            item,
-            biblio,
-            coll_type,
-            location,
-            item_status,
-            loan_status,
-            custom,
-        };
-
-        data.push(resource_with_fields(
-            "items",
-            response.item.item_id.to_string(),
-            response,
-            item_fields,
+        data.push((
+            ItemResponse { item, custom },
+            ItemRelated {
+                biblio,
+                coll_type,
+                location,
+                item_status,
+                loan_status: includes.contains("loan_status").then_some(loan_status),
+            },
         ));
     }
 
-    Ok(Json(collection_document(
-        data,
-        pagination_meta(page, per_page, total),
-    )))
+    Ok(data)
+}
+
+/// Builds the `items` resource object. `loan_status`, the current loan, has
+/// no foreign key and only appears when included.
+fn item_resource(
+    response: ItemResponse,
+    related: ItemRelated,
+    params: &ListParams,
+    included: &mut Included,
+) -> JsonValue {
+    let item = &response.item;
+
+    let biblio = match related.biblio {
+        Some(biblio) => Some(included.resource(
+            "biblios",
+            biblio.biblio_id.to_string(),
+            &biblio,
+            params.fieldset("biblios"),
+        )),
+        None => item
+            .biblio_id
+            .map(|id| identifier("biblios", id.to_string())),
+    };
+    let coll_type = match related.coll_type {
+        Some(coll_type) => Some(included.resource(
+            "coll-types",
+            coll_type.coll_type_id.to_string(),
+            &coll_type,
+            params.fieldset("coll-types"),
+        )),
+        None => item
+            .coll_type_id
+            .map(|id| identifier("coll-types", id.to_string())),
+    };
+    let location = match related.location {
+        Some(location) => Some(included.resource(
+            "locations",
+            location.location_id.clone(),
+            &location,
+            params.fieldset("locations"),
+        )),
+        None => item
+            .location_id
+            .as_deref()
+            .filter(|id| !id.is_empty())
+            .map(|id| identifier("locations", id)),
+    };
+    let item_status = match related.item_status {
+        Some(item_status) => Some(included.resource(
+            "item-statuses",
+            item_status.item_status_id.clone(),
+            &item_status,
+            params.fieldset("item-statuses"),
+        )),
+        None => item
+            .item_status_id
+            .as_deref()
+            .filter(|id| !id.is_empty())
+            .map(|id| identifier("item-statuses", id)),
+    };
+
+    let id = item.item_id.to_string();
+    let mut builder = ResourceBuilder::new("items", id, response, params.fieldset("items"))
+        .has_one("biblio", biblio)
+        .has_one("coll_type", coll_type)
+        .has_one("location", location)
+        .has_one("item_status", item_status);
+    if let Some(loan) = related.loan_status {
+        let loan = loan.map(|loan| {
+            included.resource(
+                "loans",
+                loan.loan_id.to_string(),
+                &loan,
+                params.fieldset("loans"),
+            )
+        });
+        builder = builder.has_one("loan_status", loan);
+    }
+
+    builder.build()
 }
 
 #[utoipa::path(
@@ -341,99 +488,15 @@ async fn get_item(
     .fetch_one(&state.pool)
     .await?;
 
-    let includes = params.includes();
-
-    let mut biblio = None;
-    if includes.contains("biblio") {
-        if let Some(biblio_id) = item.biblio_id {
-            biblio = sqlx::query_as::<_, BiblioSummary>(
-                "SELECT biblio_id, title FROM biblio WHERE biblio_id = ?",
-            )
-            .bind(biblio_id)
-            .fetch_optional(&state.pool)
-            .await?;
-        }
-    }
+    let includes = params.checked_includes(ITEM_INCLUDES)?;
+    let (item, related) = enrich_items(&state, &includes, vec![item])
+        .await?
+        .pop()
+        .expect("one item in, one out");
+    let mut included = Included::default();
+    let resource = item_resource(item, related, &params, &mut included);
 
-    let mut coll_type = None;
-    if includes.contains("coll_type") {
-        if let Some(coll_type_id) = item.coll_type_id {
-            coll_type = sqlx::query_as::<_, CollTypeSummary>(
-                "SELECT coll_type_id, coll_type_name FROM mst_coll_type WHERE coll_type_id = ?",
-            )
-            .bind(coll_type_id)
-            .fetch_optional(&state.pool)
-            .await?;
-        }
-    }
-
-    let mut location = None;
-    if includes.contains("location") {
-        if let Some(loc_id) = item.location_id.clone() {
-            location = sqlx::query_as::<_, LocationSummary>(
-                "SELECT location_id, location_name FROM mst_location WHERE location_id = ?",
-            )
-            .bind(&loc_id)
-            .fetch_optional(&state.pool)
-            .await?;
-        }
-    }
-
-    let mut item_status = None;
-    if includes.contains("item_status") {
-        if let Some(status_id) = item.item_status_id.clone() {
-            item_status = sqlx::query_as::<_, ItemStatusSummary>(
-                "SELECT item_status_id, item_status_name, no_loan FROM mst_item_status WHERE item_status_id = ?",
-            )
-            .bind(&status_id)
-            .fetch_optional(&state.pool)
-            .await?;
-        }
-    }
-
-    let mut loan_status = None;
-    if includes.contains("loan_status") {
-        if let Some(code) = item.item_code.clone() {
-            loan_status = sqlx::query_as::<_, LoanStatusSummary>(
-                "SELECT loan_id, item_code, member_id, loan_date, due_date, is_return, return_date FROM loan WHERE item_code = ? AND is_return = 0 ORDER BY loan_date DESC LIMIT 1",
-            )
-            .bind(&code)
-            .fetch_optional(&state.pool)
-            .await?;
-        }
-    }
-
-    let custom = if includes.contains("custom") {
-        if let Some(row) = sqlx::query("SELECT * FROM item_custom WHERE item_id = ?")
-            .bind(item.item_id)
-            .fetch_optional(&state.pool)
-            .await?
-        {
-            Some(row_to_json(&row))
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    let response = ItemResponse {
-        item,
-        biblio,
-        coll_type,
-        location,
-        item_status,
-        loan_status,
-        custom,
-    };
-
-    let item_fields = params.fieldset("items");
-    Ok(Json(single_document(resource_with_fields(
-        "items",
-        response.item.item_id.to_string(),
-        response,
-        item_fields,
-    ))))
+    Ok(Json(single_document(resource).with_included(included)))
 }
 
 fn row_to_json(row: &MySqlRow) -> JsonValue {
@@ -449,7 +512,7 @@ fn row_to_json(row: &MySqlRow) -> JsonValue {
 #[utoipa::path(
     post,
     path = "/items",
-    request_body = CreateItem,
+    request_body(content = CreateItem, content_type = "application/vnd.api+json"),
     responses((status = 200, body = JsonApiDocument)),
     security(("bearerAuth" = [])),
     tag = "Items"
@@ -457,12 +520,16 @@ fn row_to_json(row: &MySqlRow) -> JsonValue {
 async fn create_item(
     State(state): State<AppState>,
     auth: AuthUser,
-    Json(payload): Json<CreateItem>,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<CreateItem>,
 ) -> Result<Json<JsonApiDocument>, AppError> {
     auth.require_access(ModuleAccess::Bibliography, Permission::Write)?;
 
     let now = chrono::Utc::now().naive_utc();
 
+    let mut tx = state.pool.begin().await?;
     let result = sqlx::query(
         "INSERT INTO item (item_code, biblio_id, call_number, coll_type_id, location_id, item_status_id, input_date) VALUES (?, ?, ?, ?, ?, ?, ?)",
     )
@@ -473,15 +540,34 @@ async fn create_item(
     .bind(&payload.location_id)
     .bind(&payload.item_status_id)
     .bind(now)
-    .execute(&state.pool)
+    .execute(&mut *tx)
     .await?;
 
     let rec = sqlx::query_as::<_, Item>(
         "SELECT item_id, item_code, biblio_id, call_number, coll_type_id, location_id, item_status_id, last_update FROM item WHERE item_id = ?",
     )
     .bind(result.last_insert_id() as i64)
-    .fetch_one(&state.pool)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    write_system_log(
+        &mut tx,
+        &SystemLogEntry::by(
+            &auth,
+            "bibliography",
+            "item",
+            "create",
+            format!(
+                "{} created item {} ({}) for biblio {}",
+                auth.claims.username,
+                rec.item_id,
+                rec.item_code.as_deref().unwrap_or("no code"),
+                rec.biblio_id.map(|id| id.to_string()).unwrap_or_default()
+            ),
+        ),
+    )
     .await?;
+    tx.commit().await?;
 
     Ok(Json(single_document(resource(
         "items",
@@ -494,7 +580,28 @@ async fn create_item(
     put,
     path = "/items/{item_id}",
     params(("item_id" = i64, Path, description = "Item ID")),
-    request_body = CreateItem,
+    request_body(content = CreateItem, content_type = "application/vnd.api+json"),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Items"
+)]
+async fn replace_item(
+    State(state): State<AppState>,
+    Path(item_id): Path<i64>,
+    auth: AuthUser,
+    payload: JsonApi<CreateItem>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Bibliography, Permission::Write)?;
+    let payload = payload.for_id(&item_id.to_string())?;
+
+    apply_item_update(&state, item_id, &auth, payload.into()).await
+}
+
+#[utoipa::path(
+    patch,
+    path = "/items/{item_id}",
+    params(("item_id" = i64, Path, description = "Item ID")),
+    request_body(content = UpdateItem, content_type = "application/vnd.api+json"),
     responses((status = 200, body = JsonApiDocument)),
     security(("bearerAuth" = [])),
     tag = "Items"
@@ -503,33 +610,76 @@ async fn update_item(
     State(state): State<AppState>,
     Path(item_id): Path<i64>,
     auth: AuthUser,
-    Json(payload): Json<CreateItem>,
+    payload: JsonApi<UpdateItem>,
 ) -> Result<Json<JsonApiDocument>, AppError> {
     auth.require_access(ModuleAccess::Bibliography, Permission::Write)?;
+    let payload = payload.for_id(&item_id.to_string())?;
 
-    let updated = sqlx::query(
-        "UPDATE item SET item_code = ?, biblio_id = ?, call_number = ?, coll_type_id = ?, location_id = ?, item_status_id = ?, last_update = NOW() WHERE item_id = ?",
-    )
-    .bind(&payload.item_code)
-    .bind(payload.biblio_id)
-    .bind(&payload.call_number)
-    .bind(payload.coll_type_id)
-    .bind(&payload.location_id)
-    .bind(&payload.item_status_id)
-    .bind(item_id)
-    .execute(&state.pool)
-    .await?;
+    apply_item_update(&state, item_id, &auth, payload).await
+}
 
-    if updated.rows_affected() == 0 {
-        return Err(AppError::NotFound);
+async fn apply_item_update(
+    state: &AppState,
+    item_id: i64,
+    auth: &AuthUser,
+    payload: UpdateItem,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let mut set = PatchSet::default();
+    set.nullable("item_code", payload.item_code);
+    set.nullable("biblio_id", payload.biblio_id);
+    set.nullable("call_number", payload.call_number);
+    set.nullable("coll_type_id", payload.coll_type_id);
+    set.nullable("location_id", payload.location_id);
+    set.nullable("item_status_id", payload.item_status_id);
+
+    // An empty PATCH changes nothing and just returns the item.
+    let changed = !set.is_empty();
+    let mut tx = state.pool.begin().await?;
+    if changed {
+        set.touch("last_update = NOW()");
+        let sql = set.update_sql("item", "item_id");
+        let updated = set
+            .bind(sqlx::query(&sql))
+            .bind(item_id)
+            .execute(&mut *tx)
+            .await?;
+
+        if updated.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
     }
 
     let rec = sqlx::query_as::<_, Item>(
         "SELECT item_id, item_code, biblio_id, call_number, coll_type_id, location_id, item_status_id, last_update FROM item WHERE item_id = ?",
     )
     .bind(item_id)
-    .fetch_one(&state.pool)
+    .fetch_one(&mut *tx)
     .await?;
+    if !changed {
+        return Ok(Json(single_document(resource(
+            "items",
+            rec.item_id.to_string(),
+            rec,
+        ))));
+    }
+
+    write_system_log(
+        &mut tx,
+        &SystemLogEntry::by(
+            auth,
+            "bibliography",
+            "item",
+            "update",
+            format!(
+                "{} updated item {} ({})",
+                auth.claims.username,
+                rec.item_id,
+                rec.item_code.as_deref().unwrap_or("no code")
+            ),
+        ),
+    )
+    .await?;
+    tx.commit().await?;
 
     Ok(Json(single_document(resource(
         "items",
@@ -553,10 +703,36 @@ async fn delete_item(
 ) -> Result<StatusCode, AppError> {
     auth.require_access(ModuleAccess::Bibliography, Permission::Write)?;
 
-    sqlx::query("DELETE FROM item WHERE item_id = ?")
+    let mut tx = state.pool.begin().await?;
+    let item_code: Option<Option<String>> =
+        sqlx::query_scalar("SELECT item_code FROM item WHERE item_id = ? FOR UPDATE")
+            .bind(item_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+    let deleted = sqlx::query("DELETE FROM item WHERE item_id = ?")
         .bind(item_id)
-        .execute(&state.pool)
+        .execute(&mut *tx)
         .await?;
 
+    if deleted.rows_affected() > 0 {
+        write_system_log(
+            &mut tx,
+            &SystemLogEntry::by(
+                &auth,
+                "bibliography",
+                "item",
+                "delete",
+                format!(
+                    "{} deleted item {} ({})",
+                    auth.claims.username,
+                    item_id,
+                    item_code.flatten().as_deref().unwrap_or("no code")
+                ),
+            ),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
     Ok(StatusCode::NO_CONTENT)
 }
@@ -1,25 +1,30 @@
 use axum::{
     Json, Router,
     extract::{Path, Query, State},
+    http::StatusCode,
     routing::{get, post},
 };
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
-use std::collections::HashMap;
+use serde_json::{Value as JsonValue, json};
+use sqlx::{Acquire, FromRow, MySql, MySqlConnection, Transaction};
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 use utoipa::ToSchema;
 
 use crate::{
+    audit::{SystemLogEntry, write_system_log},
     auth::{AuthUser, ModuleAccess, Permission},
+    circulation::{self, LoanEvent},
     config::AppState,
     error::AppError,
     jsonapi::{
-        JsonApiDocument, collection_document, pagination_meta, resource, resource_with_fields,
-        single_document,
+        Included, JsonApi, JsonApiDocument, ResourceBuilder, ResourcePayload, collection_document,
+        identifier, pagination_meta, resource, resource_with_fields, single_document,
     },
     resources::{
-        bind_filters_to_query, bind_filters_to_scalar, where_clause, FilterField, FilterOperator,
-        FilterValueType, ListParams, SortField,
+        FilterClause, FilterField, FilterOperator, FilterValue, FilterValueType, ListParams,
+        MATCH_OPERATORS, NULLABLE_RANGE_OPERATORS, RANGE_OPERATORS, SortField,
+        bind_filters_to_query, bind_filters_to_scalar, where_clause,
     },
 };
 
@@ -30,6 +35,8 @@ pub struct Loan {
     pub member_id: Option<String>,
     pub loan_date: NaiveDate,
     pub due_date: NaiveDate,
+    pub renewed: i32,
+    pub loan_rules_id: i64,
     pub actual: Option<NaiveDate>,
     pub return_date: Option<NaiveDate>,
     pub is_return: i32,
@@ -39,7 +46,13 @@ pub struct Loan {
 pub struct CreateLoan {
     pub item_code: String,
     pub member_id: String,
-    pub due_date: NaiveDate,
+    /// Overrides the due date computed from the loan rules; the override is
+    /// written to `system_log`.
+    pub due_date: Option<NaiveDate>,
+}
+
+impl ResourcePayload for CreateLoan {
+    const TYPE: &'static str = "loans";
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow, ToSchema)]
@@ -54,14 +67,183 @@ pub struct LoanItem {
     pub item_code: Option<String>,
 }
 
+const LOAN_INCLUDES: &[&str] = &["member", "item"];
+
 #[derive(Debug, Serialize, ToSchema)]
-pub struct LoanResponse {
+pub struct ReturnedLoan {
     #[serde(flatten)]
     pub loan: Loan,
+    /// Late days after the due date. Closed days are left out unless the
+    /// `ignore_holidays_fine_calc` setting is on.
+    pub overdue_days: i64,
+    /// Amount debited to the member's fines for this return.
+    pub fine: i64,
+    /// Set when the returned copy satisfies a waiting reservation and should
+    /// be put aside instead of shelved.
+    pub on_hold: bool,
+    pub waiting_hold: Option<circulation::WaitingHold>,
+}
+
+/// Snapshot row from `loan_history`, kept even after the item, biblio or
+/// member is deleted.
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct LoanHistory {
+    pub loan_id: i64,
+    pub item_code: Option<String>,
+    pub biblio_id: i64,
+    pub title: Option<String>,
+    pub call_number: Option<String>,
+    pub classification: Option<String>,
+    pub gmd_name: Option<String>,
+    pub language_name: Option<String>,
+    pub location_name: Option<String>,
+    pub collection_type_name: Option<String>,
+    pub member_id: Option<String>,
+    pub member_name: Option<String>,
+    pub member_type_name: Option<String>,
+    pub loan_date: Option<NaiveDate>,
+    pub due_date: Option<NaiveDate>,
+    pub renewed: i32,
+    pub is_return: i32,
+    pub return_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchCheckout {
+    pub member_id: String,
+    pub item_codes: Vec<String>,
+    /// Keep the items that succeeded even when others fail.
+    #[serde(default)]
+    pub allow_partial: bool,
+}
+
+impl ResourcePayload for BatchCheckout {
+    const TYPE: &'static str = "batch-checkouts";
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchCheckin {
+    pub item_codes: Vec<String>,
+    #[serde(default)]
+    pub allow_partial: bool,
+}
+
+impl ResourcePayload for BatchCheckin {
+    const TYPE: &'static str = "batch-checkins";
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItemStatus {
+    Done,
+    Failed,
+    /// The item would have succeeded, but another item failed and the batch
+    /// was rolled back.
+    RolledBack,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchItemError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchItemResult {
+    pub item_code: String,
+    pub status: BatchItemStatus,
+    /// The loan as written: a `Loan` for checkouts, a `ReturnedLoan` for
+    /// checkins.
+    #[schema(value_type = Object, nullable)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub member: Option<LoanMember>,
+    pub loan: Option<JsonValue>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub item: Option<LoanItem>,
+    pub error: Option<BatchItemError>,
+}
+
+impl BatchItemResult {
+    fn done<T: Serialize>(item_code: &str, loan: &T) -> Self {
+        BatchItemResult {
+            item_code: item_code.to_string(),
+            status: BatchItemStatus::Done,
+            loan: serde_json::to_value(loan).ok(),
+            error: None,
+        }
+    }
+
+    fn failed(item_code: &str, error: BatchItemError) -> Self {
+        BatchItemResult {
+            item_code: item_code.to_string(),
+            status: BatchItemStatus::Failed,
+            loan: None,
+            error: Some(error),
+        }
+    }
+}
+
+const MAX_BATCH_ITEMS: usize = 50;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct OverdueParams {
+    /// `member` returns one resource per member with their overdue loans.
+    pub group: Option<String>,
+    #[serde(flatten)]
+    pub list: ListParams,
+}
+
+#[derive(Debug, FromRow)]
+struct OverdueRow {
+    loan_id: i64,
+    item_code: Option<String>,
+    member_id: Option<String>,
+    member_name: Option<String>,
+    member_email: Option<String>,
+    member_phone: Option<String>,
+    member_type_id: Option<i32>,
+    loan_date: NaiveDate,
+    due_date: NaiveDate,
+    loan_rules_id: i64,
+    title: Option<String>,
+    call_number: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OverdueMember {
+    pub member_id: Option<String>,
+    pub member_name: Option<String>,
+    pub member_email: Option<String>,
+    pub member_phone: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OverdueItem {
+    pub item_code: Option<String>,
+    pub title: Option<String>,
+    pub call_number: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OverdueLoan {
+    pub loan_id: i64,
+    pub loan_date: NaiveDate,
+    pub due_date: NaiveDate,
+    /// Late days so far, counted like the fine on return.
+    pub overdue_days: i64,
+    /// Fine the member would be charged if the item came back today.
+    pub estimated_fine: i64,
+    /// Left out when grouping by member.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member: Option<OverdueMember>,
+    pub item: OverdueItem,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OverdueMemberGroup {
+    #[serde(flatten)]
+    pub member: OverdueMember,
+    pub total_estimated_fine: i64,
+    pub loans: Vec<OverdueLoan>,
 }
 
 const LOAN_SORTS: &[SortField<'_>] = &[
@@ -77,25 +259,148 @@ const LOAN_FILTERS: &[FilterField<'_>] = &[
         "loan.item_code",
         FilterOperator::Equals,
         FilterValueType::Text,
-    ),
+    )
+    .with_operators(MATCH_OPERATORS),
     FilterField::new(
         "member_id",
         "loan.member_id",
         FilterOperator::Equals,
         FilterValueType::Text,
-    ),
+    )
+    .with_operators(MATCH_OPERATORS),
     FilterField::new(
         "is_return",
         "loan.is_return",
         FilterOperator::Equals,
         FilterValueType::Boolean,
     ),
+    FilterField::new(
+        "loan_date",
+        "loan.loan_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(RANGE_OPERATORS),
+    FilterField::new(
+        "due_date",
+        "loan.due_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(RANGE_OPERATORS),
+    FilterField::new(
+        "return_date",
+        "loan.return_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(NULLABLE_RANGE_OPERATORS),
+];
+
+const HISTORY_SORTS: &[SortField<'_>] = &[
+    SortField::new("loan_date", "loan_history.loan_date"),
+    SortField::new("due_date", "loan_history.due_date"),
+    SortField::new("return_date", "loan_history.return_date"),
+    SortField::new("loan_id", "loan_history.loan_id"),
+    SortField::new("member_name", "loan_history.member_name"),
+    SortField::new("title", "loan_history.title"),
 ];
 
+const HISTORY_FILTERS: &[FilterField<'_>] = &[
+    FilterField::new(
+        "item_code",
+        "loan_history.item_code",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    )
+    .with_operators(MATCH_OPERATORS),
+    FilterField::new(
+        "member_id",
+        "loan_history.member_id",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    )
+    .with_operators(MATCH_OPERATORS),
+    FilterField::new(
+        "biblio_id",
+        "loan_history.biblio_id",
+        FilterOperator::Equals,
+        FilterValueType::Integer,
+    ),
+    FilterField::new(
+        "is_return",
+        "loan_history.is_return",
+        FilterOperator::Equals,
+        FilterValueType::Boolean,
+    ),
+    FilterField::new(
+        "title",
+        "loan_history.title",
+        FilterOperator::Like,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "loan_date",
+        "loan_history.loan_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(RANGE_OPERATORS),
+    FilterField::new(
+        "due_date",
+        "loan_history.due_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(RANGE_OPERATORS),
+    FilterField::new(
+        "return_date",
+        "loan_history.return_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(NULLABLE_RANGE_OPERATORS),
+];
+
+const OVERDUE_SORTS: &[SortField<'_>] = &[
+    SortField::new("due_date", "loan.due_date"),
+    SortField::new("loan_date", "loan.loan_date"),
+    SortField::new("member_id", "loan.member_id"),
+    SortField::new("loan_id", "loan.loan_id"),
+];
+
+const OVERDUE_FILTERS: &[FilterField<'_>] = &[
+    FilterField::new(
+        "item_code",
+        "loan.item_code",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "member_id",
+        "loan.member_id",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "member_type_id",
+        "m.member_type_id",
+        FilterOperator::Equals,
+        FilterValueType::Integer,
+    ),
+];
+
+const OVERDUE_SELECT: &str = "SELECT loan.loan_id, loan.item_code, loan.member_id, m.member_name, m.member_email, m.member_phone, m.member_type_id, loan.loan_date, loan.due_date, loan.loan_rules_id, b.title, COALESCE(NULLIF(i.call_number, ''), b.call_number) AS call_number FROM loan LEFT JOIN member m ON m.member_id = loan.member_id LEFT JOIN item i ON i.item_code = loan.item_code LEFT JOIN biblio b ON b.biblio_id = i.biblio_id";
+
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/", get(list_loans).post(create_loan))
+        .route("/history", get(list_loan_history))
+        .route("/overdue", get(list_overdue_loans))
+        .route("/batch/checkout", post(batch_checkout))
+        .route("/batch/checkin", post(batch_checkin))
         .route("/:loan_id/return", post(return_loan))
+        .route("/:loan_id/renew", post(renew_loan))
 }
 
 #[utoipa::path(
@@ -113,8 +418,7 @@ async fn list_loans(
     auth.require_access(ModuleAccess::Circulation, Permission::Read)?;
 
     let pagination = params.pagination();
-    let includes = params.includes();
-    let loan_fields = params.fieldset("loans");
+    let includes = params.checked_includes(LOAN_INCLUDES)?;
     let (limit, offset, page, per_page) = pagination.limit_offset();
     let sort_clause = params.sort_clause(LOAN_SORTS, "loan.loan_date DESC")?;
     let filters = params.filter_clauses(LOAN_FILTERS)?;
@@ -126,7 +430,7 @@ async fn list_loans(
         .await?;
 
     let data_sql = format!(
-        "SELECT loan_id, item_code, member_id, loan_date, due_date, actual, return_date, is_return FROM loan {} ORDER BY {} LIMIT ? OFFSET ?",
+        "SELECT loan_id, item_code, member_id, loan_date, due_date, renewed, loan_rules_id, actual, return_date, is_return FROM loan {} ORDER BY {} LIMIT ? OFFSET ?",
         where_sql, sort_clause
     );
     let loans = bind_filters_to_query(sqlx::query_as::<_, Loan>(&data_sql), &filters)
@@ -137,54 +441,363 @@ async fn list_loans(
 
     let mut member_cache: HashMap<String, LoanMember> = HashMap::new();
     let mut item_cache: HashMap<String, LoanItem> = HashMap::new();
+    let mut included = Included::default();
     let mut data = Vec::with_capacity(loans.len());
 
     for loan in loans {
         let mut member = None;
-        if includes.contains("member") {
-            if let Some(member_id) = loan.member_id.clone() {
-                if let Some(existing) = member_cache.get(&member_id) {
-                    member = Some(existing.clone());
-                } else if let Some(row) = sqlx::query_as::<_, LoanMember>(
-                    "SELECT member_id, member_name FROM member WHERE member_id = ?",
-                )
-                .bind(&member_id)
-                .fetch_optional(&state.pool)
-                .await?
-                {
-                    member_cache.insert(member_id.clone(), row.clone());
-                    member = Some(row);
-                }
+        if includes.contains("member")
+            && let Some(member_id) = loan.member_id.clone()
+        {
+            if let Some(existing) = member_cache.get(&member_id) {
+                member = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, LoanMember>(
+                "SELECT member_id, member_name FROM member WHERE member_id = ?",
+            )
+            .bind(&member_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                member_cache.insert(member_id.clone(), row.clone());
+                member = Some(row);
             }
         }
 
         let mut item = None;
+        if includes.contains("item")
+            && let Some(code) = loan.item_code.clone()
+        {
+            if let Some(existing) = item_cache.get(&code) {
+                item = Some(existing.clone());
+            } else if let Some(row) = sqlx::query_as::<_, LoanItem>(
+                "SELECT item_id, item_code FROM item WHERE item_code = ?",
+            )
+            .bind(&code)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                item_cache.insert(code.clone(), row.clone());
+                item = Some(row);
+            }
+        }
+
+        // The member ID is the `members` resource ID, so its linkage is
+        // known without the include; the item is keyed by code, not by ID.
+        let member = match member {
+            Some(member) => Some(included.resource(
+                "members",
+                member.member_id.clone(),
+                &member,
+                params.fieldset("members"),
+            )),
+            None => loan
+                .member_id
+                .as_deref()
+                .map(|id| identifier("members", id)),
+        };
+        let mut builder = ResourceBuilder::new(
+            "loans",
+            loan.loan_id.to_string(),
+            loan,
+            params.fieldset("loans"),
+        )
+        .has_one("member", member);
         if includes.contains("item") {
-            if let Some(code) = loan.item_code.clone() {
-                if let Some(existing) = item_cache.get(&code) {
-                    item = Some(existing.clone());
-                } else if let Some(row) = sqlx::query_as::<_, LoanItem>(
-                    "SELECT item_id, item_code FROM item WHERE item_code = ?",
+            let item = item.map(|item| {
+                included.resource(
+                    "items",
+                    item.item_id.to_string(),
+                    &item,
+                    params.fieldset("items"),
                 )
-                .bind(&code)
-                .fetch_optional(&state.pool)
-                .await?
+            });
+            builder = builder.has_one("item", item);
+        }
+        data.push(builder.build());
+    }
+
+    Ok(Json(
+        collection_document(data, pagination_meta(page, per_page, total)).with_included(included),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/loans/history",
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Loans"
+)]
+async fn list_loan_history(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Read)?;
+
+    let pagination = params.pagination();
+    let history_fields = params.fieldset("loan-history");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause = params.sort_clause(HISTORY_SORTS, "loan_history.loan_date DESC")?;
+    let filters = params.filter_clauses(HISTORY_FILTERS)?;
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM loan_history {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "SELECT loan_id, item_code, biblio_id, title, call_number, classification, gmd_name, language_name, location_name, collection_type_name, member_id, member_name, member_type_name, loan_date, due_date, renewed, is_return, return_date FROM loan_history {} ORDER BY {} LIMIT ? OFFSET ?",
+        where_sql, sort_clause
+    );
+    let rows = bind_filters_to_query(sqlx::query_as::<_, LoanHistory>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = rows
+        .into_iter()
+        .map(|row| {
+            resource_with_fields("loan-history", row.loan_id.to_string(), row, history_fields)
+        })
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+/// Prices overdue loans the same way `return_loan` would, caching the terms
+/// of each member type and loan rule pair.
+struct FineEstimator {
+    calendar: circulation::HolidayCalendar,
+    count_closed_days: bool,
+    today: NaiveDate,
+    terms: HashMap<(i32, i64), Option<circulation::LoanTerms>>,
+}
+
+impl FineEstimator {
+    async fn load(conn: &mut MySqlConnection, today: NaiveDate) -> Result<Self, AppError> {
+        Ok(FineEstimator {
+            calendar: circulation::HolidayCalendar::load(conn).await?,
+            count_closed_days: circulation::ignore_holidays_for_fines(conn).await?,
+            today,
+            terms: HashMap::new(),
+        })
+    }
+
+    async fn terms(
+        &mut self,
+        conn: &mut MySqlConnection,
+        member_type_id: i32,
+        loan_rules_id: i64,
+    ) -> Result<Option<circulation::LoanTerms>, AppError> {
+        match self.terms.entry((member_type_id, loan_rules_id)) {
+            Entry::Occupied(entry) => Ok(*entry.get()),
+            Entry::Vacant(entry) => {
+                let terms = match circulation::loan_terms(conn, member_type_id, loan_rules_id).await
                 {
-                    item_cache.insert(code.clone(), row.clone());
-                    item = Some(row);
-                }
+                    Ok(terms) => Some(terms),
+                    Err(AppError::Policy(_)) => None,
+                    Err(err) => return Err(err),
+                };
+                Ok(*entry.insert(terms))
             }
         }
+    }
 
-        let response = LoanResponse { loan, member, item };
-        data.push(resource_with_fields(
-            "loans",
-            response.loan.loan_id.to_string(),
-            response,
-            loan_fields,
-        ));
+    async fn overdue_loan(
+        &mut self,
+        conn: &mut MySqlConnection,
+        row: OverdueRow,
+        with_member: bool,
+    ) -> Result<OverdueLoan, AppError> {
+        let overdue_days =
+            self.calendar
+                .overdue_days(row.due_date, self.today, self.count_closed_days);
+
+        let estimated_fine = match row.member_type_id {
+            Some(member_type_id) => self
+                .terms(conn, member_type_id, row.loan_rules_id)
+                .await?
+                .map(|terms| terms.fine_for(overdue_days))
+                .unwrap_or(0),
+            None => 0,
+        };
+
+        let member = with_member.then_some(OverdueMember {
+            member_id: row.member_id,
+            member_name: row.member_name,
+            member_email: row.member_email,
+            member_phone: row.member_phone,
+        });
+
+        Ok(OverdueLoan {
+            loan_id: row.loan_id,
+            loan_date: row.loan_date,
+            due_date: row.due_date,
+            overdue_days,
+            estimated_fine,
+            member,
+            item: OverdueItem {
+                item_code: row.item_code,
+                title: row.title,
+                call_number: row.call_number,
+            },
+        })
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/loans/overdue",
+    params(("group" = Option<String>, Query, description = "Set to `member` to group overdue loans by member")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Loans"
+)]
+async fn list_overdue_loans(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<OverdueParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Read)?;
+
+    let group_by_member = match params.group.as_deref() {
+        None | Some("") => false,
+        Some("member") => true,
+        Some(other) => {
+            return Err(AppError::BadRequest(format!(
+                "grouping by `{}` is not supported",
+                other
+            )));
+        }
+    };
+
+    let today = chrono::Utc::now().date_naive();
+    let list = &params.list;
+    let (limit, offset, page, per_page) = list.pagination().limit_offset();
+    let sort_clause = list.sort_clause(OVERDUE_SORTS, "loan.due_date ASC, loan.loan_id ASC")?;
+
+    let mut filters = vec![
+        FilterClause::new("loan.is_return = ?", FilterValue::Boolean(false)),
+        FilterClause::new("loan.due_date < ?", FilterValue::Date(today)),
+    ];
+    filters.extend(list.filter_clauses(OVERDUE_FILTERS)?);
+    let where_sql = where_clause(&filters);
+    let from_sql = "FROM loan LEFT JOIN member m ON m.member_id = loan.member_id";
+
+    let mut conn = state.pool.acquire().await?;
+    let mut estimator = FineEstimator::load(&mut conn, today).await?;
+
+    if !group_by_member {
+        let fields = list.fieldset("overdue-loans");
+        let count_sql = format!("SELECT COUNT(*) {} {}", from_sql, where_sql);
+        let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+            .fetch_one(&mut *conn)
+            .await?;
+
+        let data_sql = format!(
+            "{} {} ORDER BY {} LIMIT ? OFFSET ?",
+            OVERDUE_SELECT, where_sql, sort_clause
+        );
+        let rows = bind_filters_to_query(sqlx::query_as::<_, OverdueRow>(&data_sql), &filters)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        let mut data = Vec::with_capacity(rows.len());
+        for row in rows {
+            let loan = estimator.overdue_loan(&mut conn, row, true).await?;
+            data.push(resource_with_fields(
+                "overdue-loans",
+                loan.loan_id.to_string(),
+                loan,
+                fields,
+            ));
+        }
+
+        return Ok(Json(collection_document(
+            data,
+            pagination_meta(page, per_page, total),
+        )));
+    }
+
+    // Pages are counted in members, most overdue member first, so a notice
+    // run never splits one member's loans across pages.
+    let fields = list.fieldset("overdue-members");
+    let group_where = format!("{} AND loan.member_id IS NOT NULL", where_sql);
+    let count_sql = format!(
+        "SELECT COUNT(DISTINCT loan.member_id) {} {}",
+        from_sql, group_where
+    );
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let members_sql = format!(
+        "SELECT loan.member_id {} {} GROUP BY loan.member_id ORDER BY MIN(loan.due_date) ASC, loan.member_id ASC LIMIT ? OFFSET ?",
+        from_sql, group_where
+    );
+    let member_ids =
+        bind_filters_to_scalar(sqlx::query_scalar::<_, String>(&members_sql), &filters)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&mut *conn)
+            .await?;
+
+    let mut groups: Vec<OverdueMemberGroup> = Vec::with_capacity(member_ids.len());
+    if !member_ids.is_empty() {
+        let placeholders = vec!["?"; member_ids.len()].join(", ");
+        let data_sql = format!(
+            "{} {} AND loan.member_id IN ({}) ORDER BY {}",
+            OVERDUE_SELECT, where_sql, placeholders, sort_clause
+        );
+        let mut query = bind_filters_to_query(sqlx::query_as::<_, OverdueRow>(&data_sql), &filters);
+        for member_id in &member_ids {
+            query = query.bind(member_id);
+        }
+        let rows = query.fetch_all(&mut *conn).await?;
+
+        let mut by_member: HashMap<String, OverdueMemberGroup> = HashMap::new();
+        for row in rows {
+            let member = OverdueMember {
+                member_id: row.member_id.clone(),
+                member_name: row.member_name.clone(),
+                member_email: row.member_email.clone(),
+                member_phone: row.member_phone.clone(),
+            };
+            let loan = estimator.overdue_loan(&mut conn, row, false).await?;
+            let group = by_member
+                .entry(member.member_id.clone().unwrap_or_default())
+                .or_insert_with(|| OverdueMemberGroup {
+                    member,
+                    total_estimated_fine: 0,
+                    loans: Vec::new(),
+                });
+            group.total_estimated_fine += loan.estimated_fine;
+            group.loans.push(loan);
+        }
+
+        for member_id in &member_ids {
+            if let Some(group) = by_member.remove(member_id) {
+                groups.push(group);
+            }
+        }
     }
 
+    let data = groups
+        .into_iter()
+        .map(|group| {
+            let id = group.member.member_id.clone().unwrap_or_default();
+            resource_with_fields("overdue-members", id, group, fields)
+        })
+        .collect();
+
     Ok(Json(collection_document(
         data,
         pagination_meta(page, per_page, total),
@@ -194,7 +807,7 @@ async fn list_loans(
 #[utoipa::path(
     post,
     path = "/loans",
-    request_body = CreateLoan,
+    request_body(content = CreateLoan, content_type = "application/vnd.api+json"),
     responses((status = 200, body = JsonApiDocument)),
     security(("bearerAuth" = [])),
     tag = "Loans"
@@ -202,28 +815,57 @@ async fn list_loans(
 async fn create_loan(
     State(state): State<AppState>,
     auth: AuthUser,
-    Json(payload): Json<CreateLoan>,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<CreateLoan>,
 ) -> Result<Json<JsonApiDocument>, AppError> {
     auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    state.require_transactional_tables()?;
 
     let today = chrono::Utc::now().date_naive();
+    let now = chrono::Utc::now().naive_utc();
 
-    let result = sqlx::query(
-        "INSERT INTO loan (item_code, member_id, loan_date, due_date, is_lent, is_return) VALUES (?, ?, ?, ?, 1, 0)",
-    )
-    .bind(&payload.item_code)
-    .bind(&payload.member_id)
-    .bind(today)
-    .bind(payload.due_date)
-    .execute(&state.pool)
-    .await?;
+    let mut tx = state.pool.begin().await?;
+    let plan =
+        circulation::plan_checkout(&mut tx, &payload.member_id, &payload.item_code, today).await?;
 
-    let rec = sqlx::query_as::<_, Loan>(
-        "SELECT loan_id, item_code, member_id, loan_date, due_date, actual, return_date, is_return FROM loan WHERE loan_id = ?",
-    )
-    .bind(result.last_insert_id() as i64)
-    .fetch_one(&state.pool)
-    .await?;
+    let due_date = match payload.due_date {
+        Some(requested) if requested != plan.due_date => {
+            if requested < today {
+                return Err(AppError::BadRequest(
+                    "due_date cannot be before the loan date".into(),
+                ));
+            }
+            write_system_log(
+                &mut tx,
+                &SystemLogEntry::by(
+                    &auth,
+                    "circulation",
+                    "loan",
+                    "due_date_override",
+                    format!(
+                        "{} overrode due date of item {} for member {} from {} to {}",
+                        auth.claims.username,
+                        plan.item.item_code,
+                        plan.borrower.member_id,
+                        plan.due_date,
+                        requested
+                    ),
+                ),
+            )
+            .await?;
+            requested
+        }
+        _ => plan.due_date,
+    };
+
+    let loan_id =
+        circulation::checkout(&mut tx, &plan, due_date, auth.claims.sub, today, now).await?;
+    let rec = fetch_loan(&mut tx, loan_id).await?;
+    log_loan(&mut tx, &auth, &rec.event("checkout")).await?;
+
+    tx.commit().await?;
 
     Ok(Json(single_document(resource(
         "loans",
@@ -246,27 +888,52 @@ async fn return_loan(
     auth: AuthUser,
 ) -> Result<Json<JsonApiDocument>, AppError> {
     auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    state.require_transactional_tables()?;
 
     let today = chrono::Utc::now().date_naive();
+    let now = chrono::Utc::now().naive_utc();
 
-    let updated =
-        sqlx::query("UPDATE loan SET return_date = ?, is_return = 1, actual = ? WHERE loan_id = ?")
-            .bind(today)
-            .bind(today)
-            .bind(loan_id)
-            .execute(&state.pool)
-            .await?;
+    let mut tx = state.pool.begin().await?;
+    let response = return_one(&mut tx, loan_id, auth.claims.sub, today, now).await?;
+    log_loan(&mut tx, &auth, &response.loan.event("return")).await?;
+    tx.commit().await?;
 
-    if updated.rows_affected() == 0 {
-        return Err(AppError::NotFound);
-    }
+    Ok(Json(single_document(resource(
+        "loans",
+        response.loan.loan_id.to_string(),
+        response,
+    ))))
+}
 
-    let rec = sqlx::query_as::<_, Loan>(
-        "SELECT loan_id, item_code, member_id, loan_date, due_date, actual, return_date, is_return FROM loan WHERE loan_id = ?",
-    )
-    .bind(loan_id)
-    .fetch_one(&state.pool)
-    .await?;
+#[utoipa::path(
+    post,
+    path = "/loans/{loan_id}/renew",
+    params(("loan_id" = i64, Path, description = "Loan ID")),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 422, description = "Renewal refused by circulation policy"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Loans"
+)]
+async fn renew_loan(
+    State(state): State<AppState>,
+    Path(loan_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    state.require_transactional_tables()?;
+
+    let today = chrono::Utc::now().date_naive();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut tx = state.pool.begin().await?;
+    let plan = circulation::plan_renewal(&mut tx, loan_id, today).await?;
+    circulation::renew(&mut tx, &plan, auth.claims.sub, now).await?;
+    let rec = fetch_loan(&mut tx, plan.loan_id).await?;
+    log_loan(&mut tx, &auth, &rec.event("renew")).await?;
+
+    tx.commit().await?;
 
     Ok(Json(single_document(resource(
         "loans",
@@ -274,3 +941,272 @@ async fn return_loan(
         rec,
     ))))
 }
+
+async fn fetch_loan(conn: &mut MySqlConnection, loan_id: i64) -> Result<Loan, AppError> {
+    let loan = sqlx::query_as::<_, Loan>(
+        "SELECT loan_id, item_code, member_id, loan_date, due_date, renewed, loan_rules_id, actual, return_date, is_return FROM loan WHERE loan_id = ?",
+    )
+    .bind(loan_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(loan)
+}
+
+impl Loan {
+    fn event<'a>(&'a self, action: &'a str) -> LoanEvent<'a> {
+        LoanEvent {
+            action,
+            item_code: self.item_code.as_deref().unwrap_or_default(),
+            member_id: self.member_id.as_deref().unwrap_or_default(),
+            due_date: self.due_date,
+        }
+    }
+}
+
+/// Records a checkout, return or renewal in `system_log` under the caller.
+pub async fn log_loan(
+    conn: &mut MySqlConnection,
+    auth: &AuthUser,
+    event: &LoanEvent<'_>,
+) -> Result<(), AppError> {
+    write_system_log(
+        conn,
+        &SystemLogEntry::by(
+            auth,
+            "circulation",
+            "loan",
+            event.action,
+            event.message(&auth.claims.username),
+        ),
+    )
+    .await
+}
+
+async fn return_one(
+    conn: &mut MySqlConnection,
+    loan_id: i64,
+    uid: i64,
+    today: NaiveDate,
+    now: NaiveDateTime,
+) -> Result<ReturnedLoan, AppError> {
+    let plan = circulation::plan_return(conn, loan_id, today).await?;
+    circulation::checkin(conn, &plan, uid, today, now).await?;
+    let loan = fetch_loan(conn, plan.loan_id).await?;
+
+    Ok(ReturnedLoan {
+        loan,
+        overdue_days: plan.overdue_days,
+        fine: plan.fine,
+        on_hold: plan.waiting_hold.is_some(),
+        waiting_hold: plan.waiting_hold,
+    })
+}
+
+/// Turns a per-item failure into a batch result. Database and internal
+/// errors are not item failures and abort the whole batch instead.
+fn batch_item_error(err: AppError) -> Result<BatchItemError, AppError> {
+    match err {
+        AppError::Policy(violation) => Ok(BatchItemError {
+            code: Some(violation.code().to_string()),
+            detail: violation.to_string(),
+        }),
+        AppError::NotFound => Ok(BatchItemError {
+            code: None,
+            detail: "not found".into(),
+        }),
+        AppError::BadRequest(detail) => Ok(BatchItemError { code: None, detail }),
+        other => Err(other),
+    }
+}
+
+fn check_batch_items(item_codes: &[String]) -> Result<(), AppError> {
+    if item_codes.is_empty() {
+        return Err(AppError::BadRequest("item_codes cannot be empty".into()));
+    }
+    if item_codes.len() > MAX_BATCH_ITEMS {
+        return Err(AppError::BadRequest(format!(
+            "at most {} items can be processed in one batch",
+            MAX_BATCH_ITEMS
+        )));
+    }
+
+    let mut seen = HashSet::new();
+    for item_code in item_codes {
+        if !seen.insert(item_code.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "item `{}` appears more than once",
+                item_code
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Commits the batch when every item succeeded or partial success was
+/// requested; otherwise rolls it back and marks the successful items as
+/// rolled back. Responds 422 when nothing was written, which holds because
+/// the tables a batch writes are InnoDB (see `config::check_storage_engines`).
+async fn finish_batch(
+    tx: Transaction<'_, MySql>,
+    mut results: Vec<BatchItemResult>,
+    allow_partial: bool,
+) -> Result<(StatusCode, Json<JsonApiDocument>), AppError> {
+    let failed = results
+        .iter()
+        .filter(|result| matches!(result.status, BatchItemStatus::Failed))
+        .count();
+    let committed = failed == 0 || allow_partial;
+
+    if committed {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+        for result in results
+            .iter_mut()
+            .filter(|result| matches!(result.status, BatchItemStatus::Done))
+        {
+            result.status = BatchItemStatus::RolledBack;
+            result.loan = None;
+        }
+    }
+
+    let meta = json!({
+        "committed": committed,
+        "succeeded": if committed { results.len() - failed } else { 0 },
+        "failed": failed,
+    });
+    let data = results
+        .into_iter()
+        .map(|result| resource("batch-results", result.item_code.clone(), result))
+        .collect();
+
+    let status = if committed {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    Ok((status, Json(collection_document(data, meta))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/loans/batch/checkout",
+    request_body(content = BatchCheckout, content_type = "application/vnd.api+json"),
+    responses(
+        (status = 200, description = "Batch committed", body = JsonApiDocument),
+        (status = 422, description = "Batch rolled back", body = JsonApiDocument),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Loans"
+)]
+async fn batch_checkout(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<BatchCheckout>,
+) -> Result<(StatusCode, Json<JsonApiDocument>), AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    state.require_transactional_tables()?;
+    check_batch_items(&payload.item_codes)?;
+
+    let today = chrono::Utc::now().date_naive();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut tx = state.pool.begin().await?;
+    let item_codes: Vec<&str> = payload.item_codes.iter().map(String::as_str).collect();
+    circulation::lock_checkout(&mut tx, &payload.member_id, &item_codes).await?;
+
+    let mut results = Vec::with_capacity(payload.item_codes.len());
+    for item_code in &payload.item_codes {
+        // Each item runs in its own savepoint so a failed item leaves no
+        // partial writes behind when the rest of the batch is kept.
+        let mut savepoint = tx.begin().await?;
+        let outcome = async {
+            let plan =
+                circulation::plan_checkout(&mut savepoint, &payload.member_id, item_code, today)
+                    .await?;
+            let loan_id = circulation::checkout(
+                &mut savepoint,
+                &plan,
+                plan.due_date,
+                auth.claims.sub,
+                today,
+                now,
+            )
+            .await?;
+            let loan = fetch_loan(&mut savepoint, loan_id).await?;
+            log_loan(&mut savepoint, &auth, &loan.event("checkout")).await?;
+            Ok(loan)
+        }
+        .await;
+
+        results.push(match outcome {
+            Ok(loan) => {
+                savepoint.commit().await?;
+                BatchItemResult::done(item_code, &loan)
+            }
+            Err(err) => {
+                savepoint.rollback().await?;
+                BatchItemResult::failed(item_code, batch_item_error(err)?)
+            }
+        });
+    }
+
+    finish_batch(tx, results, payload.allow_partial).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/loans/batch/checkin",
+    request_body(content = BatchCheckin, content_type = "application/vnd.api+json"),
+    responses(
+        (status = 200, description = "Batch committed", body = JsonApiDocument),
+        (status = 422, description = "Batch rolled back", body = JsonApiDocument),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Loans"
+)]
+async fn batch_checkin(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<BatchCheckin>,
+) -> Result<(StatusCode, Json<JsonApiDocument>), AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    state.require_transactional_tables()?;
+    check_batch_items(&payload.item_codes)?;
+
+    let today = chrono::Utc::now().date_naive();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut tx = state.pool.begin().await?;
+    let mut results = Vec::with_capacity(payload.item_codes.len());
+    for item_code in &payload.item_codes {
+        let mut savepoint = tx.begin().await?;
+        let outcome = async {
+            let loan_id = circulation::open_loan_for_item(&mut savepoint, item_code).await?;
+            let returned = return_one(&mut savepoint, loan_id, auth.claims.sub, today, now).await?;
+            log_loan(&mut savepoint, &auth, &returned.loan.event("return")).await?;
+            Ok(returned)
+        }
+        .await;
+
+        results.push(match outcome {
+            Ok(returned) => {
+                savepoint.commit().await?;
+                BatchItemResult::done(item_code, &returned)
+            }
+            Err(err) => {
+                savepoint.rollback().await?;
+                BatchItemResult::failed(item_code, batch_item_error(err)?)
+            }
+        });
+    }
+
+    finish_batch(tx, results, payload.allow_partial).await
+}
@@ -162,7 +162,10 @@ where
         })
         .collect();
 
-    Ok(collection_document(data, pagination_meta(page, per_page, total)))
+    Ok(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    ))
 }
 
 pub fn router() -> Router<AppState> {
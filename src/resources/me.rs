@@ -0,0 +1,357 @@
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    routing::{get, post},
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, MySqlConnection};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    circulation::{self, LoanEvent},
+    config::AppState,
+    error::AppError,
+    jsonapi::{
+        JsonApi, JsonApiDocument, ResourcePayload, collection_document, pagination_meta, resource,
+        resource_with_fields, single_document,
+    },
+    resources::{
+        FilterClause, FilterField, FilterOperator, FilterValue, FilterValueType, ListParams,
+        RANGE_OPERATORS, SortField, bind_filters_to_query, bind_filters_to_scalar,
+        fines::{Fine, fetch_balance},
+        loans::log_loan,
+        members::Member,
+        reserves::{RESERVE_SELECT, Reserve},
+        where_clause,
+    },
+};
+
+/// A loan as the borrower sees it, with the title instead of staff-only
+/// fields such as the loan rule.
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct MemberLoan {
+    pub loan_id: i64,
+    pub item_code: Option<String>,
+    pub title: Option<String>,
+    pub loan_date: NaiveDate,
+    pub due_date: NaiveDate,
+    pub renewed: i32,
+    pub return_date: Option<NaiveDate>,
+    pub is_return: i32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RenewOwnLoan {
+    pub loan_id: i64,
+}
+
+impl ResourcePayload for RenewOwnLoan {
+    const TYPE: &'static str = "renewals";
+}
+
+const MEMBER_LOAN_SELECT: &str = "SELECT loan.loan_id, loan.item_code, b.title, loan.loan_date, loan.due_date, loan.renewed, loan.return_date, loan.is_return FROM loan LEFT JOIN item i ON i.item_code = loan.item_code LEFT JOIN biblio b ON b.biblio_id = i.biblio_id";
+
+const MEMBER_LOAN_SORTS: &[SortField<'_>] = &[
+    SortField::new("loan_date", "loan.loan_date"),
+    SortField::new("due_date", "loan.due_date"),
+];
+
+const MEMBER_LOAN_FILTERS: &[FilterField<'_>] = &[FilterField::new(
+    "is_return",
+    "loan.is_return",
+    FilterOperator::Equals,
+    FilterValueType::Boolean,
+)];
+
+const MEMBER_FINE_SORTS: &[SortField<'_>] = &[SortField::new("fines_date", "fines.fines_date")];
+
+const MEMBER_FINE_FILTERS: &[FilterField<'_>] = &[FilterField::new(
+    "fines_date",
+    "fines.fines_date",
+    FilterOperator::Equals,
+    FilterValueType::Date,
+)
+.with_operators(RANGE_OPERATORS)];
+
+/// Self-service routes for member tokens. Every query is pinned to the
+/// member ID from the token, so no parameter can reach another member's
+/// records.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_profile))
+        .route("/loans", get(list_own_loans))
+        .route("/fines", get(list_own_fines))
+        .route("/reservations", get(list_own_reservations))
+        .route("/renew", post(renew_own_loan))
+}
+
+fn owner_clause(column: &str, member_id: &str) -> FilterClause {
+    FilterClause::new(
+        format!("{} = ?", column),
+        FilterValue::Text(member_id.to_string()),
+    )
+}
+
+async fn fetch_member_loan(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+    loan_id: i64,
+) -> Result<MemberLoan, AppError> {
+    let sql = format!(
+        "{} WHERE loan.loan_id = ? AND loan.member_id = ?",
+        MEMBER_LOAN_SELECT
+    );
+    let loan = sqlx::query_as::<_, MemberLoan>(&sql)
+        .bind(loan_id)
+        .bind(member_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(loan)
+}
+
+#[utoipa::path(
+    get,
+    path = "/me",
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Me"
+)]
+async fn get_profile(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let member_id = auth.require_member()?;
+
+    let member = sqlx::query_as::<_, Member>(
+        "SELECT member_id, member_name, member_email, member_type_id, expire_date, is_pending FROM member WHERE member_id = ?",
+    )
+    .bind(member_id)
+    .fetch_one(&state.pool)
+    .await?;
+
+    Ok(Json(single_document(resource(
+        "members",
+        member.member_id.clone(),
+        member,
+    ))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/me/loans",
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Me"
+)]
+async fn list_own_loans(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let member_id = auth.require_member()?;
+
+    let pagination = params.pagination();
+    let loan_fields = params.fieldset("loans");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause = params.sort_clause(MEMBER_LOAN_SORTS, "loan.loan_date DESC")?;
+    let mut filters = vec![owner_clause("loan.member_id", member_id)];
+    filters.extend(params.filter_clauses(MEMBER_LOAN_FILTERS)?);
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM loan {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "{} {} ORDER BY {} LIMIT ? OFFSET ?",
+        MEMBER_LOAN_SELECT, where_sql, sort_clause
+    );
+    let loans = bind_filters_to_query(sqlx::query_as::<_, MemberLoan>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = loans
+        .into_iter()
+        .map(|loan| resource_with_fields("loans", loan.loan_id.to_string(), loan, loan_fields))
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+/// Lists the member's fine entries; `meta.balance` is the outstanding
+/// amount across all entries.
+#[utoipa::path(
+    get,
+    path = "/me/fines",
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Me"
+)]
+async fn list_own_fines(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let member_id = auth.require_member()?;
+
+    let pagination = params.pagination();
+    let fine_fields = params.fieldset("fines");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause = params.sort_clause(
+        MEMBER_FINE_SORTS,
+        "fines.fines_date DESC, fines.fines_id DESC",
+    )?;
+    let mut filters = vec![owner_clause("fines.member_id", member_id)];
+    filters.extend(params.filter_clauses(MEMBER_FINE_FILTERS)?);
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM fines {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "SELECT fines_id, fines_date, member_id, debet, credit, description FROM fines {} ORDER BY {} LIMIT ? OFFSET ?",
+        where_sql, sort_clause
+    );
+    let fines = bind_filters_to_query(sqlx::query_as::<_, Fine>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let mut conn = state.pool.acquire().await?;
+    let balance = fetch_balance(&mut conn, member_id).await?;
+
+    let data = fines
+        .into_iter()
+        .map(|fine| resource_with_fields("fines", fine.fines_id.to_string(), fine, fine_fields))
+        .collect();
+
+    let mut meta = pagination_meta(page, per_page, total);
+    meta["balance"] = json!(balance.balance);
+
+    Ok(Json(collection_document(data, meta)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/me/reservations",
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Me"
+)]
+async fn list_own_reservations(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let member_id = auth.require_member()?;
+
+    let pagination = params.pagination();
+    let reserve_fields = params.fieldset("reserves");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM reserve WHERE member_id = ?")
+        .bind(member_id)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "{} WHERE reserve.member_id = ? ORDER BY reserve.reserve_date ASC, reserve.reserve_id ASC LIMIT ? OFFSET ?",
+        RESERVE_SELECT
+    );
+    let reserves = sqlx::query_as::<_, Reserve>(&data_sql)
+        .bind(member_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = reserves
+        .into_iter()
+        .map(|reserve| {
+            resource_with_fields(
+                "reserves",
+                reserve.reserve_id.to_string(),
+                reserve,
+                reserve_fields,
+            )
+        })
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+/// Renews one of the member's own open loans under the same rules as a
+/// desk renewal. Loans of other members answer 404.
+#[utoipa::path(
+    post,
+    path = "/me/renew",
+    request_body(content = RenewOwnLoan, content_type = "application/vnd.api+json"),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 404, description = "No open loan with this ID for the member"),
+        (status = 422, description = "Renewal refused by circulation policy"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Me"
+)]
+async fn renew_own_loan(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<RenewOwnLoan>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let member_id = auth.require_member()?;
+    state.require_transactional_tables()?;
+
+    let today = chrono::Utc::now().date_naive();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut tx = state.pool.begin().await?;
+    let loan = fetch_member_loan(&mut tx, member_id, payload.loan_id).await?;
+    if loan.is_return != 0 {
+        return Err(AppError::NotFound);
+    }
+
+    let plan = circulation::plan_renewal(&mut tx, loan.loan_id, today).await?;
+    // No staff user is involved, so `uid` is recorded as 0.
+    circulation::renew(&mut tx, &plan, 0, now).await?;
+    let loan = fetch_member_loan(&mut tx, member_id, plan.loan_id).await?;
+    log_loan(
+        &mut tx,
+        &auth,
+        &LoanEvent {
+            action: "renew",
+            item_code: loan.item_code.as_deref().unwrap_or_default(),
+            member_id,
+            due_date: loan.due_date,
+        },
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(single_document(resource(
+        "loans",
+        loan.loan_id.to_string(),
+        loan,
+    ))))
+}
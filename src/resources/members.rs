@@ -13,16 +13,18 @@ use std::collections::HashMap;
 use utoipa::ToSchema;
 
 use crate::{
+    audit::{SystemLogEntry, write_system_log},
     auth::{AuthUser, ModuleAccess, Permission},
     config::AppState,
     error::AppError,
     jsonapi::{
-        JsonApiDocument, collection_document, pagination_meta, resource, resource_with_fields,
-        single_document,
+        Included, JsonApi, JsonApiDocument, ResourceBuilder, ResourcePayload, collection_document,
+        identifier, pagination_meta, resource, single_document,
     },
     resources::{
-        bind_filters_to_query, bind_filters_to_scalar, where_clause, FilterField, FilterOperator,
-        FilterValueType, ListParams, SortField,
+        FilterField, FilterOperator, FilterValueType, ListParams, MATCH_OPERATORS,
+        NULLABLE_MATCH_OPERATORS, PatchSet, RANGE_OPERATORS, SortField, bind_filters_to_query,
+        bind_filters_to_scalar, fines::fetch_balance, nullable, where_clause,
     },
 };
 
@@ -46,6 +48,53 @@ pub struct CreateMember {
     pub gender: Option<i16>,
 }
 
+impl ResourcePayload for CreateMember {
+    const TYPE: &'static str = "members";
+}
+
+/// PATCH attributes: only those present are written, and `null` clears a
+/// nullable column.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateMember {
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub member_id: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub member_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub member_email: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>, nullable)]
+    pub member_type_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<NaiveDate>)]
+    pub expire_date: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i16>)]
+    pub gender: Option<Option<i16>>,
+}
+
+impl ResourcePayload for UpdateMember {
+    const TYPE: &'static str = "members";
+}
+
+/// A PUT replaces the whole record: every attribute is written, and those
+/// left out are cleared or reset to their defaults.
+impl From<CreateMember> for UpdateMember {
+    fn from(member: CreateMember) -> Self {
+        UpdateMember {
+            member_id: Some(Some(member.member_id)),
+            member_name: Some(Some(member.member_name)),
+            member_email: Some(member.member_email),
+            member_type_id: Some(member.member_type_id),
+            expire_date: Some(Some(member.expire_date)),
+            gender: Some(Some(member.gender.unwrap_or(0))),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow, ToSchema)]
 pub struct MemberTypeInfo {
     pub member_type_id: i64,
@@ -54,17 +103,18 @@ pub struct MemberTypeInfo {
     pub loan_periode: i64,
 }
 
+/// Attributes of a `members` resource; the member type is a relationship.
 #[derive(Debug, Serialize, ToSchema)]
 pub struct MemberResponse {
     #[serde(flatten)]
     pub member: Member,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub member_type: Option<MemberTypeInfo>,
     #[schema(value_type = Object)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub custom: Option<JsonValue>,
 }
 
+const MEMBER_INCLUDES: &[&str] = &["member_type", "custom"];
+
 const MEMBER_SORTS: &[SortField<'_>] = &[
     SortField::new("member_id", "member.member_id"),
     SortField::new("member_name", "member.member_name"),
@@ -78,7 +128,8 @@ const MEMBER_FILTERS: &[FilterField<'_>] = &[
         "member.member_id",
         FilterOperator::Equals,
         FilterValueType::Text,
-    ),
+    )
+    .with_operators(MATCH_OPERATORS),
     FilterField::new(
         "member_name",
         "member.member_name",
@@ -90,6 +141,34 @@ const MEMBER_FILTERS: &[FilterField<'_>] = &[
         "member.member_email",
         FilterOperator::Equals,
         FilterValueType::Text,
+    )
+    .with_operators(&[FilterOperator::Null, FilterOperator::NotNull]),
+    FilterField::new(
+        "member_type_id",
+        "member.member_type_id",
+        FilterOperator::Equals,
+        FilterValueType::Integer,
+    )
+    .with_operators(NULLABLE_MATCH_OPERATORS),
+    FilterField::new(
+        "expire_date",
+        "member.expire_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(RANGE_OPERATORS),
+    FilterField::new(
+        "register_date",
+        "member.register_date",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(RANGE_OPERATORS),
+    FilterField::new(
+        "is_pending",
+        "member.is_pending",
+        FilterOperator::Equals,
+        FilterValueType::Boolean,
     ),
 ];
 
@@ -98,8 +177,12 @@ pub fn router() -> Router<AppState> {
         .route("/", get(list_members).post(create_member))
         .route(
             "/:member_id",
-            get(get_member).put(update_member).delete(delete_member),
+            get(get_member)
+                .put(replace_member)
+                .patch(update_member)
+                .delete(delete_member),
         )
+        .route("/:member_id/balance", get(member_balance))
 }
 
 #[utoipa::path(
@@ -117,8 +200,7 @@ async fn list_members(
     auth.require_access(ModuleAccess::Membership, Permission::Read)?;
 
     let pagination = params.pagination();
-    let includes = params.includes();
-    let member_fields = params.fieldset("members");
+    let includes = params.checked_includes(MEMBER_INCLUDES)?;
     let (limit, offset, page, per_page) = pagination.limit_offset();
     let sort_clause = params.sort_clause(MEMBER_SORTS, "member.register_date DESC")?;
     let filters = params.filter_clauses(MEMBER_FILTERS)?;
@@ -140,61 +222,49 @@ async fn list_members(
         .await?;
 
     let mut member_type_cache: HashMap<i32, MemberTypeInfo> = HashMap::new();
+    let mut included = Included::default();
     let mut data = Vec::with_capacity(members.len());
 
     for member in members {
         let mut member_type = None;
-        if includes.contains("member_type") {
-            if let Some(mt_id) = member.member_type_id {
-                if let Some(existing) = member_type_cache.get(&mt_id) {
-                    member_type = Some(existing.clone());
-                } else {
-                    if let Some(mt) = sqlx::query_as::<_, MemberTypeInfo>(
-                        "SELECT member_type_id, member_type_name, loan_limit, loan_periode FROM mst_member_type WHERE member_type_id = ?",
-                    )
-                    .bind(mt_id)
-                    .fetch_optional(&state.pool)
-                    .await?
-                    {
-                        member_type_cache.insert(mt_id, mt.clone());
-                        member_type = Some(mt);
-                    }
-                }
+        if includes.contains("member_type")
+            && let Some(mt_id) = member.member_type_id
+        {
+            if let Some(existing) = member_type_cache.get(&mt_id) {
+                member_type = Some(existing.clone());
+            } else if let Some(mt) = sqlx::query_as::<_, MemberTypeInfo>(
+                "SELECT member_type_id, member_type_name, loan_limit, loan_periode FROM mst_member_type WHERE member_type_id = ?",
+            )
+            .bind(mt_id)
+            .fetch_optional(&state.pool)
+            .await?
+            {
+                member_type_cache.insert(mt_id, mt.clone());
+                member_type = Some(mt);
             }
         }
 
         let custom = if includes.contains("custom") {
-            if let Some(row) = sqlx::query("SELECT * FROM member_custom WHERE member_id = ?")
+            sqlx::query("SELECT * FROM member_custom WHERE member_id = ?")
                 .bind(&member.member_id)
                 .fetch_optional(&state.pool)
                 .await?
-            {
-                Some(row_to_json(&row))
-            } else {
-                None
-            }
+                .map(|row| row_to_json(&row))
         } else {
             None
         };
 
-        let response = MemberResponse {
-            member,
+        data.push(member_resource(
+            MemberResponse { member, custom },
             member_type,
-            custom,
-        };
-
-        data.push(resource_with_fields(
-            "members",
-            response.member.member_id.clone(),
-            response,
-            member_fields,
+            &params,
+            &mut included,
         ));
     }
 
-    Ok(Json(collection_document(
-        data,
-        pagination_meta(page, per_page, total),
-    )))
+    Ok(Json(
+        collection_document(data, pagination_meta(page, per_page, total)).with_included(included),
+    ))
 }
 
 #[utoipa::path(
@@ -220,46 +290,64 @@ async fn get_member(
     .fetch_one(&state.pool)
     .await?;
 
-    let includes = params.includes();
+    let includes = params.checked_includes(MEMBER_INCLUDES)?;
     let mut member_type = None;
-    if includes.contains("member_type") {
-        if let Some(mt_id) = member.member_type_id {
-            member_type = sqlx::query_as::<_, MemberTypeInfo>(
-                "SELECT member_type_id, member_type_name, loan_limit, loan_periode FROM mst_member_type WHERE member_type_id = ?",
-            )
-            .bind(mt_id)
-            .fetch_optional(&state.pool)
-            .await?;
-        }
+    if includes.contains("member_type")
+        && let Some(mt_id) = member.member_type_id
+    {
+        member_type = sqlx::query_as::<_, MemberTypeInfo>(
+            "SELECT member_type_id, member_type_name, loan_limit, loan_periode FROM mst_member_type WHERE member_type_id = ?",
+        )
+        .bind(mt_id)
+        .fetch_optional(&state.pool)
+        .await?;
     }
 
     let custom = if includes.contains("custom") {
-        if let Some(row) = sqlx::query("SELECT * FROM member_custom WHERE member_id = ?")
+        sqlx::query("SELECT * FROM member_custom WHERE member_id = ?")
             .bind(&member.member_id)
             .fetch_optional(&state.pool)
             .await?
-        {
-            Some(row_to_json(&row))
-        } else {
-            None
-        }
+            .map(|row| row_to_json(&row))
     } else {
         None
     };
 
-    let response = MemberResponse {
-        member,
+    let mut included = Included::default();
+    let resource = member_resource(
+        MemberResponse { member, custom },
         member_type,
-        custom,
+        &params,
+        &mut included,
+    );
+
+    Ok(Json(single_document(resource).with_included(included)))
+}
+
+/// Builds the `members` resource object with its `member_type` linkage.
+fn member_resource(
+    response: MemberResponse,
+    member_type: Option<MemberTypeInfo>,
+    params: &ListParams,
+    included: &mut Included,
+) -> JsonValue {
+    let member_type = match member_type {
+        Some(member_type) => Some(included.resource(
+            "member-types",
+            member_type.member_type_id.to_string(),
+            &member_type,
+            params.fieldset("member-types"),
+        )),
+        None => response
+            .member
+            .member_type_id
+            .map(|id| identifier("member-types", id.to_string())),
     };
 
-    let member_fields = params.fieldset("members");
-    Ok(Json(single_document(resource_with_fields(
-        "members",
-        response.member.member_id.clone(),
-        response,
-        member_fields,
-    ))))
+    let id = response.member.member_id.clone();
+    ResourceBuilder::new("members", id, response, params.fieldset("members"))
+        .has_one("member_type", member_type)
+        .build()
 }
 
 fn row_to_json(row: &MySqlRow) -> JsonValue {
@@ -275,7 +363,7 @@ fn row_to_json(row: &MySqlRow) -> JsonValue {
 #[utoipa::path(
     post,
     path = "/members",
-    request_body = CreateMember,
+    request_body(content = CreateMember, content_type = "application/vnd.api+json"),
     responses((status = 200, body = JsonApiDocument)),
     security(("bearerAuth" = [])),
     tag = "Members"
@@ -283,12 +371,16 @@ fn row_to_json(row: &MySqlRow) -> JsonValue {
 async fn create_member(
     State(state): State<AppState>,
     auth: AuthUser,
-    Json(payload): Json<CreateMember>,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<CreateMember>,
 ) -> Result<Json<JsonApiDocument>, AppError> {
     auth.require_access(ModuleAccess::Membership, Permission::Write)?;
 
     let gender = payload.gender.unwrap_or(0);
 
+    let mut tx = state.pool.begin().await?;
     sqlx::query(
         "INSERT INTO member (member_id, member_name, gender, member_email, member_type_id, expire_date, register_date, member_since_date, is_pending) VALUES (?, ?, ?, ?, ?, ?, CURDATE(), CURDATE(), 0)",
     )
@@ -298,15 +390,31 @@ async fn create_member(
     .bind(&payload.member_email)
     .bind(payload.member_type_id)
     .bind(payload.expire_date)
-    .execute(&state.pool)
+    .execute(&mut *tx)
     .await?;
 
     let rec = sqlx::query_as::<_, Member>(
         "SELECT member_id, member_name, member_email, member_type_id, expire_date, is_pending FROM member WHERE member_id = ?",
     )
     .bind(&payload.member_id)
-    .fetch_one(&state.pool)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    write_system_log(
+        &mut tx,
+        &SystemLogEntry::by(
+            &auth,
+            "membership",
+            "member",
+            "create",
+            format!(
+                "{} created member {} ({})",
+                auth.claims.username, rec.member_id, rec.member_name
+            ),
+        ),
+    )
     .await?;
+    tx.commit().await?;
 
     Ok(Json(single_document(resource(
         "members",
@@ -318,7 +426,28 @@ async fn create_member(
 #[utoipa::path(
     put,
     path = "/members/{member_id}",
-    request_body = CreateMember,
+    request_body(content = CreateMember, content_type = "application/vnd.api+json"),
+    params(("member_id" = String, Path, description = "Member ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Members"
+)]
+async fn replace_member(
+    State(state): State<AppState>,
+    Path(member_id): Path<String>,
+    auth: AuthUser,
+    payload: JsonApi<CreateMember>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Membership, Permission::Write)?;
+    let payload = payload.for_id(&member_id)?;
+
+    apply_member_update(&state, member_id, &auth, payload.into()).await
+}
+
+#[utoipa::path(
+    patch,
+    path = "/members/{member_id}",
+    request_body(content = UpdateMember, content_type = "application/vnd.api+json"),
     params(("member_id" = String, Path, description = "Member ID")),
     responses((status = 200, body = JsonApiDocument)),
     security(("bearerAuth" = [])),
@@ -328,35 +457,78 @@ async fn update_member(
     State(state): State<AppState>,
     Path(member_id): Path<String>,
     auth: AuthUser,
-    Json(payload): Json<CreateMember>,
+    payload: JsonApi<UpdateMember>,
 ) -> Result<Json<JsonApiDocument>, AppError> {
     auth.require_access(ModuleAccess::Membership, Permission::Write)?;
+    let payload = payload.for_id(&member_id)?;
 
-    let gender = payload.gender.unwrap_or(0);
+    apply_member_update(&state, member_id, &auth, payload).await
+}
 
-    let updated = sqlx::query(
-        "UPDATE member SET member_id = ?, member_name = ?, gender = ?, member_email = ?, member_type_id = ?, expire_date = ?, last_update = CURDATE() WHERE member_id = ?",
-    )
-    .bind(&payload.member_id)
-    .bind(&payload.member_name)
-    .bind(gender)
-    .bind(&payload.member_email)
-    .bind(payload.member_type_id)
-    .bind(payload.expire_date)
-    .bind(&member_id)
-    .execute(&state.pool)
-    .await?;
+async fn apply_member_update(
+    state: &AppState,
+    member_id: String,
+    auth: &AuthUser,
+    payload: UpdateMember,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let new_id = payload
+        .member_id
+        .clone()
+        .flatten()
+        .unwrap_or_else(|| member_id.clone());
+
+    let mut set = PatchSet::default();
+    set.required("member_id", payload.member_id)?;
+    set.required("member_name", payload.member_name)?;
+    set.required("gender", payload.gender)?;
+    set.nullable("member_email", payload.member_email);
+    set.nullable("member_type_id", payload.member_type_id);
+    set.required("expire_date", payload.expire_date)?;
+
+    // An empty PATCH changes nothing and just returns the member.
+    let changed = !set.is_empty();
+    let mut tx = state.pool.begin().await?;
+    if changed {
+        set.touch("last_update = CURDATE()");
+        let sql = set.update_sql("member", "member_id");
+        let updated = set
+            .bind(sqlx::query(&sql))
+            .bind(&member_id)
+            .execute(&mut *tx)
+            .await?;
 
-    if updated.rows_affected() == 0 {
-        return Err(AppError::NotFound);
+        if updated.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
     }
-
     let rec = sqlx::query_as::<_, Member>(
         "SELECT member_id, member_name, member_email, member_type_id, expire_date, is_pending FROM member WHERE member_id = ?",
     )
-    .bind(&payload.member_id)
-    .fetch_one(&state.pool)
+    .bind(&new_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !changed {
+        return Ok(Json(single_document(resource(
+            "members",
+            rec.member_id.clone(),
+            rec,
+        ))));
+    }
+
+    let message = if member_id == rec.member_id {
+        format!("{} updated member {}", auth.claims.username, member_id)
+    } else {
+        format!(
+            "{} updated member {}, now {}",
+            auth.claims.username, member_id, rec.member_id
+        )
+    };
+    write_system_log(
+        &mut tx,
+        &SystemLogEntry::by(auth, "membership", "member", "update", message),
+    )
     .await?;
+    tx.commit().await?;
 
     Ok(Json(single_document(resource(
         "members",
@@ -380,10 +552,59 @@ async fn delete_member(
 ) -> Result<StatusCode, AppError> {
     auth.require_access(ModuleAccess::Membership, Permission::Write)?;
 
-    sqlx::query("DELETE FROM member WHERE member_id = ?")
+    let mut tx = state.pool.begin().await?;
+    let deleted = sqlx::query("DELETE FROM member WHERE member_id = ?")
         .bind(&member_id)
-        .execute(&state.pool)
+        .execute(&mut *tx)
+        .await?;
+
+    if deleted.rows_affected() > 0 {
+        write_system_log(
+            &mut tx,
+            &SystemLogEntry::by(
+                &auth,
+                "membership",
+                "member",
+                "delete",
+                format!("{} deleted member {}", auth.claims.username, member_id),
+            ),
+        )
         .await?;
+    }
+    tx.commit().await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+#[utoipa::path(
+    get,
+    path = "/members/{member_id}/balance",
+    params(("member_id" = String, Path, description = "Member ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Members"
+)]
+async fn member_balance(
+    State(state): State<AppState>,
+    Path(member_id): Path<String>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Read)?;
+
+    let mut conn = state.pool.acquire().await?;
+    let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM member WHERE member_id = ?")
+        .bind(&member_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    if exists == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    let balance = fetch_balance(&mut conn, &member_id).await?;
+
+    Ok(Json(single_document(resource(
+        "fine-balances",
+        member_id,
+        balance,
+    ))))
+}
@@ -1,18 +1,26 @@
+pub mod api_keys;
 pub mod biblios;
 pub mod contents;
 pub mod files;
+pub mod fines;
+pub mod groups;
 pub mod items;
 pub mod loans;
 pub mod lookups;
+pub mod me;
 pub mod members;
+pub mod reserves;
 pub mod settings;
+pub mod system_logs;
+pub mod users;
 pub mod visitors;
 
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::Deserialize;
 use sqlx::{
-    mysql::MySqlArguments,
-    query::{QueryAs, QueryScalar},
     MySql,
+    mysql::MySqlArguments,
+    query::{Query, QueryAs, QueryScalar},
 };
 use std::collections::{HashMap, HashSet};
 use utoipa::ToSchema;
@@ -51,10 +59,19 @@ pub struct ListParams {
     pagination: Pagination,
     pub include: Option<String>,
     fields: HashMap<String, HashSet<String>>,
-    filters: HashMap<String, Vec<String>>,
+    filters: Vec<FilterParam>,
     sorts: Vec<SortOrder>,
 }
 
+/// A `filter[name]=value` or `filter[name][op]=value` query parameter. The
+/// value is kept raw; it is split on commas only for `in` and `between`.
+#[derive(Debug, Clone)]
+struct FilterParam {
+    name: String,
+    operator: Option<String>,
+    value: String,
+}
+
 impl<'de> Deserialize<'de> for ListParams {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -75,38 +92,37 @@ impl<'de> Deserialize<'de> for ListParams {
         let raw = RawParams::deserialize(deserializer)?;
 
         let mut fields: HashMap<String, HashSet<String>> = HashMap::new();
-        let mut filters: HashMap<String, Vec<String>> = HashMap::new();
+        let mut filters = Vec::new();
 
         for (key, value) in raw.extras {
-            if let Some(name) = key.strip_prefix("fields[") {
-                if let Some(name) = name.strip_suffix(']') {
-                    let set = value
-                        .split(',')
-                        .filter_map(|part| {
-                            let trimmed = part.trim();
-                            (!trimmed.is_empty()).then(|| trimmed.to_string())
-                        })
-                        .collect::<HashSet<_>>();
-                    if !set.is_empty() {
-                        fields.insert(name.to_string(), set);
-                    }
-                    continue;
+            if let Some(name) = key.strip_prefix("fields[")
+                && let Some(name) = name.strip_suffix(']')
+            {
+                let set = value
+                    .split(',')
+                    .filter_map(|part| {
+                        let trimmed = part.trim();
+                        (!trimmed.is_empty()).then(|| trimmed.to_string())
+                    })
+                    .collect::<HashSet<_>>();
+                if !set.is_empty() {
+                    fields.insert(name.to_string(), set);
                 }
+                continue;
             }
 
-            if let Some(name) = key.strip_prefix("filter[") {
-                if let Some(name) = name.strip_suffix(']') {
-                    let values = value
-                        .split(',')
-                        .filter_map(|part| {
-                            let trimmed = part.trim();
-                            (!trimmed.is_empty()).then(|| trimmed.to_string())
-                        })
-                        .collect::<Vec<_>>();
-                    if !values.is_empty() {
-                        filters.insert(name.to_string(), values);
-                    }
-                }
+            if let Some(name) = key.strip_prefix("filter[")
+                && let Some(name) = name.strip_suffix(']')
+            {
+                let (name, operator) = match name.split_once("][") {
+                    Some((name, operator)) => (name, Some(operator.to_string())),
+                    None => (name, None),
+                };
+                filters.push(FilterParam {
+                    name: name.to_string(),
+                    operator,
+                    value: value.trim().to_string(),
+                });
             }
         }
 
@@ -135,6 +151,25 @@ impl ListParams {
         parse_include(self.include.clone())
     }
 
+    /// The requested include paths, rejecting any that `allowed` does not
+    /// list, as JSON:API requires.
+    pub fn checked_includes(
+        &self,
+        allowed: &[&str],
+    ) -> Result<HashSet<String>, crate::error::AppError> {
+        let includes = self.includes();
+        if let Some(unknown) = includes
+            .iter()
+            .find(|path| !allowed.contains(&path.as_str()))
+        {
+            return Err(crate::error::AppError::BadRequest(format!(
+                "including `{}` is not supported",
+                unknown
+            )));
+        }
+        Ok(includes)
+    }
+
     pub fn fieldset(&self, resource_type: &str) -> Option<&HashSet<String>> {
         self.fields.get(resource_type)
     }
@@ -169,27 +204,44 @@ impl ListParams {
         allowed: &[FilterField<'_>],
     ) -> Result<Vec<FilterClause>, crate::error::AppError> {
         let mut clauses = Vec::new();
-        for (name, values) in &self.filters {
+        for param in &self.filters {
             let def = allowed
                 .iter()
-                .find(|item| item.name == name)
+                .find(|item| item.name == param.name)
                 .ok_or_else(|| {
                     crate::error::AppError::BadRequest(format!(
                         "filter `{}` is not supported",
-                        name
+                        param.name
                     ))
                 })?;
 
-            if values.len() > 1 {
+            let operator = match &param.operator {
+                Some(name) => FilterOperator::from_name(name).ok_or_else(|| {
+                    crate::error::AppError::BadRequest(format!(
+                        "filter operator `{}` is not supported",
+                        name
+                    ))
+                })?,
+                None => def.operator,
+            };
+            if !def.supports(operator) {
                 return Err(crate::error::AppError::BadRequest(format!(
-                    "multiple filter values for `{}` are not supported",
-                    name
+                    "filter `{}` does not support `{}`; use one of {}",
+                    def.name,
+                    operator.name(),
+                    def.operator_names()
                 )));
             }
 
-            let raw_value = values.first().expect("checked non-empty");
-            let (statement, value) = def.to_clause(raw_value)?;
-            clauses.push(FilterClause { statement, value });
+            // An empty value drops the filter, except for the null checks,
+            // which need no value.
+            if param.value.is_empty()
+                && !matches!(operator, FilterOperator::Null | FilterOperator::NotNull)
+            {
+                continue;
+            }
+
+            clauses.push(def.to_clause(operator, &param.value)?);
         }
         Ok(clauses)
     }
@@ -249,25 +301,123 @@ impl<'a> SortField<'a> {
     }
 }
 
-#[derive(Clone, Copy)]
+/// How a filter compares its column, selected with `filter[name][op]`.
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum FilterOperator {
     Equals,
+    NotEquals,
     Like,
+    GreaterThan,
+    GreaterOrEqual,
+    LessThan,
+    LessOrEqual,
+    /// Any of a comma separated list.
+    In,
+    /// An inclusive `from,to` range.
+    Between,
+    Null,
+    NotNull,
+}
+
+const FILTER_OPERATORS: &[FilterOperator] = &[
+    FilterOperator::Equals,
+    FilterOperator::NotEquals,
+    FilterOperator::Like,
+    FilterOperator::GreaterThan,
+    FilterOperator::GreaterOrEqual,
+    FilterOperator::LessThan,
+    FilterOperator::LessOrEqual,
+    FilterOperator::In,
+    FilterOperator::Between,
+    FilterOperator::Null,
+    FilterOperator::NotNull,
+];
+
+impl FilterOperator {
+    /// The `op` in `filter[name][op]`.
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterOperator::Equals => "eq",
+            FilterOperator::NotEquals => "ne",
+            FilterOperator::Like => "like",
+            FilterOperator::GreaterThan => "gt",
+            FilterOperator::GreaterOrEqual => "gte",
+            FilterOperator::LessThan => "lt",
+            FilterOperator::LessOrEqual => "lte",
+            FilterOperator::In => "in",
+            FilterOperator::Between => "between",
+            FilterOperator::Null => "null",
+            FilterOperator::NotNull => "notnull",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        FILTER_OPERATORS
+            .iter()
+            .copied()
+            .find(|operator| operator.name() == name)
+    }
 }
 
+/// Equality and list matching, for IDs and codes.
+pub const MATCH_OPERATORS: &[FilterOperator] = &[
+    FilterOperator::Equals,
+    FilterOperator::NotEquals,
+    FilterOperator::In,
+];
+
+/// [`MATCH_OPERATORS`] for columns that may be `NULL`.
+pub const NULLABLE_MATCH_OPERATORS: &[FilterOperator] = &[
+    FilterOperator::Equals,
+    FilterOperator::NotEquals,
+    FilterOperator::In,
+    FilterOperator::Null,
+    FilterOperator::NotNull,
+];
+
+/// Comparisons and ranges, for dates and numbers.
+pub const RANGE_OPERATORS: &[FilterOperator] = &[
+    FilterOperator::Equals,
+    FilterOperator::NotEquals,
+    FilterOperator::GreaterThan,
+    FilterOperator::GreaterOrEqual,
+    FilterOperator::LessThan,
+    FilterOperator::LessOrEqual,
+    FilterOperator::Between,
+];
+
+/// [`RANGE_OPERATORS`] for columns that may be `NULL`.
+pub const NULLABLE_RANGE_OPERATORS: &[FilterOperator] = &[
+    FilterOperator::Equals,
+    FilterOperator::NotEquals,
+    FilterOperator::GreaterThan,
+    FilterOperator::GreaterOrEqual,
+    FilterOperator::LessThan,
+    FilterOperator::LessOrEqual,
+    FilterOperator::Between,
+    FilterOperator::Null,
+    FilterOperator::NotNull,
+];
+
 #[derive(Clone, Copy)]
 pub enum FilterValueType {
     Text,
     Integer,
     Boolean,
+    Date,
+    /// `YYYY-MM-DDTHH:MM:SS`; a plain date means midnight.
+    DateTime,
 }
 
 #[derive(Clone, Copy)]
 pub struct FilterField<'a> {
     pub name: &'a str,
     pub column: &'a str,
+    /// Used by `filter[name]=value`.
     pub operator: FilterOperator,
     pub value_type: FilterValueType,
+    /// Further operators accepted as `filter[name][op]=value`.
+    pub operators: &'a [FilterOperator],
 }
 
 impl<'a> FilterField<'a> {
@@ -282,41 +432,153 @@ impl<'a> FilterField<'a> {
             column,
             operator,
             value_type,
+            operators: &[],
+        }
+    }
+
+    pub const fn with_operators(mut self, operators: &'a [FilterOperator]) -> Self {
+        self.operators = operators;
+        self
+    }
+
+    fn supports(&self, operator: FilterOperator) -> bool {
+        self.operator == operator || self.operators.contains(&operator)
+    }
+
+    fn operator_names(&self) -> String {
+        let mut names = vec![self.operator.name()];
+        for operator in self.operators {
+            if !names.contains(&operator.name()) {
+                names.push(operator.name());
+            }
         }
+        names
+            .iter()
+            .map(|name| format!("`{}`", name))
+            .collect::<Vec<_>>()
+            .join(", ")
     }
 
     fn to_clause(
-        &self,
+        self,
+        operator: FilterOperator,
         raw_value: &str,
-    ) -> Result<(String, FilterValue), crate::error::AppError> {
-        let (statement, value) = match self.operator {
+    ) -> Result<FilterClause, crate::error::AppError> {
+        let column = self.column;
+        let clause = match operator {
             FilterOperator::Equals => {
-                let value = self.parse_value(raw_value)?;
-                (format!("{} = ?", self.column), value)
+                FilterClause::new(format!("{} = ?", column), self.parse_single(raw_value)?)
             }
-            FilterOperator::Like => {
-                let value = FilterValue::Text(format!("%{}%", raw_value));
-                (format!("{} LIKE ?", self.column), value)
+            FilterOperator::NotEquals => {
+                FilterClause::new(format!("{} <> ?", column), self.parse_single(raw_value)?)
+            }
+            FilterOperator::Like => FilterClause::new(
+                format!("{} LIKE ?", column),
+                FilterValue::Text(format!("%{}%", raw_value)),
+            ),
+            FilterOperator::GreaterThan => {
+                FilterClause::new(format!("{} > ?", column), self.parse_single(raw_value)?)
+            }
+            FilterOperator::GreaterOrEqual => {
+                FilterClause::new(format!("{} >= ?", column), self.parse_single(raw_value)?)
+            }
+            FilterOperator::LessThan => {
+                FilterClause::new(format!("{} < ?", column), self.parse_single(raw_value)?)
+            }
+            FilterOperator::LessOrEqual => {
+                FilterClause::new(format!("{} <= ?", column), self.parse_single(raw_value)?)
+            }
+            FilterOperator::In => {
+                let values = self.parse_list(raw_value)?;
+                FilterClause {
+                    statement: format!("{} IN ({})", column, vec!["?"; values.len()].join(", ")),
+                    values,
+                }
+            }
+            FilterOperator::Between => {
+                let values = self.parse_list(raw_value)?;
+                if values.len() != 2 {
+                    return Err(crate::error::AppError::BadRequest(format!(
+                        "filter `{}` with `between` takes two values, `from,to`",
+                        self.name
+                    )));
+                }
+                FilterClause {
+                    statement: format!("{} BETWEEN ? AND ?", column),
+                    values,
+                }
+            }
+            FilterOperator::Null | FilterOperator::NotNull => {
+                let wanted = match raw_value {
+                    "" | "true" | "1" => true,
+                    "false" | "0" => false,
+                    _ => {
+                        return Err(crate::error::AppError::BadRequest(format!(
+                            "filter `{}` with `{}` takes no value or a boolean",
+                            self.name,
+                            operator.name()
+                        )));
+                    }
+                };
+                let is_null = wanted == (operator == FilterOperator::Null);
+                FilterClause {
+                    statement: format!("{} IS {}NULL", column, if is_null { "" } else { "NOT " }),
+                    values: Vec::new(),
+                }
             }
         };
-        Ok((statement, value))
+        Ok(clause)
     }
 
-    fn parse_value(
-        &self,
-        raw_value: &str,
-    ) -> Result<FilterValue, crate::error::AppError> {
+    fn parse_single(&self, raw_value: &str) -> Result<FilterValue, crate::error::AppError> {
+        // Free text may contain commas, unless the field takes lists.
+        let takes_list = self.supports(FilterOperator::In);
+        if raw_value.contains(',')
+            && (takes_list || !matches!(self.value_type, FilterValueType::Text))
+        {
+            let hint = if takes_list {
+                format!("; use `filter[{}][in]` to match a list", self.name)
+            } else {
+                String::new()
+            };
+            return Err(crate::error::AppError::BadRequest(format!(
+                "filter `{}` takes a single value{}",
+                self.name, hint
+            )));
+        }
+        self.parse_value(raw_value)
+    }
+
+    fn parse_list(&self, raw_value: &str) -> Result<Vec<FilterValue>, crate::error::AppError> {
+        let values = raw_value
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(|part| self.parse_value(part))
+            .collect::<Result<Vec<_>, _>>()?;
+        if values.is_empty() {
+            return Err(crate::error::AppError::BadRequest(format!(
+                "filter `{}` needs at least one value",
+                self.name
+            )));
+        }
+        Ok(values)
+    }
+
+    fn parse_value(&self, raw_value: &str) -> Result<FilterValue, crate::error::AppError> {
         match self.value_type {
             FilterValueType::Text => Ok(FilterValue::Text(raw_value.to_string())),
-            FilterValueType::Integer => raw_value
-                .parse::<i64>()
-                .map(FilterValue::Integer)
-                .map_err(|_| {
-                    crate::error::AppError::BadRequest(format!(
-                        "filter `{}` must be an integer",
-                        self.name
-                    ))
-                }),
+            FilterValueType::Integer => {
+                raw_value
+                    .parse::<i64>()
+                    .map(FilterValue::Integer)
+                    .map_err(|_| {
+                        crate::error::AppError::BadRequest(format!(
+                            "filter `{}` must be an integer",
+                            self.name
+                        ))
+                    })
+            }
             FilterValueType::Boolean => match raw_value {
                 "true" | "1" => Ok(FilterValue::Boolean(true)),
                 "false" | "0" => Ok(FilterValue::Boolean(false)),
@@ -325,15 +587,44 @@ impl<'a> FilterField<'a> {
                     self.name
                 ))),
             },
+            FilterValueType::Date => NaiveDate::parse_from_str(raw_value, "%Y-%m-%d")
+                .map(FilterValue::Date)
+                .map_err(|_| {
+                    crate::error::AppError::BadRequest(format!(
+                        "filter `{}` must be a date (YYYY-MM-DD)",
+                        self.name
+                    ))
+                }),
+            FilterValueType::DateTime => parse_filter_datetime(raw_value)
+                .map(FilterValue::DateTime)
+                .ok_or_else(|| {
+                    crate::error::AppError::BadRequest(format!(
+                        "filter `{}` must be a date-time (YYYY-MM-DDTHH:MM:SS) or a date",
+                        self.name
+                    ))
+                }),
         }
     }
 }
 
-#[derive(Clone)]
+fn parse_filter_datetime(raw_value: &str) -> Option<NaiveDateTime> {
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(raw_value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(raw_value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum FilterValue {
     Text(String),
     Integer(i64),
     Boolean(bool),
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
 }
 
 impl FilterValue {
@@ -345,6 +636,8 @@ impl FilterValue {
             FilterValue::Text(val) => query.bind(val.clone()),
             FilterValue::Integer(val) => query.bind(*val),
             FilterValue::Boolean(val) => query.bind(*val),
+            FilterValue::Date(val) => query.bind(*val),
+            FilterValue::DateTime(val) => query.bind(*val),
         }
     }
 
@@ -356,15 +649,27 @@ impl FilterValue {
             FilterValue::Text(val) => query.bind(val.clone()),
             FilterValue::Integer(val) => query.bind(*val),
             FilterValue::Boolean(val) => query.bind(*val),
+            FilterValue::Date(val) => query.bind(*val),
+            FilterValue::DateTime(val) => query.bind(*val),
         }
     }
-
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct FilterClause {
     pub statement: String,
-    pub value: FilterValue,
+    /// Bound in order to the placeholders in `statement`.
+    pub values: Vec<FilterValue>,
+}
+
+impl FilterClause {
+    /// A clause with a single placeholder.
+    pub fn new(statement: impl Into<String>, value: FilterValue) -> Self {
+        FilterClause {
+            statement: statement.into(),
+            values: vec![value],
+        }
+    }
 }
 
 pub fn where_clause(filters: &[FilterClause]) -> String {
@@ -387,8 +692,8 @@ pub fn bind_filters_to_query<'q, T>(
     mut query: QueryAs<'q, MySql, T, MySqlArguments>,
     filters: &[FilterClause],
 ) -> QueryAs<'q, MySql, T, MySqlArguments> {
-    for clause in filters {
-        query = clause.value.bind_query(query);
+    for value in filters.iter().flat_map(|clause| &clause.values) {
+        query = value.bind_query(query);
     }
     query
 }
@@ -397,8 +702,516 @@ pub fn bind_filters_to_scalar<'q, T>(
     mut query: QueryScalar<'q, MySql, T, MySqlArguments>,
     filters: &[FilterClause],
 ) -> QueryScalar<'q, MySql, T, MySqlArguments> {
-    for clause in filters {
-        query = clause.value.bind_scalar(query);
+    for value in filters.iter().flat_map(|clause| &clause.values) {
+        query = value.bind_scalar(query);
     }
     query
 }
+
+/// Deserializes a PATCH attribute so that a missing attribute (`None`) can
+/// be told apart from an explicit `null` (`Some(None)`). Use it together
+/// with `#[serde(default)]`.
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// A column value written by [`PatchSet`]; `None` writes `NULL`.
+#[derive(Debug, PartialEq)]
+pub enum PatchValue {
+    Text(Option<String>),
+    Integer(Option<i64>),
+    Date(Option<NaiveDate>),
+    DateTime(Option<NaiveDateTime>),
+}
+
+impl From<Option<String>> for PatchValue {
+    fn from(value: Option<String>) -> Self {
+        PatchValue::Text(value)
+    }
+}
+
+impl From<Option<i32>> for PatchValue {
+    fn from(value: Option<i32>) -> Self {
+        PatchValue::Integer(value.map(i64::from))
+    }
+}
+
+impl From<Option<i16>> for PatchValue {
+    fn from(value: Option<i16>) -> Self {
+        PatchValue::Integer(value.map(i64::from))
+    }
+}
+
+impl From<Option<NaiveDate>> for PatchValue {
+    fn from(value: Option<NaiveDate>) -> Self {
+        PatchValue::Date(value)
+    }
+}
+
+impl From<Option<NaiveDateTime>> for PatchValue {
+    fn from(value: Option<NaiveDateTime>) -> Self {
+        PatchValue::DateTime(value)
+    }
+}
+
+/// The `SET` list of a PATCH update. Only attributes the client sent become
+/// assignments, so columns it left out keep their values.
+#[derive(Default)]
+pub struct PatchSet {
+    assignments: Vec<String>,
+    values: Vec<PatchValue>,
+}
+
+impl PatchSet {
+    /// Assigns a nullable column when its attribute was sent.
+    pub fn nullable<T>(&mut self, column: &str, value: Option<Option<T>>)
+    where
+        Option<T>: Into<PatchValue>,
+    {
+        if let Some(value) = value {
+            self.push(column, value.into());
+        }
+    }
+
+    /// Assigns a `NOT NULL` column when its attribute was sent, rejecting
+    /// an explicit `null`.
+    pub fn required<T>(
+        &mut self,
+        column: &str,
+        value: Option<Option<T>>,
+    ) -> Result<(), crate::error::AppError>
+    where
+        Option<T>: Into<PatchValue>,
+    {
+        match value {
+            Some(None) => Err(crate::error::AppError::BadRequest(format!(
+                "`{}` cannot be null",
+                column
+            ))),
+            Some(value) => {
+                self.push(column, value.into());
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Assigns a column regardless of the request, e.g. `last_update`.
+    pub fn set<T>(&mut self, column: &str, value: T)
+    where
+        Option<T>: Into<PatchValue>,
+    {
+        self.push(column, Some(value).into());
+    }
+
+    /// Adds an assignment that binds nothing, e.g. `last_update = NOW()`.
+    pub fn touch(&mut self, assignment: &str) {
+        self.assignments.push(assignment.to_string());
+    }
+
+    /// Whether no assignment has been collected yet.
+    pub fn is_empty(&self) -> bool {
+        self.assignments.is_empty()
+    }
+
+    fn push(&mut self, column: &str, value: PatchValue) {
+        self.assignments.push(format!("{} = ?", column));
+        self.values.push(value);
+    }
+
+    /// `UPDATE table SET ... WHERE key = ?`; bind the key after [`bind`].
+    ///
+    /// [`bind`]: PatchSet::bind
+    pub fn update_sql(&self, table: &str, key: &str) -> String {
+        format!(
+            "UPDATE {} SET {} WHERE {} = ?",
+            table,
+            self.assignments.join(", "),
+            key
+        )
+    }
+
+    pub fn bind<'q>(
+        self,
+        mut query: Query<'q, MySql, MySqlArguments>,
+    ) -> Query<'q, MySql, MySqlArguments> {
+        for value in self.values {
+            query = match value {
+                PatchValue::Text(val) => query.bind(val),
+                PatchValue::Integer(val) => query.bind(val),
+                PatchValue::Date(val) => query.bind(val),
+                PatchValue::DateTime(val) => query.bind(val),
+            };
+        }
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, http::Uri};
+
+    use super::*;
+    use crate::error::AppError;
+
+    const FIELDS: &[FilterField<'static>] = &[
+        FilterField::new(
+            "title",
+            "b.title",
+            FilterOperator::Like,
+            FilterValueType::Text,
+        ),
+        FilterField::new(
+            "item_code",
+            "l.item_code",
+            FilterOperator::Equals,
+            FilterValueType::Text,
+        )
+        .with_operators(MATCH_OPERATORS),
+        FilterField::new(
+            "member_type_id",
+            "m.member_type_id",
+            FilterOperator::Equals,
+            FilterValueType::Integer,
+        )
+        .with_operators(NULLABLE_MATCH_OPERATORS),
+        FilterField::new(
+            "is_return",
+            "l.is_return",
+            FilterOperator::Equals,
+            FilterValueType::Boolean,
+        ),
+        FilterField::new(
+            "loan_date",
+            "l.loan_date",
+            FilterOperator::Equals,
+            FilterValueType::Date,
+        )
+        .with_operators(RANGE_OPERATORS),
+        FilterField::new(
+            "log_date",
+            "s.log_date",
+            FilterOperator::Equals,
+            FilterValueType::DateTime,
+        )
+        .with_operators(NULLABLE_RANGE_OPERATORS),
+    ];
+
+    fn params(query: &str) -> ListParams {
+        let uri: Uri = format!("/?{}", query).parse().unwrap();
+        let Query(params) = Query::<ListParams>::try_from_uri(&uri).unwrap();
+        params
+    }
+
+    fn clause(query: &str) -> FilterClause {
+        let mut clauses = params(query).filter_clauses(FIELDS).unwrap();
+        assert_eq!(clauses.len(), 1, "one clause for `{}`", query);
+        clauses.pop().unwrap()
+    }
+
+    fn rejection(query: &str) -> String {
+        match params(query).filter_clauses(FIELDS) {
+            Err(AppError::BadRequest(message)) => message,
+            other => panic!("expected a bad request for `{}`, got {:?}", query, other),
+        }
+    }
+
+    fn date(value: &str) -> FilterValue {
+        FilterValue::Date(NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap())
+    }
+
+    fn datetime(value: &str) -> FilterValue {
+        FilterValue::DateTime(NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap())
+    }
+
+    #[test]
+    fn plain_filter_uses_the_default_operator() {
+        let like = clause("filter%5Btitle%5D=rust");
+        assert_eq!(like.statement, "b.title LIKE ?");
+        assert_eq!(like.values, vec![FilterValue::Text("%rust%".into())]);
+
+        let equals = clause("filter%5Bloan_date%5D=2024-03-01");
+        assert_eq!(equals.statement, "l.loan_date = ?");
+        assert_eq!(equals.values, vec![date("2024-03-01")]);
+    }
+
+    #[test]
+    fn parses_the_operator_from_the_second_bracket() {
+        let cases = [
+            ("eq", "="),
+            ("ne", "<>"),
+            ("gt", ">"),
+            ("gte", ">="),
+            ("lt", "<"),
+            ("lte", "<="),
+        ];
+        for (operator, sql) in cases {
+            let clause = clause(&format!(
+                "filter%5Bloan_date%5D%5B{}%5D=2024-03-01",
+                operator
+            ));
+            assert_eq!(clause.statement, format!("l.loan_date {} ?", sql));
+            assert_eq!(clause.values, vec![date("2024-03-01")]);
+        }
+    }
+
+    #[test]
+    fn splits_in_values_and_skips_empty_parts() {
+        let clause = clause("filter%5Bitem_code%5D%5Bin%5D=B001,%20B002,,B003");
+        assert_eq!(clause.statement, "l.item_code IN (?, ?, ?)");
+        assert_eq!(
+            clause.values,
+            vec![
+                FilterValue::Text("B001".into()),
+                FilterValue::Text("B002".into()),
+                FilterValue::Text("B003".into()),
+            ]
+        );
+
+        assert!(rejection("filter%5Bitem_code%5D%5Bin%5D=,").contains("at least one value"));
+        assert!(
+            rejection("filter%5Bmember_type_id%5D%5Bin%5D=1,two").contains("must be an integer")
+        );
+    }
+
+    #[test]
+    fn between_takes_exactly_two_values() {
+        let clause = clause("filter%5Bloan_date%5D%5Bbetween%5D=2024-01-01,2024-01-31");
+        assert_eq!(clause.statement, "l.loan_date BETWEEN ? AND ?");
+        assert_eq!(clause.values, vec![date("2024-01-01"), date("2024-01-31")]);
+
+        for value in ["2024-01-01", "2024-01-01,2024-01-15,2024-01-31"] {
+            assert!(
+                rejection(&format!("filter%5Bloan_date%5D%5Bbetween%5D={}", value))
+                    .contains("takes two values")
+            );
+        }
+    }
+
+    #[test]
+    fn null_checks_take_no_value_or_a_boolean() {
+        let cases = [
+            ("null", "", "m.member_type_id IS NULL"),
+            ("null", "true", "m.member_type_id IS NULL"),
+            ("null", "0", "m.member_type_id IS NOT NULL"),
+            ("notnull", "", "m.member_type_id IS NOT NULL"),
+            ("notnull", "1", "m.member_type_id IS NOT NULL"),
+            ("notnull", "false", "m.member_type_id IS NULL"),
+        ];
+        for (operator, value, sql) in cases {
+            let clause = clause(&format!(
+                "filter%5Bmember_type_id%5D%5B{}%5D={}",
+                operator, value
+            ));
+            assert_eq!(clause.statement, sql);
+            assert!(clause.values.is_empty());
+        }
+
+        assert!(
+            rejection("filter%5Bmember_type_id%5D%5Bnull%5D=maybe")
+                .contains("takes no value or a boolean")
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_filters_and_operators() {
+        assert!(rejection("filter%5Bpasswd%5D=x").contains("filter `passwd` is not supported"));
+        assert!(
+            rejection("filter%5Bloan_date%5D%5Bsince%5D=2024-01-01")
+                .contains("operator `since` is not supported")
+        );
+    }
+
+    #[test]
+    fn rejects_operators_the_field_does_not_allow() {
+        let message = rejection("filter%5Btitle%5D%5Bgt%5D=a");
+        assert!(message.contains("does not support `gt`"));
+        assert!(message.contains("`like`"));
+
+        // `null` is only offered on nullable columns.
+        assert!(rejection("filter%5Bloan_date%5D%5Bnull%5D=").contains("does not support `null`"));
+        assert!(rejection("filter%5Bitem_code%5D%5Bbetween%5D=a,b").contains("does not support"));
+    }
+
+    #[test]
+    fn drops_filters_with_an_empty_value() {
+        let clauses = params("filter%5Btitle%5D=&filter%5Bloan_date%5D%5Bgte%5D=%20")
+            .filter_clauses(FIELDS)
+            .unwrap();
+        assert!(clauses.is_empty());
+    }
+
+    #[test]
+    fn single_values_may_not_be_lists() {
+        assert!(rejection("filter%5Bitem_code%5D=B001,B002").contains("filter[item_code][in]"));
+        assert!(
+            rejection("filter%5Bloan_date%5D=2024-01-01,2024-02-01")
+                .contains("takes a single value")
+        );
+
+        // Free text without `in` may contain commas.
+        let clause = clause("filter%5Btitle%5D=war,%20peace");
+        assert_eq!(
+            clause.values,
+            vec![FilterValue::Text("%war, peace%".into())]
+        );
+    }
+
+    #[test]
+    fn parses_integers_and_booleans() {
+        assert_eq!(
+            clause("filter%5Bmember_type_id%5D=3").values,
+            vec![FilterValue::Integer(3)]
+        );
+        assert_eq!(
+            clause("filter%5Bis_return%5D=true").values,
+            vec![FilterValue::Boolean(true)]
+        );
+        assert_eq!(
+            clause("filter%5Bis_return%5D=0").values,
+            vec![FilterValue::Boolean(false)]
+        );
+        assert!(rejection("filter%5Bmember_type_id%5D=three").contains("must be an integer"));
+        assert!(rejection("filter%5Bis_return%5D=yes").contains("must be boolean"));
+    }
+
+    #[test]
+    fn parses_dates() {
+        for value in ["2024-13-01", "01-03-2024", "2024-03-01T10:00:00"] {
+            assert!(
+                rejection(&format!("filter%5Bloan_date%5D={}", value))
+                    .contains("must be a date (YYYY-MM-DD)")
+            );
+        }
+    }
+
+    #[test]
+    fn parses_date_times_and_plain_dates_as_midnight() {
+        let cases = [
+            ("2024-03-01T10:15:30", "2024-03-01 10:15:30"),
+            ("2024-03-01%2010:15:30", "2024-03-01 10:15:30"),
+            ("2024-03-01", "2024-03-01 00:00:00"),
+        ];
+        for (value, expected) in cases {
+            let clause = clause(&format!("filter%5Blog_date%5D%5Bgte%5D={}", value));
+            assert_eq!(clause.values, vec![datetime(expected)]);
+        }
+
+        assert!(rejection("filter%5Blog_date%5D=yesterday").contains("must be a date-time"));
+    }
+
+    #[derive(Deserialize)]
+    struct MemberPatch {
+        #[serde(default, deserialize_with = "nullable")]
+        member_name: Option<Option<String>>,
+        #[serde(default, deserialize_with = "nullable")]
+        member_email: Option<Option<String>>,
+        #[serde(default, deserialize_with = "nullable")]
+        member_type_id: Option<Option<i32>>,
+    }
+
+    fn patch(json: &str) -> MemberPatch {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn nullable_tells_absent_from_null() {
+        let absent = patch("{}");
+        assert_eq!(absent.member_email, None);
+        assert_eq!(absent.member_type_id, None);
+
+        let null = patch(r#"{"member_email": null, "member_type_id": null}"#);
+        assert_eq!(null.member_email, Some(None));
+        assert_eq!(null.member_type_id, Some(None));
+
+        let value = patch(r#"{"member_email": "ana@example.org", "member_type_id": 2}"#);
+        assert_eq!(value.member_email, Some(Some("ana@example.org".into())));
+        assert_eq!(value.member_type_id, Some(Some(2)));
+    }
+
+    #[test]
+    fn absent_attributes_leave_columns_alone() {
+        let payload = patch("{}");
+        let mut set = PatchSet::default();
+        set.nullable("member_email", payload.member_email);
+        set.required("member_name", payload.member_name).unwrap();
+        assert!(set.is_empty());
+        assert!(set.values.is_empty());
+    }
+
+    #[test]
+    fn null_sets_a_nullable_column_to_null() {
+        let payload = patch(r#"{"member_email": null, "member_type_id": null}"#);
+        let mut set = PatchSet::default();
+        set.nullable("member_email", payload.member_email);
+        set.nullable("member_type_id", payload.member_type_id);
+        assert_eq!(set.assignments, ["member_email = ?", "member_type_id = ?"]);
+        assert_eq!(
+            set.values,
+            [PatchValue::Text(None), PatchValue::Integer(None)]
+        );
+    }
+
+    #[test]
+    fn a_value_sets_the_column() {
+        let payload = patch(r#"{"member_email": "ana@example.org", "member_type_id": 2}"#);
+        let mut set = PatchSet::default();
+        set.nullable("member_email", payload.member_email);
+        set.nullable("member_type_id", payload.member_type_id);
+        assert_eq!(
+            set.values,
+            [
+                PatchValue::Text(Some("ana@example.org".into())),
+                PatchValue::Integer(Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn required_rejects_null() {
+        let mut set = PatchSet::default();
+        match set.required("member_name", patch(r#"{"member_name": null}"#).member_name) {
+            Err(AppError::BadRequest(message)) => {
+                assert_eq!(message, "`member_name` cannot be null")
+            }
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+        assert!(set.is_empty());
+
+        set.required(
+            "member_name",
+            patch(r#"{"member_name": "Ana"}"#).member_name,
+        )
+        .unwrap();
+        assert_eq!(set.values, [PatchValue::Text(Some("Ana".into()))]);
+    }
+
+    #[test]
+    fn update_sql_keeps_columns_and_binds_in_order() {
+        let expire_date = NaiveDate::from_ymd_opt(2025, 12, 31).unwrap();
+        let mut set = PatchSet::default();
+        set.required("member_name", Some(Some("Ana".to_string())))
+            .unwrap();
+        set.nullable("member_email", Some(None::<String>));
+        set.touch("last_update = CURDATE()");
+        set.set("expire_date", expire_date);
+
+        assert_eq!(
+            set.update_sql("member", "member_id"),
+            "UPDATE member SET member_name = ?, member_email = ?, last_update = CURDATE(), expire_date = ? WHERE member_id = ?"
+        );
+        // `touch` binds nothing, so the values line up with the placeholders.
+        assert_eq!(
+            set.values,
+            [
+                PatchValue::Text(Some("Ana".into())),
+                PatchValue::Text(None),
+                PatchValue::Date(Some(expire_date)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, MySqlConnection};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{AuthUser, ModuleAccess, Permission},
+    circulation,
+    config::AppState,
+    error::AppError,
+    jsonapi::{
+        JsonApi, JsonApiDocument, ResourcePayload, collection_document, pagination_meta, resource,
+        resource_with_fields, single_document,
+    },
+    resources::{
+        FilterField, FilterOperator, FilterValueType, ListParams, SortField, bind_filters_to_query,
+        bind_filters_to_scalar, where_clause,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Reserve {
+    pub reserve_id: i64,
+    pub member_id: String,
+    pub biblio_id: i64,
+    /// Empty for holds on the title rather than on a specific copy.
+    pub item_code: String,
+    pub reserve_date: NaiveDateTime,
+    pub title: Option<String>,
+    /// 1-based position among all holds on the same title, oldest first.
+    pub queue_position: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateReserve {
+    pub member_id: String,
+    pub biblio_id: Option<i64>,
+    pub item_code: Option<String>,
+}
+
+impl ResourcePayload for CreateReserve {
+    const TYPE: &'static str = "reserves";
+}
+
+pub const RESERVE_SELECT: &str = "SELECT reserve.reserve_id, reserve.member_id, reserve.biblio_id, reserve.item_code, reserve.reserve_date, b.title, (SELECT COUNT(*) FROM reserve q WHERE q.biblio_id = reserve.biblio_id AND (q.reserve_date < reserve.reserve_date OR (q.reserve_date = reserve.reserve_date AND q.reserve_id <= reserve.reserve_id))) AS queue_position FROM reserve LEFT JOIN biblio b ON b.biblio_id = reserve.biblio_id";
+
+const RESERVE_SORTS: &[SortField<'_>] = &[
+    SortField::new("reserve_date", "reserve.reserve_date"),
+    SortField::new("reserve_id", "reserve.reserve_id"),
+];
+
+const RESERVE_FILTERS: &[FilterField<'_>] = &[
+    FilterField::new(
+        "member_id",
+        "reserve.member_id",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "biblio_id",
+        "reserve.biblio_id",
+        FilterOperator::Equals,
+        FilterValueType::Integer,
+    ),
+    FilterField::new(
+        "item_code",
+        "reserve.item_code",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+];
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_reserves).post(create_reserve))
+        .route("/:reserve_id", get(get_reserve).delete(cancel_reserve))
+}
+
+async fn fetch_reserve(conn: &mut MySqlConnection, reserve_id: i64) -> Result<Reserve, AppError> {
+    let sql = format!("{} WHERE reserve.reserve_id = ?", RESERVE_SELECT);
+    let reserve = sqlx::query_as::<_, Reserve>(&sql)
+        .bind(reserve_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(reserve)
+}
+
+#[utoipa::path(
+    get,
+    path = "/reserves",
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Reserves"
+)]
+async fn list_reserves(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Read)?;
+
+    let pagination = params.pagination();
+    let reserve_fields = params.fieldset("reserves");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause = params.sort_clause(
+        RESERVE_SORTS,
+        "reserve.reserve_date ASC, reserve.reserve_id ASC",
+    )?;
+    let filters = params.filter_clauses(RESERVE_FILTERS)?;
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM reserve {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "{} {} ORDER BY {} LIMIT ? OFFSET ?",
+        RESERVE_SELECT, where_sql, sort_clause
+    );
+    let reserves = bind_filters_to_query(sqlx::query_as::<_, Reserve>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = reserves
+        .into_iter()
+        .map(|reserve| {
+            resource_with_fields(
+                "reserves",
+                reserve.reserve_id.to_string(),
+                reserve,
+                reserve_fields,
+            )
+        })
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/reserves/{reserve_id}",
+    params(("reserve_id" = i64, Path, description = "Reservation ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Reserves"
+)]
+async fn get_reserve(
+    State(state): State<AppState>,
+    Path(reserve_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Read)?;
+
+    let mut conn = state.pool.acquire().await?;
+    let reserve = fetch_reserve(&mut conn, reserve_id).await?;
+
+    Ok(Json(single_document(resource(
+        "reserves",
+        reserve.reserve_id.to_string(),
+        reserve,
+    ))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/reserves",
+    request_body(content = CreateReserve, content_type = "application/vnd.api+json"),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 422, description = "Reservation refused by circulation policy"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Reserves"
+)]
+async fn create_reserve(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<CreateReserve>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    state.require_transactional_tables()?;
+
+    let today = chrono::Utc::now().date_naive();
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut tx = state.pool.begin().await?;
+    let plan = circulation::plan_hold(
+        &mut tx,
+        &payload.member_id,
+        payload.biblio_id,
+        payload.item_code.as_deref(),
+        today,
+    )
+    .await?;
+
+    let result = sqlx::query(
+        "INSERT INTO reserve (member_id, biblio_id, item_code, reserve_date) VALUES (?, ?, ?, ?)",
+    )
+    .bind(&plan.member_id)
+    .bind(plan.biblio_id)
+    .bind(&plan.item_code)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
+    let reserve = fetch_reserve(&mut tx, result.last_insert_id() as i64).await?;
+    tx.commit().await?;
+
+    Ok(Json(single_document(resource(
+        "reserves",
+        reserve.reserve_id.to_string(),
+        reserve,
+    ))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/reserves/{reserve_id}",
+    params(("reserve_id" = i64, Path, description = "Reservation ID")),
+    responses((status = 204, description = "Reservation cancelled")),
+    security(("bearerAuth" = [])),
+    tag = "Reserves"
+)]
+async fn cancel_reserve(
+    State(state): State<AppState>,
+    Path(reserve_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.require_access(ModuleAccess::Circulation, Permission::Write)?;
+    state.require_transactional_tables()?;
+
+    let result = sqlx::query("DELETE FROM reserve WHERE reserve_id = ?")
+        .bind(reserve_id)
+        .execute(&state.pool)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
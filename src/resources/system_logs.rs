@@ -0,0 +1,178 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    routing::get,
+};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{AuthUser, ModuleAccess, Permission},
+    config::AppState,
+    error::AppError,
+    jsonapi::{
+        JsonApiDocument, collection_document, pagination_meta, resource, resource_with_fields,
+        single_document,
+    },
+    resources::{
+        FilterField, FilterOperator, FilterValueType, ListParams, RANGE_OPERATORS, SortField,
+        bind_filters_to_query, bind_filters_to_scalar, where_clause,
+    },
+};
+
+/// A `system_log` row, written by SLiMS itself and by this API.
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+pub struct SystemLog {
+    pub log_id: i64,
+    /// `staff`, `member` or `system`.
+    pub log_type: String,
+    /// `system_log.id`: the user ID for staff entries, the member ID for
+    /// member entries and `api-key:{id}` for changes made with an API key.
+    pub actor_id: Option<String>,
+    /// `system_log.log_location`, e.g. `bibliography` or `circulation`.
+    pub module: String,
+    pub sub_module: Option<String>,
+    pub action: Option<String>,
+    pub message: String,
+    pub log_date: NaiveDateTime,
+}
+
+const SYSTEM_LOG_SELECT: &str = "SELECT log_id, log_type, id AS actor_id, log_location AS module, sub_module, action, log_msg AS message, log_date FROM system_log";
+
+const SYSTEM_LOG_SORTS: &[SortField<'_>] = &[
+    SortField::new("log_date", "system_log.log_date"),
+    SortField::new("log_id", "system_log.log_id"),
+];
+
+const SYSTEM_LOG_FILTERS: &[FilterField<'_>] = &[
+    FilterField::new(
+        "user",
+        "system_log.id",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "log_type",
+        "system_log.log_type",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "module",
+        "system_log.log_location",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "sub_module",
+        "system_log.sub_module",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "action",
+        "system_log.action",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "message",
+        "system_log.log_msg",
+        FilterOperator::Like,
+        FilterValueType::Text,
+    ),
+    // Compared by day, so `lte` includes the whole of its date.
+    FilterField::new(
+        "log_date",
+        "DATE(system_log.log_date)",
+        FilterOperator::Equals,
+        FilterValueType::Date,
+    )
+    .with_operators(RANGE_OPERATORS),
+];
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_system_logs))
+        .route("/:log_id", get(get_system_log))
+}
+
+#[utoipa::path(
+    get,
+    path = "/system-logs",
+    responses((status = 200, description = "Paginated system log entries, newest first", body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "System Logs"
+)]
+async fn list_system_logs(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::System, Permission::Read)?;
+
+    let pagination = params.pagination();
+    let log_fields = params.fieldset("system-logs");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause = params.sort_clause(
+        SYSTEM_LOG_SORTS,
+        "system_log.log_date DESC, system_log.log_id DESC",
+    )?;
+    let filters = params.filter_clauses(SYSTEM_LOG_FILTERS)?;
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM system_log {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "{} {} ORDER BY {} LIMIT ? OFFSET ?",
+        SYSTEM_LOG_SELECT, where_sql, sort_clause
+    );
+    let rows = bind_filters_to_query(sqlx::query_as::<_, SystemLog>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = rows
+        .into_iter()
+        .map(|log| resource_with_fields("system-logs", log.log_id.to_string(), log, log_fields))
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/system-logs/{log_id}",
+    params(("log_id" = i64, Path, description = "Log ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "System Logs"
+)]
+async fn get_system_log(
+    State(state): State<AppState>,
+    Path(log_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::System, Permission::Read)?;
+
+    let sql = format!("{} WHERE log_id = ?", SYSTEM_LOG_SELECT);
+    let log = sqlx::query_as::<_, SystemLog>(&sql)
+        .bind(log_id)
+        .fetch_one(&state.pool)
+        .await?;
+
+    Ok(Json(single_document(resource(
+        "system-logs",
+        log.log_id.to_string(),
+        log,
+    ))))
+}
@@ -0,0 +1,449 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, MySqlConnection, QueryBuilder};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, AuthUser, ModuleAccess, Permission},
+    config::AppState,
+    error::AppError,
+    jsonapi::{
+        JsonApi, JsonApiDocument, ResourcePayload, collection_document, pagination_meta, resource,
+        resource_with_fields, single_document,
+    },
+    password,
+    permission_cache::Subject,
+    resources::{
+        FilterField, FilterOperator, FilterValueType, ListParams, SortField, bind_filters_to_query,
+        bind_filters_to_scalar, where_clause,
+    },
+};
+
+/// The first account created by the SLiMS installer; like the PHP admin,
+/// the API refuses to delete it.
+const MAIN_ADMIN_ID: i64 = 1;
+
+#[derive(Debug, FromRow)]
+struct UserRow {
+    user_id: i64,
+    username: String,
+    realname: String,
+    email: Option<String>,
+    user_type: Option<i16>,
+    groups: Option<String>,
+    two_factor: Option<String>,
+    last_login: Option<NaiveDateTime>,
+    last_login_ip: Option<String>,
+    input_date: Option<NaiveDate>,
+    last_update: Option<NaiveDate>,
+}
+
+/// A staff account. The password hash and 2FA secret are never returned.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StaffUser {
+    pub user_id: i64,
+    pub username: String,
+    pub realname: String,
+    pub email: Option<String>,
+    pub user_type: Option<i16>,
+    /// IDs of the `user_group` rows the user belongs to.
+    pub groups: Vec<i64>,
+    pub two_factor_enabled: bool,
+    pub last_login: Option<NaiveDateTime>,
+    pub last_login_ip: Option<String>,
+    pub input_date: Option<NaiveDate>,
+    pub last_update: Option<NaiveDate>,
+}
+
+impl UserRow {
+    fn into_user(self) -> StaffUser {
+        StaffUser {
+            user_id: self.user_id,
+            username: self.username,
+            realname: self.realname,
+            email: self.email,
+            user_type: self.user_type,
+            groups: auth::parse_groups(self.groups.as_deref()),
+            two_factor_enabled: self
+                .two_factor
+                .is_some_and(|secret| !secret.trim().is_empty()),
+            last_login: self.last_login,
+            last_login_ip: self.last_login_ip,
+            input_date: self.input_date,
+            last_update: self.last_update,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateUser {
+    pub username: String,
+    pub realname: String,
+    pub password: String,
+    pub email: Option<String>,
+    pub user_type: Option<i16>,
+    #[serde(default)]
+    pub groups: Vec<i64>,
+}
+
+impl ResourcePayload for CreateUser {
+    const TYPE: &'static str = "users";
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateUser {
+    pub username: String,
+    pub realname: String,
+    /// A new password; the current one is kept when omitted.
+    pub password: Option<String>,
+    pub email: Option<String>,
+    pub user_type: Option<i16>,
+    #[serde(default)]
+    pub groups: Vec<i64>,
+}
+
+impl ResourcePayload for UpdateUser {
+    const TYPE: &'static str = "users";
+}
+
+const USER_SELECT: &str = "SELECT user_id, username, realname, email, user_type, `groups`, `2fa` AS two_factor, last_login, last_login_ip, input_date, last_update FROM `user`";
+
+const USER_SORTS: &[SortField<'_>] = &[
+    SortField::new("username", "`user`.username"),
+    SortField::new("realname", "`user`.realname"),
+    SortField::new("last_login", "`user`.last_login"),
+];
+
+const USER_FILTERS: &[FilterField<'_>] = &[
+    FilterField::new(
+        "username",
+        "`user`.username",
+        FilterOperator::Equals,
+        FilterValueType::Text,
+    ),
+    FilterField::new(
+        "realname",
+        "`user`.realname",
+        FilterOperator::Like,
+        FilterValueType::Text,
+    ),
+];
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_users).post(create_user))
+        .route(
+            "/:user_id",
+            get(get_user).put(update_user).delete(delete_user),
+        )
+}
+
+async fn fetch_user(conn: &mut MySqlConnection, user_id: i64) -> Result<StaffUser, AppError> {
+    let sql = format!("{} WHERE user_id = ?", USER_SELECT);
+    let row = sqlx::query_as::<_, UserRow>(&sql)
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(row.into_user())
+}
+
+/// Deduplicates the requested groups and checks that each one exists.
+async fn validate_groups(conn: &mut MySqlConnection, groups: &[i64]) -> Result<Vec<i64>, AppError> {
+    let mut group_ids = groups.to_vec();
+    group_ids.sort_unstable();
+    group_ids.dedup();
+    if group_ids.is_empty() {
+        return Ok(group_ids);
+    }
+
+    let mut builder = QueryBuilder::new("SELECT group_id FROM user_group WHERE group_id IN (");
+    let mut separated = builder.separated(",");
+    for group_id in &group_ids {
+        separated.push_bind(group_id);
+    }
+    builder.push(")");
+    let existing: Vec<i64> = builder.build_query_scalar().fetch_all(&mut *conn).await?;
+
+    if let Some(missing) = group_ids.iter().find(|id| !existing.contains(id)) {
+        return Err(AppError::BadRequest(format!(
+            "group {} does not exist",
+            missing
+        )));
+    }
+
+    Ok(group_ids)
+}
+
+async fn ensure_username_free(
+    conn: &mut MySqlConnection,
+    username: &str,
+    except_user_id: Option<i64>,
+) -> Result<(), AppError> {
+    let taken: Option<i64> = sqlx::query_scalar("SELECT user_id FROM `user` WHERE username = ?")
+        .bind(username)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    match taken {
+        Some(user_id) if Some(user_id) != except_user_id => Err(AppError::BadRequest(format!(
+            "username `{}` is already taken",
+            username
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn required(value: &str, field: &str) -> Result<(), AppError> {
+    if value.trim().is_empty() {
+        return Err(AppError::BadRequest(format!("{} cannot be empty", field)));
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/users",
+    responses((status = 200, description = "Paginated staff users", body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Users"
+)]
+async fn list_users(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::System, Permission::Read)?;
+
+    let pagination = params.pagination();
+    let user_fields = params.fieldset("users");
+    let (limit, offset, page, per_page) = pagination.limit_offset();
+    let sort_clause = params.sort_clause(USER_SORTS, "`user`.username ASC")?;
+    let filters = params.filter_clauses(USER_FILTERS)?;
+    let where_sql = where_clause(&filters);
+
+    let count_sql = format!("SELECT COUNT(*) FROM `user` {}", where_sql);
+    let total = bind_filters_to_scalar(sqlx::query_scalar::<_, i64>(&count_sql), &filters)
+        .fetch_one(&state.pool)
+        .await?;
+
+    let data_sql = format!(
+        "{} {} ORDER BY {} LIMIT ? OFFSET ?",
+        USER_SELECT, where_sql, sort_clause
+    );
+    let rows = bind_filters_to_query(sqlx::query_as::<_, UserRow>(&data_sql), &filters)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.pool)
+        .await?;
+
+    let data = rows
+        .into_iter()
+        .map(|row| {
+            let user = row.into_user();
+            resource_with_fields("users", user.user_id.to_string(), user, user_fields)
+        })
+        .collect();
+
+    Ok(Json(collection_document(
+        data,
+        pagination_meta(page, per_page, total),
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}",
+    params(("user_id" = i64, Path, description = "User ID")),
+    responses((status = 200, body = JsonApiDocument)),
+    security(("bearerAuth" = [])),
+    tag = "Users"
+)]
+async fn get_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_access(ModuleAccess::System, Permission::Read)?;
+
+    let mut conn = state.pool.acquire().await?;
+    let user = fetch_user(&mut conn, user_id).await?;
+
+    Ok(Json(single_document(resource(
+        "users",
+        user.user_id.to_string(),
+        user,
+    ))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/users",
+    request_body(content = CreateUser, content_type = "application/vnd.api+json"),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 400, description = "Invalid fields, taken username or unknown group"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Users"
+)]
+async fn create_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    JsonApi {
+        attributes: payload,
+        ..
+    }: JsonApi<CreateUser>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_account_admin()?;
+
+    let username = payload.username.trim();
+    required(username, "username")?;
+    required(&payload.realname, "realname")?;
+    let passwd = auth::hash_password(&payload.password)?;
+
+    let mut conn = state.pool.acquire().await?;
+    ensure_username_free(&mut conn, username, None).await?;
+    let groups = validate_groups(&mut conn, &payload.groups).await?;
+
+    let result = sqlx::query(
+        "INSERT INTO `user` (username, realname, passwd, email, user_type, `groups`, input_date, last_update) VALUES (?, ?, ?, ?, ?, ?, CURDATE(), CURDATE())",
+    )
+    .bind(username)
+    .bind(payload.realname.trim())
+    .bind(&passwd)
+    .bind(&payload.email)
+    .bind(payload.user_type)
+    .bind(auth::serialize_groups(&groups))
+    .execute(&mut *conn)
+    .await?;
+
+    let user = fetch_user(&mut conn, result.last_insert_id() as i64).await?;
+
+    Ok(Json(single_document(resource(
+        "users",
+        user.user_id.to_string(),
+        user,
+    ))))
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}",
+    request_body(content = UpdateUser, content_type = "application/vnd.api+json"),
+    params(("user_id" = i64, Path, description = "User ID")),
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 400, description = "Invalid fields, taken username or unknown group"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Users"
+)]
+async fn update_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    auth: AuthUser,
+    payload: JsonApi<UpdateUser>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    auth.require_account_admin()?;
+    let payload = payload.for_id(&user_id.to_string())?;
+
+    let username = payload.username.trim();
+    required(username, "username")?;
+    required(&payload.realname, "realname")?;
+    let passwd = payload
+        .password
+        .as_deref()
+        .map(auth::hash_password)
+        .transpose()?;
+
+    let mut tx = state.pool.begin().await?;
+    fetch_user(&mut tx, user_id).await?;
+    ensure_username_free(&mut tx, username, Some(user_id)).await?;
+    let groups = validate_groups(&mut tx, &payload.groups).await?;
+
+    sqlx::query(
+        "UPDATE `user` SET username = ?, realname = ?, passwd = COALESCE(?, passwd), email = ?, user_type = ?, `groups` = ?, last_update = CURDATE() WHERE user_id = ?",
+    )
+    .bind(username)
+    .bind(payload.realname.trim())
+    .bind(&passwd)
+    .bind(&payload.email)
+    .bind(payload.user_type)
+    .bind(auth::serialize_groups(&groups))
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    // A new password signs the account out everywhere, as a reset does.
+    if passwd.is_some() {
+        password::revoke_refresh_tokens(&mut tx, user_id).await?;
+    }
+    let user = fetch_user(&mut tx, user_id).await?;
+    tx.commit().await?;
+
+    state.permission_cache.invalidate(&Subject::Staff(user_id));
+
+    Ok(Json(single_document(resource(
+        "users",
+        user.user_id.to_string(),
+        user,
+    ))))
+}
+
+/// Deletes a staff account together with its refresh tokens and 2FA
+/// recovery codes. The main administrator and the caller's own account
+/// cannot be deleted.
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}",
+    params(("user_id" = i64, Path, description = "User ID")),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 400, description = "The main administrator or the caller's own account"),
+        (status = 404, description = "No such user"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Users"
+)]
+async fn delete_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.require_account_admin()?;
+
+    if user_id == MAIN_ADMIN_ID {
+        return Err(AppError::BadRequest(
+            "the main administrator cannot be deleted".into(),
+        ));
+    }
+    if user_id == auth.claims.sub {
+        return Err(AppError::BadRequest(
+            "you cannot delete your own account".into(),
+        ));
+    }
+
+    let mut tx = state.pool.begin().await?;
+    let deleted = sqlx::query("DELETE FROM `user` WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    sqlx::query("DELETE FROM user_tokens WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    state.permission_cache.invalidate(&Subject::Staff(user_id));
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,808 @@
+//! 3M SIP2 listener for self-check kiosks. Each connection is a session
+//! that must log in (93) with a staff account that has Circulation write
+//! access; transactions then go through the same `circulation` functions as
+//! the REST handlers.
+
+mod protocol;
+
+use std::net::IpAddr;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use sqlx::{FromRow, MySqlConnection};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    audit::{LogType, SystemLogEntry, write_system_log},
+    auth::{self, ModuleAccess, Permission},
+    circulation::{self, LoanEvent, PolicyViolation},
+    config::AppState,
+    error::AppError,
+    login_guard::{self, Subject},
+    resources::fines::fetch_balance,
+    two_factor,
+};
+
+use protocol::{ParseError, Request, Response, due_date, timestamp};
+
+/// Messages this ACS supports, in the order of the `BX` field of the 98
+/// response: patron status, checkout, checkin, block patron, SC/ACS status,
+/// resend, login, patron information, end session, fee paid, item
+/// information, item status update, patron enable, hold, renew, renew all.
+const SUPPORTED_MESSAGES: &str = "YYYNYYYYNNYNNNYN";
+
+/// Longest message read before the connection is dropped. Real SIP2
+/// messages stay well under this, and the limit applies before login too.
+const MAX_MESSAGE_LEN: u64 = 4096;
+
+#[derive(Clone, Debug)]
+pub struct Sip2Settings {
+    pub institution_id: String,
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    state: AppState,
+    settings: Sip2Settings,
+) -> anyhow::Result<()> {
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let session = Session::new(state.clone(), settings.clone(), peer.ip().to_canonical());
+        tokio::spawn(async move {
+            tracing::info!("sip2 connection from {}", peer);
+            if let Err(err) = session.run(socket).await {
+                tracing::warn!("sip2 connection from {} failed: {}", peer, err);
+            }
+        });
+    }
+}
+
+struct Session {
+    state: AppState,
+    settings: Sip2Settings,
+    peer: IpAddr,
+    /// Staff user ID after a successful login, recorded as the loan `uid`.
+    uid: Option<i64>,
+    /// Username of that account, for the `system_log` messages.
+    username: String,
+    last_response: Option<String>,
+}
+
+enum Reply {
+    Send(String),
+    Close,
+}
+
+impl Session {
+    fn new(state: AppState, settings: Sip2Settings, peer: IpAddr) -> Self {
+        Session {
+            state,
+            settings,
+            peer,
+            uid: None,
+            username: String::new(),
+            last_response: None,
+        }
+    }
+
+    async fn run(mut self, socket: TcpStream) -> anyhow::Result<()> {
+        let (reader, mut writer) = socket.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut buffer = Vec::new();
+
+        loop {
+            buffer.clear();
+            let read = (&mut reader)
+                .take(MAX_MESSAGE_LEN)
+                .read_until(b'\r', &mut buffer)
+                .await?;
+            if read == 0 {
+                return Ok(());
+            }
+            if read as u64 == MAX_MESSAGE_LEN && buffer.last() != Some(&b'\r') {
+                tracing::warn!(
+                    "sip2 connection from {} sent a message over {} bytes",
+                    self.peer,
+                    MAX_MESSAGE_LEN
+                );
+                return Ok(());
+            }
+
+            let raw = String::from_utf8_lossy(&buffer);
+            let line = raw.trim_matches(|c| c == '\r' || c == '\n');
+            if line.is_empty() {
+                continue;
+            }
+
+            match self.handle(line).await {
+                Reply::Send(message) => {
+                    writer.write_all(message.as_bytes()).await?;
+                    writer.write_all(b"\r").await?;
+                }
+                Reply::Close => return Ok(()),
+            }
+        }
+    }
+
+    async fn handle(&mut self, line: &str) -> Reply {
+        let request = match Request::parse(line) {
+            Ok(request) => request,
+            Err(ParseError::Checksum) => return Reply::Send(Response::new("96").finish(None)),
+            Err(ParseError::TooShort) => {
+                tracing::warn!("sip2 ignored malformed message {:?}", line);
+                return Reply::Send(Response::new("96").finish(None));
+            }
+        };
+
+        if request.command == "97" {
+            return match &self.last_response {
+                Some(last) => Reply::Send(last.clone()),
+                None => Reply::Send(Response::new("96").finish(None)),
+            };
+        }
+
+        let response = match request.command.as_str() {
+            "93" => self.login(&request).await,
+            "99" => Ok(self.sc_status()),
+            _ if self.uid.is_none() => {
+                tracing::warn!(
+                    "sip2 message {} before login, closing connection",
+                    request.command
+                );
+                return Reply::Close;
+            }
+            "23" => self.patron_status(&request).await,
+            "63" => self.patron_information(&request).await,
+            "11" => self.checkout(&request).await,
+            "09" => self.checkin(&request).await,
+            "29" => self.renew(&request).await,
+            "17" => self.item_information(&request).await,
+            other => {
+                tracing::warn!("sip2 message {} is not supported", other);
+                return Reply::Send(Response::new("96").finish(None));
+            }
+        };
+
+        match response {
+            Ok(response) => {
+                let message = response.finish(request.sequence);
+                self.last_response = Some(message.clone());
+                Reply::Send(message)
+            }
+            Err(err) => {
+                tracing::error!("sip2 message {} failed: {}", request.command, err);
+                Reply::Close
+            }
+        }
+    }
+
+    fn now() -> NaiveDateTime {
+        chrono::Utc::now().naive_utc()
+    }
+
+    /// Files a kiosk checkout, return or renewal under the logged-in staff
+    /// account, like the desk endpoints do.
+    async fn log_loan(
+        &self,
+        conn: &mut MySqlConnection,
+        event: &LoanEvent<'_>,
+    ) -> Result<(), AppError> {
+        write_system_log(
+            conn,
+            &SystemLogEntry {
+                log_type: LogType::Staff,
+                id: self.uid.map(|uid| uid.to_string()),
+                location: "circulation",
+                sub_module: "loan",
+                action: event.action,
+                message: event.message(&format!("{} via SIP2", self.username)),
+            },
+        )
+        .await
+    }
+
+    /// Goes through the same login throttle as the HTTP login, so a kiosk
+    /// port cannot be used to guess passwords. SIP2 has no way to ask for a
+    /// second factor, so accounts with two-factor authentication enabled
+    /// are refused; kiosks should use a dedicated account without it.
+    async fn login(&mut self, request: &Request) -> Result<Response, AppError> {
+        let username = request.field("CN");
+        let result =
+            auth::checked_staff_login(&self.state, username, request.field("CO"), Some(self.peer))
+                .await;
+
+        let ok = match result {
+            Ok(Some((user, _))) if two_factor::enabled_secret(&user).is_some() => {
+                tracing::warn!(
+                    "sip2 login by {} refused: the account uses two-factor authentication",
+                    user.username
+                );
+                false
+            }
+            Ok(Some((user, access))) => {
+                let allowed =
+                    auth::has_access(&access, ModuleAccess::Circulation, Permission::Write);
+                if allowed {
+                    self.uid = Some(user.user_id);
+                    self.username = user.username.clone();
+                    login_guard::succeeded(&self.state, Subject::Staff(username), Some(self.peer))
+                        .await?;
+                } else {
+                    tracing::warn!("sip2 login by {} lacks circulation access", user.username);
+                }
+                allowed
+            }
+            Ok(None) => false,
+            Err(AppError::TooManyRequests(_)) => {
+                tracing::warn!("sip2 login by {} from {} is throttled", username, self.peer);
+                false
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Response::new("94").fixed(if ok { "1" } else { "0" }))
+    }
+
+    fn sc_status(&self) -> Response {
+        Response::new("98")
+            .flag(true)
+            .flag(true)
+            .flag(true)
+            .flag(true)
+            .flag(false)
+            .flag(false)
+            .fixed("030")
+            .fixed("003")
+            .fixed(&timestamp(Self::now()))
+            .fixed("2.00")
+            .field("AO", &self.settings.institution_id)
+            .field("BX", SUPPORTED_MESSAGES)
+    }
+
+    async fn patron_status(&self, request: &Request) -> Result<Response, AppError> {
+        let mut conn = self.state.pool.acquire().await?;
+        let patron = PatronSummary::load(&mut conn, request.field("AA")).await?;
+        let password_ok = patron.password_ok(request.field("AD"));
+
+        Ok(Response::new("24")
+            .fixed(&patron.status_flags())
+            .fixed("000")
+            .fixed(&timestamp(Self::now()))
+            .field("AO", &self.settings.institution_id)
+            .field("AA", request.field("AA"))
+            .field("AE", patron.name())
+            .flag_field("BL", patron.patron.is_some())
+            .flag_field("CQ", password_ok)
+            .field("BV", &patron.balance.to_string())
+            .optional_field("AF", patron.block_reason().as_deref()))
+    }
+
+    async fn patron_information(&self, request: &Request) -> Result<Response, AppError> {
+        let mut conn = self.state.pool.acquire().await?;
+        let patron = PatronSummary::load(&mut conn, request.field("AA")).await?;
+        let password_ok = patron.password_ok(request.field("AD"));
+
+        // The summary flags follow language (3) and transaction date (18).
+        let summary_offset = 21;
+        let mut response = Response::new("64")
+            .fixed(&patron.status_flags())
+            .fixed("000")
+            .fixed(&timestamp(Self::now()))
+            .count(patron.holds)
+            .count(patron.overdue)
+            .count(patron.charged)
+            .count(if patron.balance > 0 { 1 } else { 0 })
+            .count(0)
+            .count(0)
+            .field("AO", &self.settings.institution_id)
+            .field("AA", request.field("AA"))
+            .field("AE", patron.name())
+            .field("BZ", &format!("{:04}", patron.hold_limit.clamp(0, 9999)))
+            .field("CB", &format!("{:04}", patron.loan_limit.clamp(0, 9999)))
+            .flag_field("BL", patron.patron.is_some())
+            .flag_field("CQ", password_ok)
+            .field("BV", &patron.balance.to_string());
+
+        if let Some(member) = &patron.patron {
+            response = response
+                .optional_field("BE", member.member_email.as_deref())
+                .optional_field("BF", member.member_phone.as_deref());
+
+            if request.fixed_flag(summary_offset) {
+                let holds: Vec<String> = sqlx::query_scalar(
+                    "SELECT COALESCE(NULLIF(r.item_code, ''), b.title) FROM reserve r LEFT JOIN biblio b ON b.biblio_id = r.biblio_id WHERE r.member_id = ? ORDER BY r.reserve_date",
+                )
+                .bind(&member.member_id)
+                .fetch_all(&mut *conn)
+                .await?;
+                for hold in &holds {
+                    response = response.field("AS", hold);
+                }
+            }
+            if request.fixed_flag(summary_offset + 1) {
+                for item_code in patron.item_codes(&mut conn, true).await? {
+                    response = response.field("AT", &item_code);
+                }
+            }
+            if request.fixed_flag(summary_offset + 2) {
+                for item_code in patron.item_codes(&mut conn, false).await? {
+                    response = response.field("AU", &item_code);
+                }
+            }
+        }
+
+        Ok(response.optional_field("AF", patron.block_reason().as_deref()))
+    }
+
+    async fn checkout(&self, request: &Request) -> Result<Response, AppError> {
+        let member_id = request.field("AA");
+        let item_code = request.field("AB");
+        let today = chrono::Utc::now().date_naive();
+        let now = Self::now();
+
+        let outcome = async {
+            self.state.require_transactional_tables()?;
+            let mut tx = self.state.pool.begin().await?;
+            circulation::lock_checkout(&mut tx, member_id, &[item_code]).await?;
+            check_patron_password(&mut tx, member_id, request.field("AD")).await?;
+            let plan = circulation::plan_checkout(&mut tx, member_id, item_code, today).await?;
+            circulation::checkout(
+                &mut tx,
+                &plan,
+                plan.due_date,
+                self.uid.unwrap_or_default(),
+                today,
+                now,
+            )
+            .await?;
+            self.log_loan(
+                &mut tx,
+                &LoanEvent {
+                    action: "checkout",
+                    item_code,
+                    member_id,
+                    due_date: plan.due_date,
+                },
+            )
+            .await?;
+            tx.commit().await?;
+            Ok::<_, AppError>(plan.due_date)
+        }
+        .await;
+
+        let mut conn = self.state.pool.acquire().await?;
+        let title = item_title(&mut conn, item_code).await?;
+        let (ok, due, message) = match outcome {
+            Ok(due) => (true, Some(due_date(due)), None),
+            Err(err) => (false, None, Some(screen_message(err)?)),
+        };
+
+        Ok(Response::new("12")
+            .fixed(if ok { "1" } else { "0" })
+            .flag(false)
+            .fixed("N")
+            .flag(ok)
+            .fixed(&timestamp(now))
+            .field("AO", &self.settings.institution_id)
+            .field("AA", member_id)
+            .field("AB", item_code)
+            .field("AJ", title.as_deref().unwrap_or_default())
+            .optional_field("AH", due.as_deref())
+            .optional_field("AF", message.as_deref()))
+    }
+
+    async fn checkin(&self, request: &Request) -> Result<Response, AppError> {
+        let item_code = request.field("AB");
+        let today = chrono::Utc::now().date_naive();
+        let now = Self::now();
+
+        let outcome = async {
+            self.state.require_transactional_tables()?;
+            let mut tx = self.state.pool.begin().await?;
+            let loan_id = circulation::open_loan_for_item(&mut tx, item_code).await?;
+            let plan = circulation::plan_return(&mut tx, loan_id, today).await?;
+            circulation::checkin(&mut tx, &plan, self.uid.unwrap_or_default(), today, now).await?;
+            self.log_loan(
+                &mut tx,
+                &LoanEvent {
+                    action: "return",
+                    item_code,
+                    member_id: plan.member_id.as_deref().unwrap_or_default(),
+                    due_date: plan.due_date,
+                },
+            )
+            .await?;
+            tx.commit().await?;
+            Ok::<_, AppError>(plan)
+        }
+        .await;
+
+        let mut conn = self.state.pool.acquire().await?;
+        let title = item_title(&mut conn, item_code).await?;
+        let location = item_location(&mut conn, item_code).await?;
+
+        let response = match outcome {
+            Ok(plan) => {
+                let on_hold = plan.waiting_hold.is_some();
+                let message = (plan.fine > 0).then(|| format!("Overdue fine: {}", plan.fine));
+                Response::new("10")
+                    .fixed("1")
+                    .flag(true)
+                    .fixed("N")
+                    .flag(on_hold)
+                    .fixed(&timestamp(now))
+                    .field("AO", &self.settings.institution_id)
+                    .field("AB", item_code)
+                    .field("AQ", location.as_deref().unwrap_or_default())
+                    .field("AJ", title.as_deref().unwrap_or_default())
+                    .optional_field("AA", plan.member_id.as_deref())
+                    .optional_field("AF", message.as_deref())
+                    .optional_field("CV", on_hold.then_some("01"))
+            }
+            Err(err) => Response::new("10")
+                .fixed("0")
+                .flag(false)
+                .fixed("N")
+                .flag(false)
+                .fixed(&timestamp(now))
+                .field("AO", &self.settings.institution_id)
+                .field("AB", item_code)
+                .field("AQ", location.as_deref().unwrap_or_default())
+                .field("AJ", title.as_deref().unwrap_or_default())
+                .field("AF", &screen_message(err)?),
+        };
+
+        Ok(response)
+    }
+
+    async fn renew(&self, request: &Request) -> Result<Response, AppError> {
+        let member_id = request.field("AA");
+        let item_code = request.field("AB");
+        let today = chrono::Utc::now().date_naive();
+        let now = Self::now();
+
+        let outcome = async {
+            self.state.require_transactional_tables()?;
+            let mut tx = self.state.pool.begin().await?;
+            check_patron_password(&mut tx, member_id, request.field("AD")).await?;
+            let loan_id = circulation::open_loan_for_item(&mut tx, item_code).await?;
+            let borrower: Option<String> =
+                sqlx::query_scalar("SELECT member_id FROM loan WHERE loan_id = ?")
+                    .bind(loan_id)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if borrower.as_deref() != Some(member_id) {
+                return Err(AppError::BadRequest(
+                    "item is not on loan to this patron".into(),
+                ));
+            }
+            let plan = circulation::plan_renewal(&mut tx, loan_id, today).await?;
+            circulation::renew(&mut tx, &plan, self.uid.unwrap_or_default(), now).await?;
+            self.log_loan(
+                &mut tx,
+                &LoanEvent {
+                    action: "renew",
+                    item_code,
+                    member_id,
+                    due_date: plan.due_date,
+                },
+            )
+            .await?;
+            tx.commit().await?;
+            Ok::<_, AppError>(plan.due_date)
+        }
+        .await;
+
+        let mut conn = self.state.pool.acquire().await?;
+        let title = item_title(&mut conn, item_code).await?;
+        let (ok, due, message) = match outcome {
+            Ok(due) => (true, Some(due_date(due)), None),
+            Err(err) => (false, None, Some(screen_message(err)?)),
+        };
+
+        Ok(Response::new("30")
+            .fixed(if ok { "1" } else { "0" })
+            .flag(ok)
+            .fixed("N")
+            .flag(false)
+            .fixed(&timestamp(now))
+            .field("AO", &self.settings.institution_id)
+            .field("AA", member_id)
+            .field("AB", item_code)
+            .field("AJ", title.as_deref().unwrap_or_default())
+            .optional_field("AH", due.as_deref())
+            .optional_field("AF", message.as_deref()))
+    }
+
+    async fn item_information(&self, request: &Request) -> Result<Response, AppError> {
+        let item_code = request.field("AB");
+        let mut conn = self.state.pool.acquire().await?;
+
+        let item = match circulation::fetch_item(&mut conn, item_code).await {
+            Ok(item) => item,
+            Err(AppError::Policy(_)) => {
+                return Ok(Response::new("18")
+                    .fixed("01")
+                    .fixed("00")
+                    .fixed("01")
+                    .fixed(&timestamp(Self::now()))
+                    .field("AB", item_code)
+                    .field("AJ", "")
+                    .field("AF", "Item not found"));
+            }
+            Err(err) => return Err(err),
+        };
+
+        let due: Option<NaiveDate> = sqlx::query_scalar(
+            "SELECT due_date FROM loan WHERE item_code = ? AND is_return = 0 LIMIT 1",
+        )
+        .bind(&item.item_code)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let queue: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM reserve WHERE item_code = ? OR (item_code = '' AND biblio_id = ?)",
+        )
+        .bind(&item.item_code)
+        .bind(item.biblio_id.unwrap_or(0))
+        .fetch_one(&mut *conn)
+        .await?;
+
+        // 04 charged, 03 available, 01 other (status does not allow loans).
+        let circulation_status = if due.is_some() {
+            "04"
+        } else if item.no_loan.unwrap_or(0) != 0 {
+            "01"
+        } else {
+            "03"
+        };
+
+        let title = item_title(&mut conn, &item.item_code).await?;
+        let location = item_location(&mut conn, &item.item_code).await?;
+
+        Ok(Response::new("18")
+            .fixed(circulation_status)
+            .fixed("00")
+            .fixed("01")
+            .fixed(&timestamp(Self::now()))
+            .field("CF", &queue.to_string())
+            .optional_field("AH", due.map(due_date).as_deref())
+            .field("AB", &item.item_code)
+            .field("AJ", title.as_deref().unwrap_or_default())
+            .optional_field("AQ", location.as_deref()))
+    }
+}
+
+/// Text for the `AF` screen message. Only errors caused by the request are
+/// shown to the patron; anything else aborts the session.
+fn screen_message(err: AppError) -> Result<String, AppError> {
+    match err {
+        AppError::Policy(violation) => Ok(violation.to_string()),
+        AppError::BadRequest(message) | AppError::Unauthorized(message) => Ok(message),
+        AppError::NotFound => Ok("not found".into()),
+        AppError::ServiceUnavailable(message) => Ok(message),
+        other => Err(other),
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct Patron {
+    member_id: String,
+    member_name: String,
+    member_email: Option<String>,
+    member_phone: Option<String>,
+    member_type_id: Option<i32>,
+    expire_date: NaiveDate,
+    is_pending: i16,
+    mpasswd: Option<String>,
+}
+
+async fn fetch_patron(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+) -> Result<Option<Patron>, AppError> {
+    let patron = sqlx::query_as::<_, Patron>(
+        "SELECT member_id, member_name, member_email, member_phone, member_type_id, expire_date, is_pending, mpasswd FROM member WHERE member_id = ?",
+    )
+    .bind(member_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(patron)
+}
+
+fn verify_member_password(patron: &Patron, password: &str) -> bool {
+    patron
+        .mpasswd
+        .as_deref()
+        .filter(|hash| !hash.is_empty())
+        .is_some_and(|hash| bcrypt::verify(password, hash).unwrap_or(false))
+}
+
+/// Kiosks that only scan a card send no `AD`; when a password is sent it
+/// has to match.
+async fn check_patron_password(
+    conn: &mut MySqlConnection,
+    member_id: &str,
+    password: &str,
+) -> Result<(), AppError> {
+    if password.is_empty() {
+        return Ok(());
+    }
+    let patron = fetch_patron(conn, member_id)
+        .await?
+        .ok_or_else(|| PolicyViolation::MemberNotFound(member_id.to_string()))?;
+    if verify_member_password(&patron, password) {
+        Ok(())
+    } else {
+        Err(AppError::Unauthorized("invalid patron password".into()))
+    }
+}
+
+struct PatronSummary {
+    patron: Option<Patron>,
+    today: NaiveDate,
+    loan_limit: i64,
+    hold_limit: i64,
+    charged: i64,
+    overdue: i64,
+    holds: i64,
+    balance: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct PatronLimits {
+    loan_limit: i64,
+    reserve_limit: i64,
+}
+
+impl PatronSummary {
+    async fn load(conn: &mut MySqlConnection, member_id: &str) -> Result<Self, AppError> {
+        let today = chrono::Utc::now().date_naive();
+        let mut summary = PatronSummary {
+            patron: fetch_patron(conn, member_id).await?,
+            today,
+            loan_limit: 0,
+            hold_limit: 0,
+            charged: 0,
+            overdue: 0,
+            holds: 0,
+            balance: 0,
+        };
+        let Some(patron) = &summary.patron else {
+            return Ok(summary);
+        };
+
+        if let Some(member_type_id) = patron.member_type_id
+            && let Some(limits) = sqlx::query_as::<_, PatronLimits>(
+                "SELECT loan_limit, reserve_limit FROM mst_member_type WHERE member_type_id = ?",
+            )
+            .bind(member_type_id)
+            .fetch_optional(&mut *conn)
+            .await?
+        {
+            summary.loan_limit = limits.loan_limit;
+            summary.hold_limit = limits.reserve_limit;
+        }
+
+        summary.charged =
+            sqlx::query_scalar("SELECT COUNT(*) FROM loan WHERE member_id = ? AND is_return = 0")
+                .bind(&patron.member_id)
+                .fetch_one(&mut *conn)
+                .await?;
+        summary.overdue = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM loan WHERE member_id = ? AND is_return = 0 AND due_date < ?",
+        )
+        .bind(&patron.member_id)
+        .bind(today)
+        .fetch_one(&mut *conn)
+        .await?;
+        summary.holds = sqlx::query_scalar("SELECT COUNT(*) FROM reserve WHERE member_id = ?")
+            .bind(&patron.member_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        summary.balance = fetch_balance(conn, &patron.member_id).await?.balance;
+
+        Ok(summary)
+    }
+
+    fn name(&self) -> &str {
+        self.patron
+            .as_ref()
+            .map(|patron| patron.member_name.as_str())
+            .unwrap_or_default()
+    }
+
+    fn password_ok(&self, password: &str) -> bool {
+        !password.is_empty()
+            && self
+                .patron
+                .as_ref()
+                .is_some_and(|patron| verify_member_password(patron, password))
+    }
+
+    fn block_reason(&self) -> Option<String> {
+        let patron = self.patron.as_ref()?;
+        let borrower = circulation::Borrower {
+            member_id: patron.member_id.clone(),
+            member_type_id: patron.member_type_id,
+            expire_date: patron.expire_date,
+            is_pending: patron.is_pending,
+        };
+        circulation::check_borrower(&borrower, self.today)
+            .err()
+            .map(|violation| violation.to_string())
+    }
+
+    /// The 14 patron status flags: charge, renewal, recall and hold
+    /// privileges are denied for unknown, pending or expired patrons, and
+    /// the "too many items charged/overdue" flags follow the loan counts.
+    fn status_flags(&self) -> String {
+        let mut flags = [' '; 14];
+        let blocked = self.patron.is_none() || self.block_reason().is_some();
+        if blocked {
+            flags[..4].fill('Y');
+        }
+        if self.loan_limit > 0 && self.charged >= self.loan_limit {
+            flags[5] = 'Y';
+        }
+        if self.overdue > 0 {
+            flags[6] = 'Y';
+        }
+        flags.iter().collect()
+    }
+
+    async fn item_codes(
+        &self,
+        conn: &mut MySqlConnection,
+        overdue_only: bool,
+    ) -> Result<Vec<String>, AppError> {
+        let Some(patron) = &self.patron else {
+            return Ok(Vec::new());
+        };
+        // Charged items are all open loans; the overdue list stops at today.
+        let due_before = if overdue_only {
+            self.today
+        } else {
+            NaiveDate::MAX
+        };
+        let codes = sqlx::query_scalar(
+            "SELECT item_code FROM loan WHERE member_id = ? AND is_return = 0 AND item_code IS NOT NULL AND due_date < ? ORDER BY due_date",
+        )
+        .bind(&patron.member_id)
+        .bind(due_before)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(codes)
+    }
+}
+
+async fn item_title(
+    conn: &mut MySqlConnection,
+    item_code: &str,
+) -> Result<Option<String>, AppError> {
+    let title: Option<Option<String>> = sqlx::query_scalar(
+        "SELECT b.title FROM item i LEFT JOIN biblio b ON b.biblio_id = i.biblio_id WHERE i.item_code = ?",
+    )
+    .bind(item_code)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(title.flatten())
+}
+
+async fn item_location(
+    conn: &mut MySqlConnection,
+    item_code: &str,
+) -> Result<Option<String>, AppError> {
+    let location: Option<Option<String>> = sqlx::query_scalar(
+        "SELECT l.location_name FROM item i LEFT JOIN mst_location l ON l.location_id = i.location_id WHERE i.item_code = ?",
+    )
+    .bind(item_code)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(location.flatten())
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("message is too short")]
+    TooShort,
+    #[error("checksum mismatch")]
+    Checksum,
+}
+
+/// One SC -> ACS message: the two-digit command, its fixed-length fields
+/// and the `XXvalue|` variable fields.
+#[derive(Debug)]
+pub struct Request {
+    pub command: String,
+    pub fixed: String,
+    fields: HashMap<String, String>,
+    /// Sequence number from `AY`, present when the SC uses error detection.
+    pub sequence: Option<char>,
+}
+
+/// Length of the fixed-length part that follows the command code.
+fn fixed_length(command: &str) -> usize {
+    match command {
+        "93" => 2,
+        "99" => 8,
+        "23" => 21,
+        "63" => 31,
+        "11" | "29" => 38,
+        "09" => 37,
+        "17" => 18,
+        _ => 0,
+    }
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let mut body = line;
+        let mut sequence = None;
+
+        if let Some(pos) = line.rfind("AZ")
+            && line.len() == pos + 6
+        {
+            let expected = &line[pos + 2..];
+            if !checksum(&line[..pos + 2]).eq_ignore_ascii_case(expected) {
+                return Err(ParseError::Checksum);
+            }
+            body = &line[..pos];
+            if let Some(seq_pos) = body.rfind("AY")
+                && body.len() == seq_pos + 3
+            {
+                sequence = body[seq_pos + 2..].chars().next();
+                body = &body[..seq_pos];
+            }
+        }
+
+        if body.len() < 2 || !body.is_char_boundary(2) {
+            return Err(ParseError::TooShort);
+        }
+        let command = body[..2].to_string();
+        let fixed_end = (2 + fixed_length(&command)).min(body.len());
+        if !body.is_char_boundary(fixed_end) {
+            return Err(ParseError::TooShort);
+        }
+        let fixed = body[2..fixed_end].to_string();
+
+        let mut fields = HashMap::new();
+        for part in body[fixed_end..].split('|') {
+            if part.len() >= 2 && part.is_char_boundary(2) {
+                fields
+                    .entry(part[..2].to_string())
+                    .or_insert_with(|| part[2..].to_string());
+            }
+        }
+
+        Ok(Request {
+            command,
+            fixed,
+            fields,
+            sequence,
+        })
+    }
+
+    pub fn field(&self, id: &str) -> &str {
+        self.fields.get(id).map(String::as_str).unwrap_or_default()
+    }
+
+    /// Character of the fixed-length part at `index`, used for flags such
+    /// as the patron information summary.
+    pub fn fixed_flag(&self, index: usize) -> bool {
+        self.fixed
+            .as_bytes()
+            .get(index)
+            .is_some_and(|flag| *flag == b'Y')
+    }
+}
+
+/// Builds an ACS -> SC message.
+#[derive(Debug)]
+pub struct Response {
+    body: String,
+}
+
+impl Response {
+    pub fn new(command: &str) -> Self {
+        Response {
+            body: command.to_string(),
+        }
+    }
+
+    pub fn fixed(mut self, value: &str) -> Self {
+        self.body.push_str(value);
+        self
+    }
+
+    pub fn flag(self, value: bool) -> Self {
+        self.fixed(if value { "Y" } else { "N" })
+    }
+
+    pub fn flag_field(self, id: &str, value: bool) -> Self {
+        self.field(id, if value { "Y" } else { "N" })
+    }
+
+    pub fn count(self, value: i64) -> Self {
+        self.fixed(&format!("{:04}", value.clamp(0, 9999)))
+    }
+
+    pub fn field(mut self, id: &str, value: &str) -> Self {
+        self.body.push_str(id);
+        self.body
+            .extend(value.chars().filter(|c| *c != '|' && *c != '\r'));
+        self.body.push('|');
+        self
+    }
+
+    pub fn optional_field(self, id: &str, value: Option<&str>) -> Self {
+        match value {
+            Some(value) if !value.is_empty() => self.field(id, value),
+            _ => self,
+        }
+    }
+
+    /// Appends `AY`/`AZ` when the request used error detection.
+    pub fn finish(mut self, sequence: Option<char>) -> String {
+        if let Some(sequence) = sequence {
+            self.body.push_str("AY");
+            self.body.push(sequence);
+            self.body.push_str("AZ");
+            let sum = checksum(&self.body);
+            self.body.push_str(&sum);
+        }
+        self.body
+    }
+}
+
+/// Two's complement of the byte sum, as four uppercase hex digits.
+pub fn checksum(message: &str) -> String {
+    let sum = message
+        .bytes()
+        .fold(0u16, |acc, byte| acc.wrapping_add(byte as u16));
+    format!("{:04X}", (!sum).wrapping_add(1))
+}
+
+/// SIP2 `YYYYMMDDZZZZHHMMSS` timestamp. The API works in UTC, so the zone
+/// is always `   Z`.
+pub fn timestamp(value: NaiveDateTime) -> String {
+    value.format("%Y%m%d   Z%H%M%S").to_string()
+}
+
+pub fn due_date(value: NaiveDate) -> String {
+    value.format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_matches_the_specification_example() {
+        assert_eq!(checksum("9900302.00AY1AZ"), "FCA5");
+    }
+
+    #[test]
+    fn checksum_makes_the_byte_sum_zero() {
+        let message = "9300CNkiosk|COsecret|CPMain|AY0AZ";
+        let sum = message
+            .bytes()
+            .fold(0u16, |acc, byte| acc.wrapping_add(byte as u16));
+        let value = u16::from_str_radix(&checksum(message), 16).unwrap();
+        assert_eq!(sum.wrapping_add(value), 0);
+    }
+
+    #[test]
+    fn parses_fixed_and_variable_fields() {
+        let request = Request::parse("9300CNkiosk|COsecret|CPMain|").unwrap();
+        assert_eq!(request.command, "93");
+        assert_eq!(request.fixed, "00");
+        assert_eq!(request.field("CN"), "kiosk");
+        assert_eq!(request.field("CO"), "secret");
+        assert_eq!(request.field("CP"), "Main");
+        assert_eq!(request.field("AA"), "");
+        assert_eq!(request.sequence, None);
+    }
+
+    #[test]
+    fn strips_sequence_and_checksum() {
+        let request = Request::parse("9900302.00AY1AZFCA5").unwrap();
+        assert_eq!(request.command, "99");
+        assert_eq!(request.fixed, "00302.00");
+        assert_eq!(request.sequence, Some('1'));
+    }
+
+    #[test]
+    fn accepts_a_lowercase_checksum() {
+        assert!(Request::parse("9900302.00AY1AZfca5").is_ok());
+    }
+
+    #[test]
+    fn rejects_a_wrong_checksum() {
+        assert_eq!(
+            Request::parse("9900302.00AY1AZFCA6").unwrap_err(),
+            ParseError::Checksum
+        );
+        assert_eq!(
+            Request::parse("9900302.00AY2AZFCA5").unwrap_err(),
+            ParseError::Checksum
+        );
+    }
+
+    #[test]
+    fn response_round_trips_through_parse() {
+        let message = Response::new("94").fixed("1").finish(Some('4'));
+        assert!(message.ends_with(&checksum(&message[..message.len() - 4])));
+        let request = Request::parse(&message).unwrap();
+        assert_eq!(request.command, "94");
+        assert_eq!(request.sequence, Some('4'));
+    }
+
+    #[test]
+    fn rejects_short_messages() {
+        assert_eq!(Request::parse("").unwrap_err(), ParseError::TooShort);
+        assert_eq!(Request::parse("9").unwrap_err(), ParseError::TooShort);
+        assert_eq!(
+            Request::parse("AY1AZFE9A").unwrap_err(),
+            ParseError::TooShort
+        );
+    }
+
+    #[test]
+    fn handles_lossy_decoded_input() {
+        let raw = String::from_utf8_lossy(b"\xff9300CNkiosk|");
+        assert_eq!(Request::parse(&raw).unwrap_err(), ParseError::TooShort);
+
+        let raw = String::from_utf8_lossy(b"93\xffCNkiosk|");
+        assert_eq!(Request::parse(&raw).unwrap_err(), ParseError::TooShort);
+
+        let raw = String::from_utf8_lossy(b"9300CN\xffkiosk|CO\xfe|");
+        let request = Request::parse(&raw).unwrap();
+        assert_eq!(request.command, "93");
+        assert_eq!(request.field("CN"), "\u{fffd}kiosk");
+        assert_eq!(request.field("CO"), "\u{fffd}");
+    }
+
+    #[test]
+    fn ignores_fields_split_inside_a_character() {
+        let request = Request::parse("9300C\u{e9}x|CNkiosk|").unwrap();
+        assert_eq!(request.field("CN"), "kiosk");
+    }
+}
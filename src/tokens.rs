@@ -0,0 +1,24 @@
+use rand::{RngCore, rngs::OsRng};
+use sha2::{Digest, Sha256};
+
+/// `len` random bytes from the OS generator, hex-encoded.
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Digest used for high-entropy secrets such as token validators. These are
+/// random, so a fast hash is enough; passwords still go through bcrypt.
+pub fn sha256_hex(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+/// Compares two digests without returning early on the first difference.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -0,0 +1,614 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use rand::{RngCore, rngs::OsRng};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, MySqlConnection};
+use totp_rs::{Algorithm as TotpAlgorithm, Secret, TOTP};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, AuthUser, ClientIp, Role},
+    config::AppState,
+    error::AppError,
+    jsonapi::{JsonApiDocument, resource, single_document},
+    login_guard::{self, Subject},
+    tokens::{constant_time_eq, random_hex, sha256_hex},
+};
+
+const ISSUER: &str = "SLiMS";
+const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+const ENROLLMENT_TTL: Duration = Duration::from_secs(10 * 60);
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Recovery codes live in `user_tokens` next to refresh tokens, under this
+/// selector prefix, so the `2fa` column keeps holding only the base32
+/// secret that SLiMS itself reads.
+pub const RECOVERY_SELECTOR_PREFIX: &str = "recovery:";
+
+/// The last accepted TOTP time step is kept in `user_tokens` too, one row
+/// per user under this selector prefix, so a code cannot be replayed while
+/// it is still within its validity window.
+pub const TOTP_STEP_SELECTOR_PREFIX: &str = "totp-step:";
+
+/// Seconds per TOTP code.
+const TOTP_STEP: u64 = 30;
+
+const PURPOSE_CHALLENGE: &str = "2fa-challenge";
+const PURPOSE_ENROLLMENT: &str = "2fa-enrollment";
+
+/// Claims of the short-lived tokens used between the steps of a login or
+/// an enrollment. They lack `username` and `role`, so `AuthUser` never
+/// accepts them as access tokens.
+#[derive(Debug, Serialize, Deserialize)]
+struct StepClaims {
+    sub: i64,
+    purpose: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    exp: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TwoFactorChallenge {
+    /// Pass to `POST /auth/login/2fa` together with the code.
+    pub challenge_token: String,
+    pub expires_at: usize,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyLogin {
+    pub challenge_token: String,
+    /// A current TOTP code or an unused recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TwoFactorEnrollment {
+    /// Base32 secret for manual entry in an authenticator app.
+    pub secret: String,
+    pub otpauth_uri: String,
+    /// Pass to `POST /auth/2fa/activate` together with the first code.
+    pub enrollment_token: String,
+    pub expires_at: usize,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ActivateTwoFactor {
+    pub enrollment_token: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TwoFactorCode {
+    /// A current TOTP code or an unused recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RecoveryCodes {
+    /// Shown once; only their hashes are stored.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct StoredRecoveryCode {
+    id: i64,
+    hashed_validator: String,
+}
+
+fn expires_in(ttl: Duration) -> usize {
+    (SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        + ttl)
+        .as_secs() as usize
+}
+
+fn sign_step(state: &AppState, claims: &StepClaims) -> Result<String, AppError> {
+    Ok(encode(
+        &Header::new(Algorithm::HS256),
+        claims,
+        &EncodingKey::from_secret(state.jwt_secret.as_bytes()),
+    )?)
+}
+
+fn decode_step(state: &AppState, token: &str, purpose: &str) -> Result<StepClaims, AppError> {
+    let claims = decode::<StepClaims>(
+        token,
+        &DecodingKey::from_secret(state.jwt_secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .map_err(|_| AppError::Unauthorized(format!("invalid or expired {} token", purpose)))?
+    .claims;
+
+    if claims.purpose != purpose {
+        return Err(AppError::Unauthorized(format!(
+            "invalid or expired {} token",
+            purpose
+        )));
+    }
+
+    Ok(claims)
+}
+
+fn totp(secret: &str, account: &str) -> Result<TOTP, AppError> {
+    let bytes = Secret::Encoded(secret.trim().to_uppercase())
+        .to_bytes()
+        .map_err(|_| AppError::Internal("stored 2FA secret is not valid base32".into()))?;
+
+    Ok(totp_from_bytes(bytes, account))
+}
+
+fn totp_from_bytes(secret: Vec<u8>, account: &str) -> TOTP {
+    // Unchecked so that shorter secrets enrolled through SLiMS still verify.
+    TOTP::new_unchecked(
+        TotpAlgorithm::SHA1,
+        6,
+        1,
+        TOTP_STEP,
+        secret,
+        Some(ISSUER.into()),
+        account.replace(':', ""),
+    )
+}
+
+/// The time step `code` belongs to, allowing one step of clock skew either
+/// way, or `None` when it matches none of them.
+fn matching_step(secret: &str, account: &str, code: &str) -> Result<Option<u64>, AppError> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let totp = totp(secret, account)?;
+    let current = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / TOTP_STEP;
+
+    Ok((current.saturating_sub(1)..=current + 1)
+        .find(|step| constant_time_eq(&totp.generate(step * TOTP_STEP), &code)))
+}
+
+/// Expiry of rows that are kept indefinitely, since `expires_at` is NOT NULL.
+fn never_expires(now: NaiveDateTime) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(9999, 12, 31)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .unwrap_or(now)
+}
+
+/// Recovery codes are typed by hand, so dashes, spaces and case are ignored.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// The enabled TOTP secret of a user, if any.
+pub fn enabled_secret(user: &auth::User) -> Option<&str> {
+    user.two_factor
+        .as_deref()
+        .map(str::trim)
+        .filter(|secret| !secret.is_empty())
+}
+
+/// First step of a 2FA login: the password was correct, so hand out a
+/// token that only `POST /auth/login/2fa` accepts.
+pub fn challenge(state: &AppState, user_id: i64) -> Result<TwoFactorChallenge, AppError> {
+    let expires_at = expires_in(CHALLENGE_TTL);
+    let challenge_token = sign_step(
+        state,
+        &StepClaims {
+            sub: user_id,
+            purpose: PURPOSE_CHALLENGE.into(),
+            secret: None,
+            exp: expires_at,
+        },
+    )?;
+
+    Ok(TwoFactorChallenge {
+        challenge_token,
+        expires_at,
+    })
+}
+
+async fn replace_recovery_codes(
+    conn: &mut MySqlConnection,
+    user_id: i64,
+) -> Result<Vec<String>, AppError> {
+    sqlx::query("DELETE FROM user_tokens WHERE user_id = ? AND selector LIKE ?")
+        .bind(user_id)
+        .bind(format!("{}%", RECOVERY_SELECTOR_PREFIX))
+        .execute(&mut *conn)
+        .await?;
+
+    let now = Utc::now().naive_utc();
+    let never = never_expires(now);
+
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let raw = random_hex(10);
+        let code = format!(
+            "{}-{}-{}-{}",
+            &raw[..5],
+            &raw[5..10],
+            &raw[10..15],
+            &raw[15..]
+        );
+
+        sqlx::query(
+            "INSERT INTO user_tokens (selector, hashed_validator, user_id, expires_at, created_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(format!(
+            "{}{}:{}",
+            RECOVERY_SELECTOR_PREFIX,
+            user_id,
+            random_hex(8)
+        ))
+        .bind(sha256_hex(&raw))
+        .bind(user_id)
+        .bind(never)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+
+        codes.push(code);
+    }
+
+    Ok(codes)
+}
+
+/// Deletes the matching recovery code, so each one works once.
+async fn consume_recovery_code(
+    conn: &mut MySqlConnection,
+    user_id: i64,
+    code: &str,
+) -> Result<bool, AppError> {
+    let presented = sha256_hex(&normalize_recovery_code(code));
+    let stored = sqlx::query_as::<_, StoredRecoveryCode>(
+        "SELECT id, hashed_validator FROM user_tokens WHERE user_id = ? AND selector LIKE ?",
+    )
+    .bind(user_id)
+    .bind(format!("{}%", RECOVERY_SELECTOR_PREFIX))
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let Some(matched) = stored
+        .iter()
+        .find(|row| constant_time_eq(&row.hashed_validator, &presented))
+    else {
+        return Ok(false);
+    };
+
+    let result = sqlx::query("DELETE FROM user_tokens WHERE id = ?")
+        .bind(matched.id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Records `step` as the last TOTP step used by the user. Returns `false`
+/// when the same or a later step was already accepted, so each code works
+/// once. The conditional `UPDATE` makes the decision in one statement, so
+/// two concurrent logins with the same code cannot both pass; should they
+/// both insert the first row, the update still covers every copy.
+async fn claim_totp_step(
+    conn: &mut MySqlConnection,
+    user_id: i64,
+    step: u64,
+) -> Result<bool, AppError> {
+    let selector = format!("{}{}", TOTP_STEP_SELECTOR_PREFIX, user_id);
+    let existing: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user_tokens WHERE selector = ?")
+        .bind(&selector)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    if existing == 0 {
+        let now = Utc::now().naive_utc();
+        sqlx::query(
+            "INSERT INTO user_tokens (selector, hashed_validator, user_id, expires_at, created_at) VALUES (?, '0', ?, ?, ?)",
+        )
+        .bind(&selector)
+        .bind(user_id)
+        .bind(never_expires(now))
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    let result = sqlx::query(
+        "UPDATE user_tokens SET hashed_validator = ? WHERE selector = ? AND CAST(hashed_validator AS UNSIGNED) < ?",
+    )
+    .bind(step.to_string())
+    .bind(&selector)
+    .bind(step)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Accepts either a TOTP code that was not used before or a recovery code.
+async fn verify_second_factor(
+    conn: &mut MySqlConnection,
+    user: &auth::User,
+    secret: &str,
+    code: &str,
+) -> Result<(), AppError> {
+    if let Some(step) = matching_step(secret, &user.username, code)? {
+        return if claim_totp_step(conn, user.user_id, step).await? {
+            Ok(())
+        } else {
+            Err(AppError::Unauthorized(
+                "two-factor code was already used".into(),
+            ))
+        };
+    }
+
+    if consume_recovery_code(conn, user.user_id, code).await? {
+        Ok(())
+    } else {
+        Err(AppError::Unauthorized("invalid two-factor code".into()))
+    }
+}
+
+/// Loads the user behind an access token for the 2FA management endpoints,
+/// which only make sense for staff accounts.
+async fn current_staff_user(state: &AppState, auth: &AuthUser) -> Result<auth::User, AppError> {
+    if auth.claims.role == Role::Member {
+        return Err(AppError::Forbidden(
+            "two-factor authentication is only available for staff accounts".into(),
+        ));
+    }
+
+    let (user, _) = auth::fetch_staff_user(state, auth.claims.sub)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("account no longer exists".into()))?;
+
+    Ok(user)
+}
+
+/// [`verify_second_factor`] behind the login throttle. Wrong codes count
+/// against the same counter as wrong passwords, so a stolen access token
+/// cannot be used to guess codes either.
+async fn verify_throttled(
+    state: &AppState,
+    conn: &mut MySqlConnection,
+    user: &auth::User,
+    secret: &str,
+    code: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), AppError> {
+    let subject = Subject::Staff(&user.username);
+    let attempt = login_guard::check(state, subject, ip).await?;
+
+    if let Err(err) = verify_second_factor(conn, user, secret, code).await {
+        if let AppError::Unauthorized(reason) = &err {
+            login_guard::failed(state, attempt, reason).await?;
+        }
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/login/2fa",
+    request_body = VerifyLogin,
+    responses(
+        (status = 200, description = "Login success", body = JsonApiDocument),
+        (status = 401, description = "Invalid challenge or code"),
+        (status = 429, description = "Too many failed attempts; see the `Retry-After` header"),
+    ),
+    tag = "Auth"
+)]
+pub async fn verify_login(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<VerifyLogin>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let claims = decode_step(&state, &payload.challenge_token, PURPOSE_CHALLENGE)?;
+    let (user, access) = auth::fetch_staff_user(&state, claims.sub)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("invalid credentials".into()))?;
+    let secret = enabled_secret(&user)
+        .ok_or_else(|| AppError::Unauthorized("two-factor authentication is not enabled".into()))?
+        .to_string();
+
+    let mut conn = state.pool.acquire().await?;
+    verify_throttled(&state, &mut conn, &user, &secret, &payload.code, ip).await?;
+    login_guard::succeeded(&state, Subject::Staff(&user.username), ip).await?;
+
+    let response = auth::start_session(&state, &mut conn, &user, access).await?;
+
+    let token_id = response.token.clone();
+    Ok(Json(single_document(resource(
+        "tokens", token_id, response,
+    ))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/2fa/enroll",
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 400, description = "Two-factor authentication is already enabled"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Auth"
+)]
+pub async fn enroll(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let user = current_staff_user(&state, &auth).await?;
+    if enabled_secret(&user).is_some() {
+        return Err(AppError::BadRequest(
+            "two-factor authentication is already enabled".into(),
+        ));
+    }
+
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    let totp = totp_from_bytes(bytes.to_vec(), &user.username);
+    let secret = totp.get_secret_base32();
+    let otpauth_uri = totp.get_url();
+
+    let expires_at = expires_in(ENROLLMENT_TTL);
+    let enrollment_token = sign_step(
+        &state,
+        &StepClaims {
+            sub: user.user_id,
+            purpose: PURPOSE_ENROLLMENT.into(),
+            secret: Some(secret.clone()),
+            exp: expires_at,
+        },
+    )?;
+
+    let enrollment = TwoFactorEnrollment {
+        secret,
+        otpauth_uri,
+        enrollment_token,
+        expires_at,
+    };
+
+    Ok(Json(single_document(resource(
+        "2fa-enrollments",
+        user.user_id.to_string(),
+        enrollment,
+    ))))
+}
+
+/// Stores the secret once the user proves their authenticator produces
+/// matching codes, and returns the first set of recovery codes.
+#[utoipa::path(
+    post,
+    path = "/auth/2fa/activate",
+    request_body = ActivateTwoFactor,
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 400, description = "Invalid code"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Auth"
+)]
+pub async fn activate(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<ActivateTwoFactor>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let user = current_staff_user(&state, &auth).await?;
+    let claims = decode_step(&state, &payload.enrollment_token, PURPOSE_ENROLLMENT)?;
+    let secret = claims.secret.unwrap_or_default();
+    if claims.sub != user.user_id || secret.is_empty() {
+        return Err(AppError::Unauthorized(
+            "invalid or expired 2fa-enrollment token".into(),
+        ));
+    }
+    if enabled_secret(&user).is_some() {
+        return Err(AppError::BadRequest(
+            "two-factor authentication is already enabled".into(),
+        ));
+    }
+    let Some(step) = matching_step(&secret, &user.username, &payload.code)? else {
+        return Err(AppError::BadRequest("invalid two-factor code".into()));
+    };
+
+    let mut conn = state.pool.acquire().await?;
+    sqlx::query("UPDATE `user` SET `2fa` = ? WHERE user_id = ?")
+        .bind(&secret)
+        .bind(user.user_id)
+        .execute(&mut *conn)
+        .await?;
+    // The activation code must not also work for the next login.
+    claim_totp_step(&mut conn, user.user_id, step).await?;
+    let recovery_codes = replace_recovery_codes(&mut conn, user.user_id).await?;
+
+    Ok(Json(single_document(resource(
+        "2fa-recovery-codes",
+        user.user_id.to_string(),
+        RecoveryCodes { recovery_codes },
+    ))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/2fa/recovery-codes",
+    request_body = TwoFactorCode,
+    responses(
+        (status = 200, body = JsonApiDocument),
+        (status = 401, description = "Invalid code"),
+        (status = 429, description = "Too many failed attempts; see the `Retry-After` header"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Auth"
+)]
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    auth: AuthUser,
+    Json(payload): Json<TwoFactorCode>,
+) -> Result<Json<JsonApiDocument>, AppError> {
+    let user = current_staff_user(&state, &auth).await?;
+    let secret = enabled_secret(&user)
+        .ok_or_else(|| AppError::BadRequest("two-factor authentication is not enabled".into()))?
+        .to_string();
+
+    let mut conn = state.pool.acquire().await?;
+    verify_throttled(&state, &mut conn, &user, &secret, &payload.code, ip).await?;
+    let recovery_codes = replace_recovery_codes(&mut conn, user.user_id).await?;
+
+    Ok(Json(single_document(resource(
+        "2fa-recovery-codes",
+        user.user_id.to_string(),
+        RecoveryCodes { recovery_codes },
+    ))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/2fa/disable",
+    request_body = TwoFactorCode,
+    responses(
+        (status = 204, description = "Two-factor authentication disabled"),
+        (status = 401, description = "Invalid code"),
+        (status = 429, description = "Too many failed attempts; see the `Retry-After` header"),
+    ),
+    security(("bearerAuth" = [])),
+    tag = "Auth"
+)]
+pub async fn disable(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    auth: AuthUser,
+    Json(payload): Json<TwoFactorCode>,
+) -> Result<StatusCode, AppError> {
+    let user = current_staff_user(&state, &auth).await?;
+    let secret = enabled_secret(&user)
+        .ok_or_else(|| AppError::BadRequest("two-factor authentication is not enabled".into()))?
+        .to_string();
+
+    let mut conn = state.pool.acquire().await?;
+    verify_throttled(&state, &mut conn, &user, &secret, &payload.code, ip).await?;
+
+    sqlx::query("UPDATE `user` SET `2fa` = NULL WHERE user_id = ?")
+        .bind(user.user_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        "DELETE FROM user_tokens WHERE user_id = ? AND (selector LIKE ? OR selector LIKE ?)",
+    )
+    .bind(user.user_id)
+    .bind(format!("{}%", RECOVERY_SELECTOR_PREFIX))
+    .bind(format!("{}%", TOTP_STEP_SELECTOR_PREFIX))
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}